@@ -0,0 +1,303 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JClass, JString};
+use hex::{encode, decode};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use serde::{Serialize, Deserialize};
+use crate::{Error, EncryptAttachment, DecryptFile};
+use crate::frame::{FrameOptions, frame_file, unframe_file};
+use crate::envelope::{Kind, seal, open_expecting};
+use crate::error;
+
+// Describes an attachment uploaded after encrypting it with encrypt_file. It is sent to the contact as a MSG_TYPE_ATTACHMENT payload.
+// key is hex encoded, hash is the hex encoded hash of the plaintext and size is the plaintext length in bytes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AttachmentDescriptor {
+	pub location: String,
+	pub key: String,
+	pub hash: String,
+	pub size: u64,
+	pub mime_type: String,
+	pub file_name: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub width: Option<u32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub height: Option<u32>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub duration_ms: Option<u64>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub thumbnail: Option<Thumbnail>
+}
+
+// The thumbnail is small enough to be sent inline, so its ciphertext is base64 encoded and carried in the descriptor itself
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Thumbnail {
+	pub key: String,
+	pub ciphertext: String
+}
+
+#[derive(Deserialize, Default)]
+struct MediaInfo {
+	width: Option<u32>,
+	height: Option<u32>,
+	duration_ms: Option<u64>
+}
+
+// Decrypts the attachment and checks that it matches the descriptor
+pub fn verify_attachment(descriptor: &AttachmentDescriptor, ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+	let key = decode(&descriptor.key).map_err(|_| "Attachment key invalid".to_string())?;
+	let expected_hash = decode(&descriptor.hash).map_err(|_| "Attachment hash invalid".to_string())?;
+	let file = unframe_file(decrypt_file(open_expecting(ciphertext, Kind::File, &key)?, &key)?)?;
+	if file.len() as u64 != descriptor.size { return Err("Attachment size does not match descriptor".to_string()); }
+	if hash(&file) != expected_hash { return Err("Attachment hash does not match descriptor".to_string()); }
+	Ok(file)
+}
+
+// Sealed under its own key like the attachment itself, an empty thumbnail means there is none
+fn encrypt_thumbnail(thumbnail: &[u8], options: &FrameOptions) -> Result<Option<Thumbnail>, String> {
+	if thumbnail.is_empty() { return Ok(None); }
+	let framed_thumbnail = frame_file(thumbnail, options).map_err(|err| format!("Could not frame thumbnail: {}", err))?;
+	let (ciphertext, key) = encrypt_file(&framed_thumbnail)?;
+	Ok(Some(Thumbnail {
+		ciphertext: BASE64.encode(seal(Kind::File, &ciphertext, &key)),
+		key: encode(key)
+	}))
+}
+
+pub fn decrypt_thumbnail(thumbnail: &Thumbnail) -> Result<Vec<u8>, String> {
+	let key = decode(&thumbnail.key).map_err(|_| "Thumbnail key invalid".to_string())?;
+	let ciphertext = BASE64.decode(&thumbnail.ciphertext).map_err(|_| "Thumbnail ciphertext invalid".to_string())?;
+	unframe_file(decrypt_file(open_expecting(&ciphertext, Kind::File, &key)?, &key)?)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_encryptAttachment<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	file: JByteArray<'local>,
+	thumbnail: JByteArray<'local>,
+	mime_type: JString<'local>,
+	file_name: JString<'local>,
+	media_info: JString<'local>,
+	options: JString<'local>
+) -> JString<'local> {
+	
+	let file = match env.convert_byte_array(file) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "Could not read file"); }
+	};
+	
+	let thumbnail = match env.convert_byte_array(thumbnail) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "Could not read thumbnail"); }
+	};
+	
+	let mime_type = env.get_string(&mime_type);
+	if mime_type.is_err() { error!(env, "Could not get java variable: mime_type"); }
+	let mime_type: String = mime_type.unwrap().into();
+	
+	let file_name = env.get_string(&file_name);
+	if file_name.is_err() { error!(env, "Could not get java variable: file_name"); }
+	let file_name: String = file_name.unwrap().into();
+	
+	let media_info = env.get_string(&media_info);
+	if media_info.is_err() { error!(env, "Could not get java variable: media_info"); }
+	let media_info: String = media_info.unwrap().into();
+	let media_info: MediaInfo = match media_info.as_str() {
+		"" => MediaInfo::default(),
+		_ => match serde_json::from_str(&media_info) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "media_info invalid"); }
+		}
+	};
+	
+	let options = env.get_string(&options);
+	if options.is_err() { error!(env, "Could not get java variable: options"); }
+	let options: String = options.unwrap().into();
+	let options: FrameOptions = match options.as_str() {
+		"" => FrameOptions::default(),
+		_ => match serde_json::from_str(&options) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "options invalid"); }
+		}
+	};
+	
+	let framed_file = match frame_file(&file, &options) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not frame file: {}", err)); }
+	};
+	
+	let (ciphertext, key) = match encrypt_file(&framed_file) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let ciphertext = seal(Kind::File, &ciphertext, &key);
+	
+	let thumbnail = match encrypt_thumbnail(&thumbnail, &options) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	// the location is only known once the ciphertext has been uploaded, so the app fills it in before sending the descriptor
+	let descriptor = AttachmentDescriptor {
+		location: "".to_string(),
+		key: encode(key),
+		hash: encode(hash(&file)),
+		size: file.len() as u64,
+		mime_type,
+		file_name,
+		width: media_info.width,
+		height: media_info.height,
+		duration_ms: media_info.duration_ms,
+		thumbnail
+	};
+	
+	let encrypt_attachment = EncryptAttachment {
+		status: "ok",
+		ciphertext: &encode(ciphertext),
+		descriptor: &descriptor
+	};
+	
+	let encrypt_attachment_json = match serde_json::to_string(&encrypt_attachment) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	encrypt_attachment_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_verifyAttachment<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	descriptor: JString<'local>,
+	ciphertext: JByteArray<'local>
+) -> JString<'local> {
+	
+	let descriptor = env.get_string(&descriptor);
+	if descriptor.is_err() { error!(env, "Could not get java variable: descriptor"); }
+	let descriptor: String = descriptor.unwrap().into();
+	let descriptor: AttachmentDescriptor = match serde_json::from_str(&descriptor) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "descriptor invalid"); }
+	};
+	
+	let ciphertext = match env.convert_byte_array(ciphertext) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "Could not read ciphertext"); }
+	};
+	
+	let file = match verify_attachment(&descriptor, &ciphertext) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not verify attachment: {}", err)); }
+	};
+	
+	let file = DecryptFile {
+		status: "ok",
+		file: &encode(file)
+	};
+	
+	let file_json = match serde_json::to_string(&file) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	file_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_decryptAttachmentThumbnail<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	descriptor: JString<'local>
+) -> JString<'local> {
+	
+	let descriptor = env.get_string(&descriptor);
+	if descriptor.is_err() { error!(env, "Could not get java variable: descriptor"); }
+	let descriptor: String = descriptor.unwrap().into();
+	let descriptor: AttachmentDescriptor = match serde_json::from_str(&descriptor) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "descriptor invalid"); }
+	};
+	
+	let thumbnail = match descriptor.thumbnail {
+		Some(thumbnail) => thumbnail,
+		None => { error!(env, "Attachment has no thumbnail"); }
+	};
+	
+	let file = match decrypt_thumbnail(&thumbnail) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not decrypt thumbnail: {}", err)); }
+	};
+	
+	let file = DecryptFile {
+		status: "ok",
+		file: &encode(file)
+	};
+	
+	let file_json = match serde_json::to_string(&file) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	file_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::padding::PaddingPolicy;
+	
+	#[test]
+	fn empty_thumbnail_is_omitted() {
+		assert_eq!(encrypt_thumbnail(&[], &FrameOptions::default()).unwrap(), None);
+	}
+	
+	#[test]
+	fn thumbnail_roundtrip() {
+		let options = FrameOptions { padding: PaddingPolicy::Padme, ..Default::default() };
+		let thumbnail = encrypt_thumbnail(b"thumbnail", &options).unwrap().unwrap();
+		assert!(BASE64.decode(&thumbnail.ciphertext).unwrap().starts_with(b"DWNE"));
+		assert_eq!(decrypt_thumbnail(&thumbnail).unwrap(), b"thumbnail");
+		let mut tampered = BASE64.decode(&thumbnail.ciphertext).unwrap();
+		let last = tampered.len() - 1;
+		tampered[last] ^= 1;
+		assert!(decrypt_thumbnail(&Thumbnail { key: thumbnail.key.clone(), ciphertext: BASE64.encode(tampered) }).is_err());
+	}
+	
+	#[test]
+	fn verifies_attachment_against_descriptor() {
+		let file = b"attachment".to_vec();
+		let (ciphertext, key) = encrypt_file(&frame_file(&file, &FrameOptions::default()).unwrap()).unwrap();
+		let ciphertext = seal(Kind::File, &ciphertext, &key);
+		let mut descriptor = AttachmentDescriptor { location: "".to_string(), key: encode(&key), hash: encode(hash(&file)), size: file.len() as u64, mime_type: "text/plain".to_string(), file_name: "a.txt".to_string(), width: None, height: None, duration_ms: None, thumbnail: None };
+		assert_eq!(verify_attachment(&descriptor, &ciphertext).unwrap(), file);
+		descriptor.size += 1;
+		assert!(verify_attachment(&descriptor, &ciphertext).is_err());
+	}
+}
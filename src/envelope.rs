@@ -0,0 +1,197 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use dawn_stdlib::hash;
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JClass, JString};
+use serde::Serialize;
+use crate::{Error, ClassifyCiphertext};
+use crate::signatures::signed_data;
+use crate::error;
+
+// Outer envelope laid out as magic, version, kind and tag in front of the ciphertext.
+// The tag is a keyed hash over header and body. The key is a secret both sides hold: the pfs_salt of the session for messages,
+// init requests and init responses, the file key for files. It catches corrupted, misrouted or forged blobs before any decryption is attempted.
+// The receiver of an init request only learns the pfs_salt from the request itself, so those tags are checked after parsing.
+const ENVELOPE_MAGIC: &[u8] = b"DWNE";
+const ENVELOPE_VERSION: u8 = 1;
+const TAG_SIZE: usize = 16;
+const HEADER_SIZE: usize = 4 + 1 + 1 + TAG_SIZE;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+	Message = 1,
+	InitRequest = 2,
+	InitResponse = 3,
+	File = 4
+}
+
+impl Kind {
+	fn from_byte(byte: u8) -> Option<Kind> {
+		match byte {
+			1 => Some(Kind::Message),
+			2 => Some(Kind::InitRequest),
+			3 => Some(Kind::InitResponse),
+			4 => Some(Kind::File),
+			_ => None
+		}
+	}
+}
+
+// Envelope whose tag has not been checked yet
+pub struct Unverified<'a> {
+	kind: Kind,
+	tag: &'a [u8],
+	body: &'a [u8]
+}
+
+impl Unverified<'_> {
+	pub fn verify(&self, key: &[u8]) -> Result<(), String> {
+		match tags_equal(self.tag, &tag(self.kind, key, self.body)) {
+			true => Ok(()),
+			false => Err("Envelope tag mismatch".to_string())
+		}
+	}
+}
+
+fn tag(kind: Kind, key: &[u8], body: &[u8]) -> Vec<u8> {
+	hash(&signed_data(&[b"dawn-envelope", key, &[ENVELOPE_VERSION, kind as u8], body]))[..TAG_SIZE].to_vec()
+}
+
+// Compares every byte regardless of where the first difference is, so the time taken does not reveal how much of a forged tag was right
+fn tags_equal(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub fn seal(kind: Kind, body: &[u8], key: &[u8]) -> Vec<u8> {
+	let mut sealed = Vec::with_capacity(HEADER_SIZE + body.len());
+	sealed.extend_from_slice(ENVELOPE_MAGIC);
+	sealed.push(ENVELOPE_VERSION);
+	sealed.push(kind as u8);
+	sealed.extend_from_slice(&tag(kind, key, body));
+	sealed.extend_from_slice(body);
+	sealed
+}
+
+// Returns None for blobs without an envelope
+fn read(data: &[u8]) -> Result<Option<Unverified<'_>>, String> {
+	if !data.starts_with(ENVELOPE_MAGIC) { return Ok(None); }
+	if data.len() < HEADER_SIZE { return Err("Envelope truncated".to_string()); }
+	let (version, kind) = (data[4], data[5]);
+	if version != ENVELOPE_VERSION { return Err(format!("Envelope version unsupported: {}", version)); }
+	let kind = match Kind::from_byte(kind) {
+		Some(kind) => kind,
+		None => return Err(format!("Envelope kind unknown: {}", kind))
+	};
+	Ok(Some(Unverified { kind, tag: &data[6..HEADER_SIZE], body: &data[HEADER_SIZE..] }))
+}
+
+// Blobs without an envelope are passed through unchanged, so ciphertexts of old clients keep working
+pub fn open_unverified(data: &[u8], expected: Kind) -> Result<(Option<Unverified<'_>>, &[u8]), String> {
+	match read(data)? {
+		Some(envelope) if envelope.kind == expected => {
+			let body = envelope.body;
+			Ok((Some(envelope), body))
+		},
+		Some(envelope) => Err(format!("Ciphertext is a {:?}, expected a {:?}", envelope.kind, expected)),
+		None => Ok((None, data))
+	}
+}
+
+pub fn open_expecting<'a>(data: &'a [u8], expected: Kind, key: &[u8]) -> Result<&'a [u8], String> {
+	let (envelope, body) = open_unverified(data, expected)?;
+	if let Some(envelope) = envelope { envelope.verify(key)?; }
+	Ok(body)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_classifyCiphertext<'local> (
+	env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	ciphertext: JByteArray<'local>
+) -> JString<'local> {
+	
+	let ciphertext = env.convert_byte_array(ciphertext);
+	if ciphertext.is_err() { error!(env, "Could not get java variable: ciphertext"); }
+	let ciphertext = ciphertext.unwrap();
+	
+	// Only the header is read, the tag is checked by the parse function the app picks, which knows the key.
+	// Blobs without an envelope are reported as unknown.
+	let (kind, version) = match read(&ciphertext) {
+		Ok(Some(envelope)) => (Some(envelope.kind), Some(ENVELOPE_VERSION)),
+		Ok(None) => (None, None),
+		Err(err) => { error!(env, &format!("Invalid envelope: {}", err)); }
+	};
+	
+	let classify_ciphertext = ClassifyCiphertext {
+		status: "ok",
+		kind,
+		envelope_version: version
+	};
+	
+	let classify_ciphertext_json = match serde_json::to_string(&classify_ciphertext) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	classify_ciphertext_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn seal_roundtrip() {
+		let sealed = seal(Kind::Message, b"ciphertext", b"key");
+		assert_eq!(open_expecting(&sealed, Kind::Message, b"key").unwrap(), b"ciphertext");
+		assert!(open_expecting(&sealed, Kind::Message, b"other key").is_err());
+		assert!(open_expecting(&sealed, Kind::File, b"key").is_err());
+	}
+	
+	#[test]
+	fn rejects_tampered_envelopes() {
+		let mut sealed = seal(Kind::File, b"ciphertext", b"key");
+		let last = sealed.len() - 1;
+		sealed[last] ^= 1;
+		assert!(open_expecting(&sealed, Kind::File, b"key").is_err());
+		sealed[5] = Kind::Message as u8;
+		assert!(open_expecting(&sealed, Kind::Message, b"key").is_err());
+		assert!(open_expecting(&sealed[..HEADER_SIZE - 1], Kind::Message, b"key").is_err());
+	}
+	
+	#[test]
+	fn unsealed_data_passes_through() {
+		let (envelope, body) = open_unverified(b"legacy", Kind::InitRequest).unwrap();
+		assert!(envelope.is_none());
+		assert_eq!(body, b"legacy");
+		assert_eq!(open_expecting(b"legacy", Kind::InitRequest, b"key").unwrap(), b"legacy");
+	}
+	
+	#[test]
+	fn compares_tags() {
+		assert!(tags_equal(b"tag", b"tag"));
+		assert!(!tags_equal(b"tag", b"tab"));
+		assert!(!tags_equal(b"tag", b"ta"));
+		assert!(!tags_equal(b"", b"tag"));
+	}
+}
@@ -0,0 +1,361 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::collections::BTreeMap;
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use serde::{Serialize, Deserialize};
+use crate::{Error, ExpiryResult, ExpiryDue, ExpiryTimer};
+use crate::handles::timestamp_value;
+use crate::error;
+
+// Messages to wipe, keyed by their mdc, and the default ttls of conversations, keyed by contact id.
+// Kept by the app and passed in on every call, like the trust store.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ExpiryScheduler {
+	#[serde(default)]
+	pub messages: BTreeMap<String, u64>,
+	#[serde(default)]
+	pub timers: BTreeMap<String, u64>
+}
+
+// Received messages are scheduled with the deadline returned by parseMsg, sent ones with the ttl they were sent with.
+// Without either, the timer of the conversation given by id is used.
+#[derive(Deserialize)]
+struct ScheduleEntry {
+	mdc: String,
+	expires: Option<String>,
+	ttl: Option<u64>,
+	id: Option<String>
+}
+
+impl ExpiryScheduler {
+	pub fn schedule(&mut self, mdc: &str, expires: u64) {
+		let deadline = self.messages.entry(mdc.to_string()).or_insert(expires);
+		// a message is never kept longer than first scheduled
+		*deadline = expires.min(*deadline);
+	}
+	
+	// Removes and returns the mdcs of all messages due at now
+	pub fn take_due(&mut self, now: u64) -> Vec<String> {
+		let due: Vec<String> = self.messages.iter().filter(|(_, expires)| **expires <= now).map(|(mdc, _)| mdc.clone()).collect();
+		for mdc in &due {
+			self.messages.remove(mdc);
+		}
+		due
+	}
+	
+	pub fn next_deadline(&self) -> Option<u64> {
+		self.messages.values().min().copied()
+	}
+	
+	pub fn timer(&self, id: &str) -> Option<u64> {
+		self.timers.get(id).copied()
+	}
+}
+
+fn parse_scheduler(scheduler: &str) -> Result<ExpiryScheduler, String> {
+	match scheduler {
+		"" => Ok(ExpiryScheduler::default()),
+		_ => serde_json::from_str(scheduler).map_err(|_| "scheduler invalid".to_string())
+	}
+}
+
+fn current_timestamp() -> Result<u64, String> {
+	timestamp_value(&get_current_timestamp()?)
+}
+
+fn entry_deadline(entry: &ScheduleEntry, scheduler: &ExpiryScheduler, now: u64) -> Result<u64, String> {
+	match (&entry.expires, entry.ttl) {
+		(Some(expires), None) => timestamp_value(expires),
+		(None, Some(ttl)) => Ok(now.saturating_add(ttl)),
+		(None, None) => match entry.id.as_deref().and_then(|id| scheduler.timer(id)) {
+			Some(ttl) => Ok(now.saturating_add(ttl)),
+			None => Err("No deadline given and no timer set for the conversation".to_string())
+		},
+		_ => Err("Only one of expires and ttl can be given".to_string())
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_expirySchedule<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	scheduler: JString<'local>,
+	entry: JString<'local>
+) -> JString<'local> {
+	
+	let scheduler = env.get_string(&scheduler);
+	if scheduler.is_err() { error!(env, "Could not get java variable: scheduler"); }
+	let scheduler: String = scheduler.unwrap().into();
+	let mut scheduler = match parse_scheduler(&scheduler) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let entry = env.get_string(&entry);
+	if entry.is_err() { error!(env, "Could not get java variable: entry"); }
+	let entry: String = entry.unwrap().into();
+	let entry: ScheduleEntry = match serde_json::from_str(&entry) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "entry invalid"); }
+	};
+	
+	let now = match current_timestamp() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let expires = match entry_deadline(&entry, &scheduler, now) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	scheduler.schedule(&entry.mdc, expires);
+	
+	let expiry_result = ExpiryResult {
+		status: "ok",
+		scheduler: &scheduler
+	};
+	
+	let expiry_result_json = match serde_json::to_string(&expiry_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	expiry_result_json
+}
+
+// For messages deleted before their deadline
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_expiryCancel<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	scheduler: JString<'local>,
+	mdc: JString<'local>
+) -> JString<'local> {
+	
+	let scheduler = env.get_string(&scheduler);
+	if scheduler.is_err() { error!(env, "Could not get java variable: scheduler"); }
+	let scheduler: String = scheduler.unwrap().into();
+	let mut scheduler = match parse_scheduler(&scheduler) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let mdc = env.get_string(&mdc);
+	if mdc.is_err() { error!(env, "Could not get java variable: mdc"); }
+	let mdc: String = mdc.unwrap().into();
+	
+	scheduler.messages.remove(&mdc);
+	
+	let expiry_result = ExpiryResult {
+		status: "ok",
+		scheduler: &scheduler
+	};
+	
+	let expiry_result_json = match serde_json::to_string(&expiry_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	expiry_result_json
+}
+
+// Returns the mdcs of all messages to wipe now and the next deadline, so the app knows when to call again
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_expiryDue<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	scheduler: JString<'local>
+) -> JString<'local> {
+	
+	let scheduler = env.get_string(&scheduler);
+	if scheduler.is_err() { error!(env, "Could not get java variable: scheduler"); }
+	let scheduler: String = scheduler.unwrap().into();
+	let mut scheduler = match parse_scheduler(&scheduler) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let now = match current_timestamp() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let due = scheduler.take_due(now);
+	let next = scheduler.next_deadline().map(|next| next.to_string());
+	
+	let expiry_due = ExpiryDue {
+		status: "ok",
+		due: &due,
+		next: next.as_deref(),
+		scheduler: &scheduler
+	};
+	
+	let expiry_due_json = match serde_json::to_string(&expiry_due) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	expiry_due_json
+}
+
+// Stores the default ttl of a conversation, e.g. after receiving an expiry timer payload. An empty ttl turns it off.
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_expirySetTimer<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	scheduler: JString<'local>,
+	id: JString<'local>,
+	ttl: JString<'local>
+) -> JString<'local> {
+	
+	let scheduler = env.get_string(&scheduler);
+	if scheduler.is_err() { error!(env, "Could not get java variable: scheduler"); }
+	let scheduler: String = scheduler.unwrap().into();
+	let mut scheduler = match parse_scheduler(&scheduler) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let id = env.get_string(&id);
+	if id.is_err() { error!(env, "Could not get java variable: id"); }
+	let id: String = id.unwrap().into();
+	
+	let ttl = env.get_string(&ttl);
+	if ttl.is_err() { error!(env, "Could not get java variable: ttl"); }
+	let ttl: String = ttl.unwrap().into();
+	match ttl.as_str() {
+		"" => { scheduler.timers.remove(&id); },
+		_ => match ttl.parse::<u64>() {
+			Ok(ttl) => { scheduler.timers.insert(id, ttl); },
+			Err(_) => { error!(env, "ttl invalid"); }
+		}
+	};
+	
+	let expiry_result = ExpiryResult {
+		status: "ok",
+		scheduler: &scheduler
+	};
+	
+	let expiry_result_json = match serde_json::to_string(&expiry_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	expiry_result_json
+}
+
+// The default ttl of a conversation, to be passed in the FrameOptions of sendMsgWithOptions and sendMsgFragmented
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_expiryGetTimer<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	scheduler: JString<'local>,
+	id: JString<'local>
+) -> JString<'local> {
+	
+	let scheduler = env.get_string(&scheduler);
+	if scheduler.is_err() { error!(env, "Could not get java variable: scheduler"); }
+	let scheduler: String = scheduler.unwrap().into();
+	let scheduler = match parse_scheduler(&scheduler) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let id = env.get_string(&id);
+	if id.is_err() { error!(env, "Could not get java variable: id"); }
+	let id: String = id.unwrap().into();
+	
+	let expiry_timer = ExpiryTimer {
+		status: "ok",
+		ttl: scheduler.timer(&id)
+	};
+	
+	let expiry_timer_json = match serde_json::to_string(&expiry_timer) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	expiry_timer_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn takes_due_messages() {
+		let mut scheduler = ExpiryScheduler::default();
+		scheduler.schedule("a", 10);
+		scheduler.schedule("b", 20);
+		assert_eq!(scheduler.next_deadline(), Some(10));
+		assert!(scheduler.take_due(9).is_empty());
+		assert_eq!(scheduler.take_due(10), vec!["a"]);
+		assert_eq!(scheduler.next_deadline(), Some(20));
+		assert_eq!(scheduler.take_due(30), vec!["b"]);
+		assert_eq!(scheduler.next_deadline(), None);
+	}
+	
+	#[test]
+	fn deadlines_only_move_earlier() {
+		let mut scheduler = ExpiryScheduler::default();
+		scheduler.schedule("a", 10);
+		scheduler.schedule("a", 20);
+		assert_eq!(scheduler.messages["a"], 10);
+		scheduler.schedule("a", 5);
+		assert_eq!(scheduler.messages["a"], 5);
+	}
+	
+	fn entry(expires: Option<&str>, ttl: Option<u64>, id: Option<&str>) -> ScheduleEntry {
+		ScheduleEntry { mdc: "a".to_string(), expires: expires.map(str::to_string), ttl, id: id.map(str::to_string) }
+	}
+	
+	#[test]
+	fn entry_needs_one_deadline() {
+		let scheduler = ExpiryScheduler::default();
+		assert_eq!(entry_deadline(&entry(Some("10"), None, None), &scheduler, 100).unwrap(), 10);
+		assert_eq!(entry_deadline(&entry(None, Some(10), None), &scheduler, 100).unwrap(), 110);
+		assert_eq!(entry_deadline(&entry(None, Some(u64::MAX), None), &scheduler, 100).unwrap(), u64::MAX);
+		assert!(entry_deadline(&entry(Some("10"), Some(10), None), &scheduler, 100).is_err());
+		assert!(entry_deadline(&entry(None, None, None), &scheduler, 100).is_err());
+	}
+	
+	#[test]
+	fn entry_falls_back_to_timer() {
+		let mut scheduler = ExpiryScheduler::default();
+		scheduler.timers.insert("contact".to_string(), 30);
+		assert_eq!(scheduler.timer("contact"), Some(30));
+		assert_eq!(scheduler.timer("other"), None);
+		assert_eq!(entry_deadline(&entry(None, None, Some("contact")), &scheduler, 100).unwrap(), 130);
+		assert_eq!(entry_deadline(&entry(None, Some(10), Some("contact")), &scheduler, 100).unwrap(), 110);
+		assert_eq!(entry_deadline(&entry(Some("10"), None, Some("contact")), &scheduler, 100).unwrap(), 10);
+		assert!(entry_deadline(&entry(None, None, Some("other")), &scheduler, 100).is_err());
+	}
+}
@@ -0,0 +1,174 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::convert::TryInto;
+use dawn_stdlib::hash;
+use crate::frame::{FrameOptions, Compression};
+use crate::padding::PaddingPolicy;
+use crate::payload::is_typed;
+use crate::signatures::{sign_detached, verify_detached, signed_data, read_signed_data};
+
+pub const PROTOCOL_VERSION: u16 = 1;
+
+// Feature bits announced in the init handshake
+pub const FEATURE_FRAME: u32 = 0x01;
+pub const FEATURE_PADDING: u32 = 0x02;
+pub const FEATURE_COMPRESSION: u32 = 0x04;
+pub const FEATURE_TYPED_PAYLOADS: u32 = 0x08;
+pub const FEATURE_ENVELOPE: u32 = 0x10;
+pub const FEATURE_FRAGMENTS: u32 = 0x20;
+pub const FEATURE_SEQUENCE: u32 = 0x40;
+pub const FEATURE_EXPIRY: u32 = 0x80;
+pub const SUPPORTED_FEATURES: u32 = FEATURE_FRAME | FEATURE_PADDING | FEATURE_COMPRESSION | FEATURE_TYPED_PAYLOADS | FEATURE_ENVELOPE | FEATURE_FRAGMENTS | FEATURE_SEQUENCE | FEATURE_EXPIRY;
+
+// The init request carries the announcement in front of the comment. Old clients would display it as part of the comment,
+// so it is only added for handles that advertise features in their mdc, which only handles of new clients do.
+const COMMENT_PREFIX: &str = "dawn-features:";
+const COMMENT_SEPARATOR: char = ';';
+
+// The init response carries it as a block in front of the ciphertext, signed by the accepting side and bound to the ciphertext
+const RESPONSE_BLOCK_MAGIC: &[u8] = b"DWNV";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Announcement {
+	pub version: u16,
+	pub features: u32
+}
+
+pub struct SignedAnnouncement {
+	pub announcement: Announcement,
+	pub signature: Vec<u8>
+}
+
+impl Default for Announcement {
+	fn default() -> Self {
+		Announcement { version: PROTOCOL_VERSION, features: SUPPORTED_FEATURES }
+	}
+}
+
+pub fn announce_in_comment(comment: &str) -> String {
+	let own = Announcement::default();
+	format!("{}{}:{:08x}{}{}", COMMENT_PREFIX, own.version, own.features, COMMENT_SEPARATOR, comment)
+}
+
+// Comments of old clients are returned unchanged without an announcement
+pub fn split_comment(comment: &str) -> (Option<Announcement>, String) {
+	let rest = match comment.strip_prefix(COMMENT_PREFIX) {
+		Some(rest) => rest,
+		None => return (None, comment.to_string())
+	};
+	let (announcement, user_comment) = match rest.split_once(COMMENT_SEPARATOR) {
+		Some(res) => res,
+		None => return (None, comment.to_string())
+	};
+	let announcement = match announcement.split_once(':') {
+		Some((version, features)) => match (version.parse::<u16>(), u32::from_str_radix(features, 16)) {
+			(Ok(version), Ok(features)) => Announcement { version, features },
+			_ => return (None, comment.to_string())
+		},
+		None => return (None, comment.to_string())
+	};
+	(Some(announcement), user_comment.to_string())
+}
+
+fn response_signed_data(announcement: &Announcement, ciphertext: &[u8]) -> Vec<u8> {
+	signed_data(&[b"dawn-features", &announcement.version.to_be_bytes(), &announcement.features.to_be_bytes(), &hash(ciphertext)])
+}
+
+pub fn announce_in_response(ciphertext: &[u8], own_seckey_sig: &[u8]) -> Result<Vec<u8>, String> {
+	let own = Announcement::default();
+	let signature = sign_detached(&response_signed_data(&own, ciphertext), own_seckey_sig)?;
+	let mut response = RESPONSE_BLOCK_MAGIC.to_vec();
+	response.extend_from_slice(&signed_data(&[&own.version.to_be_bytes(), &own.features.to_be_bytes(), &signature]));
+	response.extend_from_slice(ciphertext);
+	Ok(response)
+}
+
+// Responses of old clients are returned unchanged without an announcement
+pub fn split_response(response: &[u8]) -> Result<(Option<SignedAnnouncement>, &[u8]), String> {
+	let block = match response.strip_prefix(RESPONSE_BLOCK_MAGIC) {
+		Some(block) => block,
+		None => return Ok((None, response))
+	};
+	let (fields, ciphertext) = read_signed_data(block, 3)?;
+	let announcement = match (fields[0].as_slice().try_into(), fields[1].as_slice().try_into()) {
+		(Ok(version), Ok(features)) => Announcement { version: u16::from_be_bytes(version), features: u32::from_be_bytes(features) },
+		_ => return Err("Feature announcement invalid".to_string())
+	};
+	Ok((Some(SignedAnnouncement { announcement, signature: fields[2].clone() }), ciphertext))
+}
+
+// The signing key of the peer is only known once the response has been parsed, so the signature is checked afterwards
+pub fn verify_response_announcement(signed: &SignedAnnouncement, ciphertext: &[u8], remote_pubkey_sig: &[u8]) -> Result<(), String> {
+	verify_detached(&response_signed_data(&signed.announcement, ciphertext), &signed.signature, remote_pubkey_sig)
+}
+
+// Features the peer has to support to read a message of this type, sent with the given frame options
+pub fn required_features(msg_type: u8, options: Option<&FrameOptions>) -> u32 {
+	let mut features = 0;
+	if is_typed(msg_type) { features |= FEATURE_TYPED_PAYLOADS; }
+	if let Some(options) = options {
+		features |= FEATURE_FRAME;
+		if options.padding != PaddingPolicy::None { features |= FEATURE_PADDING; }
+		if options.compression != Compression::None { features |= FEATURE_COMPRESSION; }
+		if options.ttl.is_some() { features |= FEATURE_EXPIRY; }
+	}
+	features
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dawn_stdlib::sign_keygen;
+	use crate::payload::{MSG_TYPE_TEXT, MSG_TYPE_REPLY};
+	
+	#[test]
+	fn comment_roundtrip() {
+		let (announcement, comment) = split_comment(&announce_in_comment("hi; there"));
+		assert_eq!(announcement, Some(Announcement::default()));
+		assert_eq!(comment, "hi; there");
+		for legacy in ["hi", "dawn-features:1:zz;hi", "dawn-features:1"] {
+			assert_eq!(split_comment(legacy), (None, legacy.to_string()));
+		}
+	}
+	
+	#[test]
+	fn response_roundtrip() {
+		let (pubkey_sig, seckey_sig) = sign_keygen();
+		let response = announce_in_response(b"ciphertext", &seckey_sig).unwrap();
+		let (signed, ciphertext) = split_response(&response).unwrap();
+		let signed = signed.unwrap();
+		assert_eq!(ciphertext, b"ciphertext");
+		assert_eq!(signed.announcement, Announcement::default());
+		assert!(verify_response_announcement(&signed, ciphertext, &pubkey_sig).is_ok());
+		assert!(verify_response_announcement(&signed, b"other", &pubkey_sig).is_err());
+		let (signed, ciphertext) = split_response(b"legacy").unwrap();
+		assert!(signed.is_none());
+		assert_eq!(ciphertext, b"legacy");
+	}
+	
+	#[test]
+	fn features_required_by_messages() {
+		assert_eq!(required_features(MSG_TYPE_TEXT, None), 0);
+		assert_eq!(required_features(MSG_TYPE_REPLY, None), FEATURE_TYPED_PAYLOADS);
+		assert_eq!(required_features(MSG_TYPE_TEXT, Some(&FrameOptions::default())), FEATURE_FRAME);
+		let options = FrameOptions { padding: PaddingPolicy::Padme, compression: Compression::Deflate, ttl: Some(1) };
+		assert_eq!(required_features(MSG_TYPE_TEXT, Some(&options)), FEATURE_FRAME | FEATURE_PADDING | FEATURE_COMPRESSION | FEATURE_EXPIRY);
+	}
+}
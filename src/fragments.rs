@@ -0,0 +1,675 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::convert::{TryFrom, TryInto};
+use std::collections::BTreeMap;
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::jshort;
+use hex::{encode, decode};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use serde::{Serialize, Deserialize};
+use crate::{Error, SendFragments, FragmentCiphertext, AddFragment, AddFragmentWithWindow, ExpireFragments};
+use crate::frame::{FrameOptions, FrameInfo, FRAME_MSG_TYPE, frame_msg, unframe_msg_info};
+use crate::features::{FEATURE_ENVELOPE, FEATURE_FRAGMENTS, required_features};
+use crate::session::parse_session;
+use crate::envelope::{Kind, seal};
+use crate::handles::timestamp_value;
+use crate::replay::{ReplayWindow, STATUS_REPLAY, parse_window};
+use crate::error;
+
+// Fragments are sent with this msg_type. The message is framed as a whole first, the frame is then split into fragments,
+// each of which is laid out as version, message id, index, count, length prefixed hash of the whole frame and data.
+// Every fragment is encrypted under the normal pfs chain, the hash binds the reassembled frame to the fragments.
+pub const FRAGMENT_MSG_TYPE: u8 = 253;
+const FRAGMENT_VERSION: u8 = 1;
+const MESSAGE_ID_SIZE: usize = 16;
+const MAX_FRAGMENTS: u32 = 4096;
+const MIN_FRAGMENT_SIZE: usize = 1024;
+// Same bound as for decompressed messages
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+pub const STATUS_INCOMPLETE: &str = "incomplete";
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FragmentOptions {
+	#[serde(flatten)]
+	pub frame: FrameOptions,
+	// upper bound for the data of one fragment including its header, in bytes
+	pub max_fragment_size: usize
+}
+
+impl Default for FragmentOptions {
+	fn default() -> Self {
+		FragmentOptions { frame: FrameOptions::default(), max_fragment_size: 64 * 1024 }
+	}
+}
+
+pub struct Fragment {
+	pub message_id: Vec<u8>,
+	pub index: u32,
+	pub count: u32,
+	pub message_hash: Vec<u8>,
+	pub data: Vec<u8>
+}
+
+impl Fragment {
+	fn header_size(message_hash: &[u8]) -> usize {
+		1 + MESSAGE_ID_SIZE + 4 + 4 + 1 + message_hash.len()
+	}
+	
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(Fragment::header_size(&self.message_hash) + self.data.len());
+		bytes.push(FRAGMENT_VERSION);
+		bytes.extend_from_slice(&self.message_id);
+		bytes.extend_from_slice(&self.index.to_be_bytes());
+		bytes.extend_from_slice(&self.count.to_be_bytes());
+		bytes.push(self.message_hash.len() as u8);
+		bytes.extend_from_slice(&self.message_hash);
+		bytes.extend_from_slice(&self.data);
+		bytes
+	}
+	
+	pub fn from_bytes(bytes: &[u8]) -> Result<Fragment, String> {
+		let fixed_size = 1 + MESSAGE_ID_SIZE + 4 + 4 + 1;
+		if bytes.len() < fixed_size { return Err("Fragment too short".to_string()); }
+		if bytes[0] != FRAGMENT_VERSION { return Err(format!("Fragment version unsupported: {}", bytes[0])); }
+		let message_id = bytes[1..1 + MESSAGE_ID_SIZE].to_vec();
+		let index = u32::from_be_bytes(bytes[1 + MESSAGE_ID_SIZE..5 + MESSAGE_ID_SIZE].try_into().unwrap());
+		let count = u32::from_be_bytes(bytes[5 + MESSAGE_ID_SIZE..9 + MESSAGE_ID_SIZE].try_into().unwrap());
+		let hash_size = bytes[fixed_size - 1] as usize;
+		if bytes.len() < fixed_size + hash_size { return Err("Fragment too short".to_string()); }
+		if count == 0 || count > MAX_FRAGMENTS || index >= count { return Err("Fragment index invalid".to_string()); }
+		Ok(Fragment {
+			message_id,
+			index,
+			count,
+			message_hash: bytes[fixed_size..fixed_size + hash_size].to_vec(),
+			data: bytes[fixed_size + hash_size..].to_vec()
+		})
+	}
+}
+
+pub fn split_frame(frame: &[u8], max_fragment_size: usize) -> Result<Vec<Fragment>, String> {
+	if max_fragment_size < MIN_FRAGMENT_SIZE { return Err(format!("Fragment size must be at least {} bytes", MIN_FRAGMENT_SIZE)); }
+	if frame.len() > MAX_MESSAGE_SIZE { return Err("Message too large".to_string()); }
+	let message_id = sym_key_gen()[..MESSAGE_ID_SIZE].to_vec();
+	let message_hash = hash(frame);
+	let chunk_size = max_fragment_size - Fragment::header_size(&message_hash);
+	let chunks: Vec<&[u8]> = match frame.is_empty() {
+		true => vec![frame],
+		false => frame.chunks(chunk_size).collect()
+	};
+	let count = match u32::try_from(chunks.len()) {
+		Ok(count) if count <= MAX_FRAGMENTS => count,
+		_ => return Err("Message needs too many fragments".to_string())
+	};
+	Ok(chunks.into_iter().enumerate().map(|(index, chunk)| Fragment {
+		message_id: message_id.clone(),
+		index: index as u32,
+		count,
+		message_hash: message_hash.clone(),
+		data: chunk.to_vec()
+	}).collect())
+}
+
+// All durations are given in timestamp units
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ReassemblyConfig {
+	pub timeout: u64
+}
+
+impl Default for ReassemblyConfig {
+	fn default() -> Self {
+		ReassemblyConfig { timeout: 24 }
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PartialMessage {
+	pub first_received: u64,
+	pub count: u32,
+	pub message_hash: String,
+	pub fragments: BTreeMap<u32, String>
+}
+
+impl PartialMessage {
+	pub fn missing(&self) -> Vec<u32> {
+		(0..self.count).filter(|index| !self.fragments.contains_key(index)).collect()
+	}
+}
+
+// Incomplete messages keyed by hex encoded message id. Kept by the app and passed in on every call.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FragmentBuffer {
+	#[serde(default)]
+	pub config: ReassemblyConfig,
+	#[serde(default)]
+	pub messages: BTreeMap<String, PartialMessage>
+}
+
+impl FragmentBuffer {
+	// Returns the reassembled frame once the last missing fragment arrived
+	pub fn add(&mut self, fragment: Fragment, now: u64) -> Result<Option<Vec<u8>>, String> {
+		let message_id = encode(&fragment.message_id);
+		let message_hash = encode(&fragment.message_hash);
+		let partial = self.messages.entry(message_id.clone()).or_insert_with(|| PartialMessage {
+			first_received: now,
+			count: fragment.count,
+			message_hash: message_hash.clone(),
+			fragments: BTreeMap::new()
+		});
+		if partial.count != fragment.count || partial.message_hash != message_hash { return Err("Fragment does not match earlier fragments of the message".to_string()); }
+		let data = BASE64.encode(&fragment.data);
+		match partial.fragments.get(&fragment.index) {
+			Some(existing) if *existing != data => return Err("Fragment conflicts with an earlier copy".to_string()),
+			Some(_) => return Ok(None),
+			None => ()
+		}
+		let size: usize = partial.fragments.values().map(|data| data.len()).sum::<usize>() + data.len();
+		// base64 grows the data by a third
+		if size / 4 * 3 > MAX_MESSAGE_SIZE { return Err("Message too large".to_string()); }
+		partial.fragments.insert(fragment.index, data);
+		if (partial.fragments.len() as u32) < partial.count { return Ok(None); }
+		let partial = match self.messages.remove(&message_id) {
+			Some(partial) => partial,
+			None => return Err("Message vanished from buffer".to_string())
+		};
+		let mut frame = Vec::new();
+		for data in partial.fragments.values() {
+			match BASE64.decode(data) {
+				Ok(data) => frame.extend_from_slice(&data),
+				Err(_) => return Err("Fragment buffer invalid".to_string())
+			}
+		}
+		if encode(hash(&frame)) != partial.message_hash { return Err("Reassembled message does not match its hash".to_string()); }
+		Ok(Some(frame))
+	}
+	
+	pub fn expire(&mut self, now: u64) -> Vec<String> {
+		let timeout = self.config.timeout;
+		let expired: Vec<String> = self.messages.iter().filter(|(_, partial)| partial.first_received.saturating_add(timeout) < now).map(|(message_id, _)| message_id.clone()).collect();
+		for message_id in &expired {
+			self.messages.remove(message_id);
+		}
+		expired
+	}
+}
+
+fn parse_buffer(buffer: &str) -> Result<FragmentBuffer, String> {
+	match buffer {
+		"" => Ok(FragmentBuffer::default()),
+		_ => serde_json::from_str(buffer).map_err(|_| "buffer invalid".to_string())
+	}
+}
+
+fn current_timestamp() -> Result<u64, String> {
+	timestamp_value(&get_current_timestamp()?)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_sendMsgFragmented<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	msg_type: jshort,
+	msg_string: JString<'local>,
+	msg_bytes: JByteArray<'local>,
+	remote_pubkey_kyber: JString<'local>,
+	own_seckey_sig: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>,
+	id: JString<'local>,
+	mdc_seed: JString<'local>,
+	session: JString<'local>,
+	options: JString<'local>
+) -> JString<'local> {
+	
+	let msg_type = match u8::try_from(msg_type) {
+		Ok(n) => n,
+		Err(_) => { error!(env, &format!("Invalid message type provided: {}", msg_type)); },
+	};
+	
+	let msg_string = env.get_string(&msg_string);
+	if msg_string.is_err() { error!(env, "Could not get java variable: msg_string"); }
+	let msg_string: String = msg_string.unwrap().into();
+	let msg_string = match msg_string.as_str() {
+		"" => None,
+		_ => Some(msg_string.as_str())
+	};
+	
+	let msg_bytes = env.convert_byte_array(msg_bytes);
+	if msg_bytes.is_err() { error!(env, "Could not get java variable: msg_bytes"); }
+	let msg_bytes = msg_bytes.unwrap();
+	let msg_bytes = match msg_bytes.len() {
+		0 => None,
+		_ => Some(msg_bytes.as_slice())
+	};
+	
+	let remote_pubkey_kyber = env.get_string(&remote_pubkey_kyber);
+	if remote_pubkey_kyber.is_err() { error!(env, "Could not get java variable: remote_pubkey_kyber"); }
+	let remote_pubkey_kyber: String = remote_pubkey_kyber.unwrap().into();
+	let remote_pubkey_kyber = match decode(remote_pubkey_kyber) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "remote_pubkey_kyber invalid"); }
+	};
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let pfs_key = env.get_string(&pfs_key);
+	if pfs_key.is_err() { error!(env, "Could not get java variable: pfs_key"); }
+	let pfs_key: String = pfs_key.unwrap().into();
+	let pfs_key = match decode(pfs_key) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_key invalid"); }
+	};
+	
+	let pfs_salt = env.get_string(&pfs_salt);
+	if pfs_salt.is_err() { error!(env, "Could not get java variable: pfs_salt"); }
+	let pfs_salt: String = pfs_salt.unwrap().into();
+	let pfs_salt = match decode(pfs_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_salt invalid"); }
+	};
+	
+	let id = env.get_string(&id);
+	if id.is_err() { error!(env, "Could not get java variable: id"); }
+	let id: String = id.unwrap().into();
+	
+	let mdc_seed = env.get_string(&mdc_seed);
+	if mdc_seed.is_err() { error!(env, "Could not get java variable: mdc_seed"); }
+	let mdc_seed: String = mdc_seed.unwrap().into();
+	
+	let session = env.get_string(&session);
+	if session.is_err() { error!(env, "Could not get java variable: session"); }
+	let session: String = session.unwrap().into();
+	let mut session = match parse_session(&session) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let options = env.get_string(&options);
+	if options.is_err() { error!(env, "Could not get java variable: options"); }
+	let options: String = options.unwrap().into();
+	let options: FragmentOptions = match options.as_str() {
+		"" => FragmentOptions::default(),
+		_ => match serde_json::from_str(&options) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "options invalid"); }
+		}
+	};
+	
+	if let Err(err) = session.check_features(FEATURE_FRAGMENTS | required_features(msg_type, Some(&options.frame))) { error!(env, &err); }
+	
+	// The counter is taken once for the whole message, the fragments are ordered by their index
+	let counter = match session.take_counter() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let frame = match frame_msg((msg_type, msg_string, msg_bytes), &options.frame, counter) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not frame message: {}", err)); }
+	};
+	
+	let fragments = match split_frame(&frame, options.max_fragment_size) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not fragment message: {}", err)); }
+	};
+	
+	// Every fragment advances the pfs chain like a regular message
+	let mut pfs_key = pfs_key;
+	let mut ciphertexts = Vec::with_capacity(fragments.len());
+	for fragment in fragments {
+		let (new_pfs_key, mdc, ciphertext) = match send_msg((FRAGMENT_MSG_TYPE, None, Some(&fragment.to_bytes())), &remote_pubkey_kyber, Some(&own_seckey_sig), &pfs_key, &pfs_salt, &id, &mdc_seed) {
+			Ok(res) => res,
+			Err(err) => { error!(env, &err); }
+		};
+		let ciphertext = match session.supports(FEATURE_ENVELOPE) {
+			true => seal(Kind::Message, &ciphertext, &pfs_salt),
+			false => ciphertext
+		};
+		pfs_key = new_pfs_key;
+		ciphertexts.push(FragmentCiphertext { mdc, ciphertext: BASE64.encode(ciphertext) });
+	}
+	
+	let send_fragments = SendFragments {
+		status: "ok",
+		new_pfs_key: &encode(pfs_key),
+		fragments: &ciphertexts,
+		session: &session
+	};
+	
+	let send_fragments_json = match serde_json::to_string(&send_fragments) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	send_fragments_json
+}
+
+// Takes the msg_bytes of a message parsed with msg_type FRAGMENT_MSG_TYPE
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_addFragment<'local> (
+	env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	buffer: JString<'local>,
+	msg_bytes: JString<'local>
+) -> JString<'local> {
+	add_fragment(env, buffer, msg_bytes, None)
+}
+
+// Shared by addFragment and addFragmentWithReplayWindow. The counter of a fragmented message is inside the reassembled frame,
+// so gaps and replayed messages can only be reported once the last fragment arrived and a window is given.
+pub fn add_fragment<'local> (
+	mut env: JNIEnv<'local>,
+	buffer: JString<'local>,
+	msg_bytes: JString<'local>,
+	window: Option<JString<'local>>
+) -> JString<'local> {
+	
+	let mut window: Option<ReplayWindow> = match window {
+		Some(window) => {
+			let window = env.get_string(&window);
+			if window.is_err() { error!(env, "Could not get java variable: window"); }
+			let window: String = window.unwrap().into();
+			match parse_window(&window) {
+				Ok(res) => Some(res),
+				Err(err) => { error!(env, &err); }
+			}
+		},
+		None => None
+	};
+	
+	let buffer = env.get_string(&buffer);
+	if buffer.is_err() { error!(env, "Could not get java variable: buffer"); }
+	let buffer: String = buffer.unwrap().into();
+	let mut buffer = match parse_buffer(&buffer) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let msg_bytes = env.get_string(&msg_bytes);
+	if msg_bytes.is_err() { error!(env, "Could not get java variable: msg_bytes"); }
+	let msg_bytes: String = msg_bytes.unwrap().into();
+	let msg_bytes = match BASE64.decode(msg_bytes) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "msg_bytes invalid"); }
+	};
+	
+	let fragment = match Fragment::from_bytes(&msg_bytes) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let message_id = encode(&fragment.message_id);
+	
+	let now = match current_timestamp() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not get timestamp: {}", err)); }
+	};
+	
+	let frame = match buffer.add(fragment, now) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not add fragment: {}", err)); }
+	};
+	
+	let (msg, info) = match frame {
+		Some(frame) => match unframe_msg_info((FRAME_MSG_TYPE, None, Some(frame))) {
+			Ok((msg, info)) => (Some(msg), info),
+			Err(err) => { error!(env, &format!("Could not unframe message: {}", err)); }
+		},
+		None => (None, FrameInfo::default())
+	};
+	
+	// The fragments are already taken out of the buffer, so a replayed message is dropped but the new buffer is still returned
+	let replayed = match (&window, info.sequence) {
+		(Some(window), Some(sequence)) => window.contains_counter(sequence.counter),
+		_ => false
+	};
+	let (msg, info) = match replayed {
+		true => (None, FrameInfo::default()),
+		false => (msg, info)
+	};
+	let sequence = info.sequence;
+	
+	let expires = match info.expires() {
+		Ok(res) => res.map(|expires| expires.to_string()),
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let clock_skew = match sequence {
+		Some(sequence) => match sequence.clock_skew() {
+			Ok(res) => Some(res),
+			Err(err) => { error!(env, &err); }
+		},
+		None => None
+	};
+	let sent = sequence.map(|sequence| sequence.sent.to_string());
+	
+	let (gap, out_of_order) = match (&mut window, sequence) {
+		(Some(window), Some(sequence)) => window.record_counter(sequence.counter),
+		_ => (0, false)
+	};
+	
+	let missing = match buffer.messages.get(&message_id) {
+		Some(partial) => partial.missing(),
+		None => Vec::new()
+	};
+	let msg_text = msg.as_ref().map(|(_, msg_text, _)| msg_text.clone().unwrap_or_default());
+	let msg_bytes = msg.as_ref().map(|(_, _, msg_bytes)| msg_bytes.as_ref().map(|bytes| BASE64.encode(bytes)).unwrap_or_default());
+	
+	let add_fragment = AddFragment {
+		status: match (&msg, replayed) { (Some(_), _) => "ok", (None, true) => STATUS_REPLAY, (None, false) => STATUS_INCOMPLETE },
+		message_id: &message_id,
+		missing: &missing,
+		msg_type: msg.as_ref().map(|(msg_type, _, _)| *msg_type),
+		msg_text: msg_text.as_deref(),
+		msg_bytes: msg_bytes.as_deref(),
+		counter: sequence.map(|sequence| sequence.counter),
+		sent: sent.as_deref(),
+		clock_skew,
+		expires: expires.as_deref(),
+		buffer: &buffer
+	};
+	
+	let add_fragment_json = match &window {
+		Some(window) => serde_json::to_string(&AddFragmentWithWindow {
+			fragment: &add_fragment,
+			gap,
+			out_of_order,
+			window
+		}),
+		None => serde_json::to_string(&add_fragment)
+	};
+	
+	let add_fragment_json = match add_fragment_json {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	add_fragment_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_expireFragments<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	buffer: JString<'local>
+) -> JString<'local> {
+	
+	let buffer = env.get_string(&buffer);
+	if buffer.is_err() { error!(env, "Could not get java variable: buffer"); }
+	let buffer: String = buffer.unwrap().into();
+	let mut buffer = match parse_buffer(&buffer) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let now = match current_timestamp() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not get timestamp: {}", err)); }
+	};
+	
+	let expire_fragments = ExpireFragments {
+		status: "ok",
+		expired: &buffer.expire(now),
+		buffer: &buffer
+	};
+	
+	let expire_fragments_json = match serde_json::to_string(&expire_fragments) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	expire_fragments_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn frame(length: usize) -> Vec<u8> {
+		(0..length).map(|i| (i * 7) as u8).collect()
+	}
+	
+	#[test]
+	fn fragment_roundtrip() {
+		let fragment = Fragment { message_id: vec![1; MESSAGE_ID_SIZE], index: 2, count: 3, message_hash: vec![4; 64], data: vec![5, 6, 7] };
+		let parsed = Fragment::from_bytes(&fragment.to_bytes()).unwrap();
+		assert_eq!(parsed.message_id, fragment.message_id);
+		assert_eq!((parsed.index, parsed.count), (2, 3));
+		assert_eq!(parsed.message_hash, fragment.message_hash);
+		assert_eq!(parsed.data, fragment.data);
+	}
+	
+	#[test]
+	fn from_bytes_rejects_invalid_fragments() {
+		let fragment = |index, count| Fragment { message_id: vec![1; MESSAGE_ID_SIZE], index, count, message_hash: vec![4; 64], data: vec![] }.to_bytes();
+		assert!(Fragment::from_bytes(&fragment(3, 3)).is_err());
+		assert!(Fragment::from_bytes(&fragment(0, 0)).is_err());
+		assert!(Fragment::from_bytes(&fragment(0, MAX_FRAGMENTS + 1)).is_err());
+		let bytes = fragment(0, 1);
+		assert!(Fragment::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+		let mut bytes = bytes;
+		bytes[0] = FRAGMENT_VERSION + 1;
+		assert!(Fragment::from_bytes(&bytes).is_err());
+	}
+	
+	#[test]
+	fn split_frame_rejects_small_fragments() {
+		assert!(split_frame(&frame(10), MIN_FRAGMENT_SIZE - 1).is_err());
+		assert_eq!(split_frame(&[], MIN_FRAGMENT_SIZE).unwrap().len(), 1);
+	}
+	
+	#[test]
+	fn reassembles_out_of_order() {
+		let frame = frame(5000);
+		let mut fragments = split_frame(&frame, MIN_FRAGMENT_SIZE).unwrap();
+		assert!(fragments.len() > 2);
+		assert!(fragments.iter().all(|fragment| fragment.to_bytes().len() <= MIN_FRAGMENT_SIZE));
+		fragments.reverse();
+		let last = fragments.pop().unwrap();
+		let mut buffer = FragmentBuffer::default();
+		for fragment in fragments {
+			assert_eq!(buffer.add(fragment, 0).unwrap(), None);
+		}
+		assert_eq!(buffer.add(last, 0).unwrap(), Some(frame));
+		assert!(buffer.messages.is_empty());
+	}
+	
+	#[test]
+	fn add_rejects_conflicting_fragments() {
+		let mut fragments = split_frame(&frame(3000), MIN_FRAGMENT_SIZE).unwrap();
+		let mut buffer = FragmentBuffer::default();
+		let first = fragments.remove(0);
+		let copy = Fragment::from_bytes(&first.to_bytes()).unwrap();
+		let mut conflicting = Fragment::from_bytes(&first.to_bytes()).unwrap();
+		conflicting.data[0] ^= 1;
+		assert_eq!(buffer.add(first, 0).unwrap(), None);
+		assert_eq!(buffer.add(copy, 0).unwrap(), None);
+		assert!(buffer.add(conflicting, 0).is_err());
+		let mut other_count = fragments.remove(0);
+		other_count.count += 1;
+		assert!(buffer.add(other_count, 0).is_err());
+	}
+	
+	#[test]
+	fn add_rejects_hash_mismatch() {
+		let mut fragments = split_frame(&frame(3000), MIN_FRAGMENT_SIZE).unwrap();
+		fragments[1].data[0] ^= 1;
+		let mut buffer = FragmentBuffer::default();
+		let results: Vec<_> = fragments.into_iter().map(|fragment| buffer.add(fragment, 0)).collect();
+		assert!(results.last().unwrap().is_err());
+	}
+	
+	#[test]
+	fn expire_drops_old_messages() {
+		let mut buffer = FragmentBuffer::default();
+		let fragment = split_frame(&frame(3000), MIN_FRAGMENT_SIZE).unwrap().remove(0);
+		let message_id = encode(&fragment.message_id);
+		buffer.add(fragment, 100).unwrap();
+		assert!(buffer.expire(100 + buffer.config.timeout).is_empty());
+		assert_eq!(buffer.expire(101 + buffer.config.timeout), vec![message_id]);
+		assert!(buffer.messages.is_empty());
+	}
+	
+	#[test]
+	fn reassembled_frame_keeps_sequence() {
+		let framed = frame_msg((0, Some("fragmented"), Some(&frame(4000))), &FrameOptions::default(), Some(7)).unwrap();
+		let mut buffer = FragmentBuffer::default();
+		let mut reassembled = None;
+		for fragment in split_frame(&framed, MIN_FRAGMENT_SIZE).unwrap() {
+			reassembled = buffer.add(fragment, 0).unwrap();
+		}
+		let (msg, info) = unframe_msg_info((FRAME_MSG_TYPE, None, reassembled)).unwrap();
+		assert_eq!(msg, (0, Some("fragmented".to_string()), Some(frame(4000))));
+		assert_eq!(info.sequence.map(|sequence| sequence.counter), Some(7));
+	}
+	
+	#[test]
+	fn reassembled_frame_keeps_ttl() {
+		let options = FrameOptions { ttl: Some(60), ..FrameOptions::default() };
+		let framed = frame_msg((0, None, Some(&frame(4000))), &options, None).unwrap();
+		let mut buffer = FragmentBuffer::default();
+		let mut reassembled = None;
+		for fragment in split_frame(&framed, MIN_FRAGMENT_SIZE).unwrap() {
+			reassembled = buffer.add(fragment, 0).unwrap();
+		}
+		let (msg, info) = unframe_msg_info((FRAME_MSG_TYPE, None, reassembled)).unwrap();
+		assert_eq!(msg.2, Some(frame(4000)));
+		assert_eq!(info.ttl, Some(60));
+		let before = current_timestamp().unwrap();
+		let expires = info.expires().unwrap().unwrap();
+		let after = current_timestamp().unwrap();
+		assert!(before + 60 <= expires && expires <= after + 60);
+	}
+}
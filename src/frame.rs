@@ -0,0 +1,364 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use flate2::Compression as Level;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Serialize, Deserialize};
+use dawn_stdlib::{get_current_timestamp, hash};
+use crate::handles::timestamp_value;
+use crate::padding::{PaddingPolicy, pad, unpad};
+use crate::payload::MsgTriple;
+use crate::signatures::signed_data;
+
+// Framed messages are sent with this msg_type. The actual message (type, text and bytes) is then carried inside msg_bytes,
+// which allows transforming it as a whole (e.g. padding it) before it gets encrypted by send_msg.
+pub const FRAME_MSG_TYPE: u8 = 255;
+const FRAME_VERSION: u8 = 1;
+
+// Framed files are prefixed with this magic, the version, the flags and a check value over all of them and the body before being encrypted.
+// Files without a matching check value are returned unchanged by unframe_file, so legacy files that happen to start with the magic still pass through.
+const FILE_FRAME_MAGIC: &[u8] = b"DWNF";
+const FILE_FRAME_CHECK_SIZE: usize = 8;
+const FILE_FRAME_HEADER_SIZE: usize = 4 + 2 + FILE_FRAME_CHECK_SIZE;
+
+const FLAG_PADDED: u8 = 0x01;
+const FLAG_COMPRESSED: u8 = 0x02;
+const FLAG_SEQUENCED: u8 = 0x04;
+const FLAG_EXPIRING: u8 = 0x08;
+const SUPPORTED_FLAGS: u8 = FLAG_PADDED | FLAG_COMPRESSED | FLAG_SEQUENCED | FLAG_EXPIRING;
+// Flags describing headers in front of the body rather than transformations of it
+const HEADER_FLAGS: u8 = FLAG_SEQUENCED | FLAG_EXPIRING;
+
+// Sequenced frames carry the big endian u64 counter and sender timestamp between the flags and the body,
+// expiring frames then carry the big endian u64 ttl
+const SEQUENCE_SIZE: usize = 16;
+const TTL_SIZE: usize = 8;
+
+// Upper bounds for decompressed data, anything bigger is rejected to protect against decompression bombs
+const MAX_DECOMPRESSED_MSG_SIZE: usize = 16 * 1024 * 1024;
+const MAX_DECOMPRESSED_FILE_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+	#[default]
+	None,
+	Deflate
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct FrameOptions {
+	pub padding: PaddingPolicy,
+	pub compression: Compression,
+	// time after which the receiver should delete the message, counted from when it is received
+	pub ttl: Option<u64>
+}
+
+// Sequence information of a received message, sent is the sender's timestamp
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sequence {
+	pub counter: u64,
+	pub sent: u64
+}
+
+impl Sequence {
+	// Positive when the sender's clock is ahead of ours. The sent timestamp is chosen by the sender, so the result saturates instead of overflowing.
+	pub fn clock_skew(&self) -> Result<i64, String> {
+		let now = timestamp_value(&get_current_timestamp()?)?;
+		let sent = i64::try_from(self.sent).unwrap_or(i64::MAX);
+		let now = i64::try_from(now).unwrap_or(i64::MAX);
+		Ok(sent.saturating_sub(now))
+	}
+}
+
+// Header information of a received frame. Being part of the frame, it is covered by the message authentication.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameInfo {
+	pub sequence: Option<Sequence>,
+	pub ttl: Option<u64>
+}
+
+impl FrameInfo {
+	// The absolute deadline of expiring messages. It is based on our own clock, so a skewed sender clock can not shorten it.
+	pub fn expires(&self) -> Result<Option<u64>, String> {
+		match self.ttl {
+			Some(ttl) => Ok(Some(timestamp_value(&get_current_timestamp()?)?.saturating_add(ttl))),
+			None => Ok(None)
+		}
+	}
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+	let mut value = [0u8; 8];
+	value.copy_from_slice(&data[..8]);
+	u64::from_be_bytes(value)
+}
+
+fn encode_body(msg: (u8, Option<&str>, Option<&[u8]>)) -> Result<Vec<u8>, String> {
+	let (msg_type, msg_text, msg_bytes) = msg;
+	let msg_text = msg_text.unwrap_or("").as_bytes();
+	let msg_bytes = msg_bytes.unwrap_or(&[]);
+	let text_length = match u32::try_from(msg_text.len()) {
+		Ok(length) => length,
+		Err(_) => return Err("Message text too long".to_string())
+	};
+	let mut body = Vec::with_capacity(5 + msg_text.len() + msg_bytes.len());
+	body.push(msg_type);
+	body.extend_from_slice(&text_length.to_be_bytes());
+	body.extend_from_slice(msg_text);
+	body.extend_from_slice(msg_bytes);
+	Ok(body)
+}
+
+fn decode_body(body: &[u8]) -> Result<MsgTriple, String> {
+	if body.len() < 5 { return Err("Frame body too short".to_string()); }
+	let msg_type = body[0];
+	let mut text_length = [0u8; 4];
+	text_length.copy_from_slice(&body[1..5]);
+	let text_length = u32::from_be_bytes(text_length) as usize;
+	if text_length > body.len() - 5 { return Err("Frame text length invalid".to_string()); }
+	let msg_text = match text_length {
+		0 => None,
+		_ => match String::from_utf8(body[5..5 + text_length].to_vec()) {
+			Ok(text) => Some(text),
+			Err(_) => return Err("Frame text is not valid utf-8".to_string())
+		}
+	};
+	let msg_bytes = match body.len() - 5 - text_length {
+		0 => None,
+		_ => Some(body[5 + text_length..].to_vec())
+	};
+	Ok((msg_type, msg_text, msg_bytes))
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>, String> {
+	let mut encoder = DeflateEncoder::new(Vec::new(), Level::default());
+	if encoder.write_all(data).is_err() { return Err("Could not compress data".to_string()); }
+	encoder.finish().map_err(|_| "Could not compress data".to_string())
+}
+
+fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+	let mut decompressed = Vec::new();
+	// read one byte more than allowed to be able to tell whether the limit was exceeded
+	let mut decoder = DeflateDecoder::new(data).take(max_size as u64 + 1);
+	if decoder.read_to_end(&mut decompressed).is_err() { return Err("Could not decompress data".to_string()); }
+	if decompressed.len() > max_size { return Err("Decompressed data exceeds size limit".to_string()); }
+	Ok(decompressed)
+}
+
+// Applies the transformations selected in options and returns the flags describing them together with the transformed data.
+// Compression is applied before padding, so the padded length does not depend on how well the data compressed.
+fn transform(data: Vec<u8>, options: &FrameOptions) -> Result<(u8, Vec<u8>), String> {
+	let mut flags = 0;
+	let mut data = data;
+	if options.compression == Compression::Deflate {
+		let compressed = compress(&data)?;
+		// incompressible data is sent as is
+		if compressed.len() < data.len() {
+			data = compressed;
+			flags |= FLAG_COMPRESSED;
+		}
+	}
+	if options.padding != PaddingPolicy::None {
+		data = pad(&data, &options.padding)?;
+		flags |= FLAG_PADDED;
+	}
+	Ok((flags, data))
+}
+
+fn revert(flags: u8, data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+	if flags & !SUPPORTED_FLAGS != 0 { return Err("Frame uses unsupported flags".to_string()); }
+	let mut data = data.to_vec();
+	if flags & FLAG_PADDED != 0 {
+		data = unpad(&data)?;
+	}
+	if flags & FLAG_COMPRESSED != 0 {
+		data = decompress(&data, max_size)?;
+	}
+	Ok(data)
+}
+
+// Returns the msg_bytes to send with FRAME_MSG_TYPE. The counter is taken from the session and sent together with the current timestamp.
+pub fn frame_msg(msg: (u8, Option<&str>, Option<&[u8]>), options: &FrameOptions, counter: Option<u64>) -> Result<Vec<u8>, String> {
+	let (mut flags, body) = transform(encode_body(msg)?, options)?;
+	let mut header = Vec::new();
+	if let Some(counter) = counter {
+		let sent = timestamp_value(&get_current_timestamp()?)?;
+		header.extend_from_slice(&counter.to_be_bytes());
+		header.extend_from_slice(&sent.to_be_bytes());
+		flags |= FLAG_SEQUENCED;
+	}
+	if let Some(ttl) = options.ttl {
+		header.extend_from_slice(&ttl.to_be_bytes());
+		flags |= FLAG_EXPIRING;
+	}
+	let mut frame = Vec::with_capacity(2 + header.len() + body.len());
+	frame.push(FRAME_VERSION);
+	frame.push(flags);
+	frame.extend_from_slice(&header);
+	frame.extend_from_slice(&body);
+	Ok(frame)
+}
+
+// Returns the original message for framed messages and passes all other messages through unchanged
+pub fn unframe_msg(msg: MsgTriple) -> Result<MsgTriple, String> {
+	Ok(unframe_msg_info(msg)?.0)
+}
+
+// Like unframe_msg, but also returns the header information of the frame
+pub fn unframe_msg_info(msg: MsgTriple) -> Result<(MsgTriple, FrameInfo), String> {
+	let (msg_type, _, msg_bytes) = &msg;
+	if *msg_type != FRAME_MSG_TYPE { return Ok((msg, FrameInfo::default())); }
+	let frame = match msg_bytes {
+		Some(bytes) if bytes.len() >= 2 => bytes,
+		_ => return Err("Frame too short".to_string())
+	};
+	if frame[0] != FRAME_VERSION { return Err(format!("Unsupported frame version: {}", frame[0])); }
+	let flags = frame[1];
+	let mut body = &frame[2..];
+	let mut info = FrameInfo::default();
+	if flags & FLAG_SEQUENCED != 0 {
+		if body.len() < SEQUENCE_SIZE { return Err("Frame sequence too short".to_string()); }
+		info.sequence = Some(Sequence { counter: read_u64(body), sent: read_u64(&body[8..]) });
+		body = &body[SEQUENCE_SIZE..];
+	}
+	if flags & FLAG_EXPIRING != 0 {
+		if body.len() < TTL_SIZE { return Err("Frame ttl too short".to_string()); }
+		info.ttl = Some(read_u64(body));
+		body = &body[TTL_SIZE..];
+	}
+	Ok((decode_body(&revert(flags & !HEADER_FLAGS, body, MAX_DECOMPRESSED_MSG_SIZE)?)?, info))
+}
+
+fn file_frame_check(version: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+	hash(&signed_data(&[b"dawn-file-frame", &[version, flags], body]))[..FILE_FRAME_CHECK_SIZE].to_vec()
+}
+
+pub fn frame_file(file: &[u8], options: &FrameOptions) -> Result<Vec<u8>, String> {
+	let (flags, body) = transform(file.to_vec(), options)?;
+	let mut frame = Vec::with_capacity(FILE_FRAME_HEADER_SIZE + body.len());
+	frame.extend_from_slice(FILE_FRAME_MAGIC);
+	frame.push(FRAME_VERSION);
+	frame.push(flags);
+	frame.extend_from_slice(&file_frame_check(FRAME_VERSION, flags, &body));
+	frame.extend_from_slice(&body);
+	Ok(frame)
+}
+
+pub fn unframe_file(file: Vec<u8>) -> Result<Vec<u8>, String> {
+	if !file.starts_with(FILE_FRAME_MAGIC) || file.len() < FILE_FRAME_HEADER_SIZE { return Ok(file); }
+	let (version, flags) = (file[FILE_FRAME_MAGIC.len()], file[FILE_FRAME_MAGIC.len() + 1]);
+	let check = &file[FILE_FRAME_MAGIC.len() + 2..FILE_FRAME_HEADER_SIZE];
+	let body = &file[FILE_FRAME_HEADER_SIZE..];
+	if check != file_frame_check(version, flags, body).as_slice() { return Ok(file); }
+	if version != FRAME_VERSION { return Err(format!("Unsupported frame version: {}", version)); }
+	revert(flags, body, MAX_DECOMPRESSED_FILE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn unframe(frame: Vec<u8>) -> Result<(MsgTriple, FrameInfo), String> {
+		unframe_msg_info((FRAME_MSG_TYPE, None, Some(frame)))
+	}
+	
+	#[test]
+	fn padded_msg_roundtrip() {
+		let options = FrameOptions { padding: PaddingPolicy::Block(64), ..Default::default() };
+		let frame = frame_msg((3, Some("hello"), Some(&[1, 2, 3])), &options, None).unwrap();
+		assert_eq!((frame.len() - 2) % 64, 0);
+		let (msg, info) = unframe(frame).unwrap();
+		assert_eq!(msg, (3, Some("hello".to_string()), Some(vec![1, 2, 3])));
+		assert_eq!(info, FrameInfo::default());
+	}
+	
+	#[test]
+	fn unframed_msg_passes_through() {
+		let msg = (0, Some("legacy".to_string()), None);
+		assert_eq!(unframe_msg(msg.clone()).unwrap(), msg);
+		assert!(unframe(vec![FRAME_VERSION]).is_err());
+		assert!(unframe(vec![FRAME_VERSION, 0x80, 0, 0, 0, 0, 0]).is_err());
+	}
+	
+	#[test]
+	fn padded_file_roundtrip() {
+		let options = FrameOptions { padding: PaddingPolicy::Padme, ..Default::default() };
+		let file = b"hello hello hello".repeat(10);
+		assert_eq!(unframe_file(frame_file(&file, &options).unwrap()).unwrap(), file);
+		// legacy files starting with the magic are returned unchanged
+		let legacy = b"DWNF legacy file".to_vec();
+		assert_eq!(unframe_file(legacy.clone()).unwrap(), legacy);
+	}
+	
+	#[test]
+	fn compressed_roundtrip() {
+		let options = FrameOptions { padding: PaddingPolicy::Padme, compression: Compression::Deflate, ..Default::default() };
+		let text = "hello ".repeat(100);
+		let frame = frame_msg((0, Some(&text), None), &options, None).unwrap();
+		assert!(frame.len() < text.len());
+		assert_eq!(unframe(frame).unwrap().0, (0, Some(text.clone()), None));
+		assert_eq!(unframe_file(frame_file(text.as_bytes(), &options).unwrap()).unwrap(), text.as_bytes());
+	}
+	
+	#[test]
+	fn decompression_is_capped() {
+		let options = FrameOptions { compression: Compression::Deflate, ..Default::default() };
+		let frame = frame_msg((0, None, Some(&vec![0u8; MAX_DECOMPRESSED_MSG_SIZE + 1])), &options, None).unwrap();
+		assert!(frame.len() < 64 * 1024);
+		assert!(unframe(frame).is_err());
+		assert_eq!(decompress(&compress(&[0u8; 100]).unwrap(), 100).unwrap(), vec![0u8; 100]);
+		assert!(decompress(&compress(&[0u8; 101]).unwrap(), 100).is_err());
+	}
+	
+	#[test]
+	fn sequenced_msg_roundtrip() {
+		let now = || timestamp_value(&get_current_timestamp().unwrap()).unwrap();
+		let before = now();
+		let frame = frame_msg((0, Some("hi"), None), &FrameOptions::default(), Some(7)).unwrap();
+		let after = now();
+		let sequence = unframe(frame).unwrap().1.sequence.unwrap();
+		assert_eq!(sequence.counter, 7);
+		assert!(before <= sequence.sent && sequence.sent <= after);
+	}
+	
+	#[test]
+	fn clock_skew_saturates() {
+		let sequence = Sequence { counter: 0, sent: u64::MAX };
+		assert!(sequence.clock_skew().unwrap() > 0);
+		let sequence = Sequence { counter: 0, sent: 0 };
+		assert!(sequence.clock_skew().unwrap() < 0);
+	}
+	
+	#[test]
+	fn expiring_msg_roundtrip() {
+		let options = FrameOptions { ttl: Some(60), ..Default::default() };
+		let info = unframe(frame_msg((0, Some("hi"), None), &options, None).unwrap()).unwrap().1;
+		assert_eq!(info.ttl, Some(60));
+		let now = timestamp_value(&get_current_timestamp().unwrap()).unwrap();
+		let expires = info.expires().unwrap().unwrap();
+		assert!(now + 60 <= expires && expires <= timestamp_value(&get_current_timestamp().unwrap()).unwrap() + 60);
+		assert_eq!(FrameInfo { sequence: None, ttl: Some(u64::MAX) }.expires().unwrap(), Some(u64::MAX));
+		assert_eq!(FrameInfo::default().expires().unwrap(), None);
+	}
+}
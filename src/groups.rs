@@ -0,0 +1,1168 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::jshort;
+use hex::{encode, decode};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce, aead::{Aead, KeyInit}};
+use serde::{Serialize, Deserialize};
+use crate::{Error, GroupResponse, SignGroupOperation, ValidateGroupOperations, EncryptGroupMessage, DecryptGroupMessage, OutgoingPayload};
+use crate::frame::{FrameOptions, FRAME_MSG_TYPE, frame_msg, unframe_msg};
+use crate::payload::{Payload, MsgTriple};
+use crate::signatures::{sign_detached, verify_detached, signed_data, fingerprint};
+use crate::trust::{TrustStore, TrustState};
+use crate::error;
+
+const GROUP_MESSAGE_VERSION: u8 = 1;
+
+// Limits how far a receiving chain may be advanced for a single message and how many skipped message keys are kept for
+// messages arriving out of order
+const MAX_SKIP: u64 = 2000;
+const MAX_SKIPPED_KEYS: usize = 2000;
+
+// Every member encrypts its group messages with its own sender key. The chain key is ratcheted forward after every message,
+// so a compromised chain key does not reveal earlier messages. Sender keys are only ever distributed through the pairwise
+// sessions (as GroupKey payloads) and are replaced by a new epoch whenever a member leaves the group.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SenderKey {
+	pub epoch: u32,
+	pub chain_key: String,
+	pub counter: u64,
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub skipped: BTreeMap<u64, String>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Member {
+	pub member_id: String,
+	pub pubkey_sig: String,
+	// whether pubkey_sig was checked against a known contact instead of only being taken from an invitation or operation
+	#[serde(default)]
+	pub verified: bool,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sender_key: Option<SenderKey>
+}
+
+// Group state as stored by the app. members does not contain the own member.
+// op_seq and op_head describe the last operation applied from the group operation log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Group {
+	pub group_id: String,
+	pub name: String,
+	pub own_member_id: String,
+	pub own_pubkey_sig: String,
+	pub own_sender_key: SenderKey,
+	pub members: Vec<Member>,
+	#[serde(default)]
+	pub admins: Vec<String>,
+	#[serde(default)]
+	pub op_seq: u64,
+	#[serde(default)]
+	pub op_head: String
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MemberInfo {
+	pub member_id: String,
+	pub pubkey_sig: String
+}
+
+// Snapshot of a group sent to new members when they are added. members contains every member including the sender.
+// All later changes are only accepted as signed group operations.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GroupUpdate {
+	pub group_id: String,
+	pub name: String,
+	pub members: Vec<MemberInfo>,
+	#[serde(default)]
+	pub admins: Vec<String>,
+	#[serde(default)]
+	pub op_seq: u64,
+	#[serde(default)]
+	pub op_head: String
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum OperationKind {
+	Add { member_id: String, pubkey_sig: String },
+	Remove { member_id: String },
+	Promote { member_id: String },
+	Rename { name: String }
+}
+
+// Entry of the group operation log. Every operation is signed by an admin and references the hash of the previous
+// operation, so members that agree on op_head agree on the whole history of the group.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GroupOperation {
+	pub group_id: String,
+	pub seq: u64,
+	pub prev: String,
+	pub actor: String,
+	pub operation: OperationKind,
+	#[serde(default)]
+	pub signature: String
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SenderKeyDistribution {
+	pub group_id: String,
+	pub sender_id: String,
+	pub epoch: u32,
+	pub chain_key: String,
+	pub counter: u64
+}
+
+#[derive(Serialize, Deserialize)]
+struct GroupMessage {
+	version: u8,
+	group_id: String,
+	sender_id: String,
+	epoch: u32,
+	counter: u64,
+	ciphertext: String,
+	signature: String
+}
+
+fn kdf(chain_key: &[u8], label: u8) -> Result<Vec<u8>, String> {
+	let mut input = chain_key.to_vec();
+	input.push(label);
+	let output = hash(&input);
+	if output.len() < 32 { return Err("Hash output too short for key derivation".to_string()); }
+	Ok(output[..32].to_vec())
+}
+
+fn message_key(chain_key: &[u8]) -> Result<Vec<u8>, String> {
+	kdf(chain_key, 0x01)
+}
+
+fn next_chain_key(chain_key: &[u8]) -> Result<Vec<u8>, String> {
+	kdf(chain_key, 0x02)
+}
+
+fn decode_key(key: &str) -> Result<Vec<u8>, String> {
+	decode(key).map_err(|_| "Group key invalid".to_string())
+}
+
+// Every message key is only used once, so a fixed nonce is fine here
+fn sym_encrypt(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+	let cipher = Aes256GcmSiv::new_from_slice(key).map_err(|_| "Group key invalid".to_string())?;
+	cipher.encrypt(Nonce::from_slice(&[0u8; 12]), plaintext).map_err(|_| "Could not encrypt group message".to_string())
+}
+
+fn sym_decrypt(key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+	let cipher = Aes256GcmSiv::new_from_slice(key).map_err(|_| "Group key invalid".to_string())?;
+	cipher.decrypt(Nonce::from_slice(&[0u8; 12]), ciphertext).map_err(|_| "Could not decrypt group message".to_string())
+}
+
+fn message_signed_data(group_id: &str, sender_id: &str, epoch: u32, counter: u64, ciphertext: &[u8]) -> Vec<u8> {
+	signed_data(&[&[GROUP_MESSAGE_VERSION], group_id.as_bytes(), sender_id.as_bytes(), &epoch.to_be_bytes(), &counter.to_be_bytes(), ciphertext])
+}
+
+fn operation_signed_data(operation: &GroupOperation) -> Result<Vec<u8>, String> {
+	let kind = serde_json::to_vec(&operation.operation).map_err(|_| "Could not serialize group operation".to_string())?;
+	Ok(signed_data(&[b"group-operation", operation.group_id.as_bytes(), &operation.seq.to_be_bytes(), operation.prev.as_bytes(), operation.actor.as_bytes(), &kind]))
+}
+
+fn outgoing(recipient: &str, payload: &Payload) -> Result<OutgoingPayload, String> {
+	let (msg_type, msg_text, msg_bytes) = payload.encode()?;
+	Ok(OutgoingPayload {
+		recipient: recipient.to_string(),
+		msg_type,
+		msg_text: msg_text.unwrap_or_default(),
+		msg_bytes: match msg_bytes {
+			Some(bytes) => BASE64.encode(bytes),
+			None => "".to_string()
+		}
+	})
+}
+
+impl SenderKey {
+	fn new(epoch: u32) -> SenderKey {
+		SenderKey {
+			epoch,
+			chain_key: encode(sym_key_gen()),
+			counter: 0,
+			skipped: BTreeMap::new()
+		}
+	}
+	
+	// Returns the message key for the given counter and advances the chain if necessary
+	fn receive(&mut self, counter: u64) -> Result<Vec<u8>, String> {
+		if counter < self.counter {
+			return match self.skipped.remove(&counter) {
+				Some(key) => decode_key(&key),
+				None => Err("Message key already used or discarded".to_string())
+			};
+		}
+		if counter - self.counter > MAX_SKIP { return Err("Too many skipped group messages".to_string()); }
+		let mut chain_key = decode_key(&self.chain_key)?;
+		while self.counter < counter {
+			self.skipped.insert(self.counter, encode(message_key(&chain_key)?));
+			chain_key = next_chain_key(&chain_key)?;
+			self.counter += 1;
+		}
+		while self.skipped.len() > MAX_SKIPPED_KEYS {
+			self.skipped.pop_first();
+		}
+		let key = message_key(&chain_key)?;
+		self.chain_key = encode(next_chain_key(&chain_key)?);
+		self.counter += 1;
+		Ok(key)
+	}
+}
+
+impl Group {
+	pub fn new(name: &str, own_member_id: &str, own_pubkey_sig: &str) -> Group {
+		Group {
+			group_id: id_gen(),
+			name: name.to_string(),
+			own_member_id: own_member_id.to_string(),
+			own_pubkey_sig: own_pubkey_sig.to_string(),
+			own_sender_key: SenderKey::new(0),
+			members: Vec::new(),
+			admins: vec![own_member_id.to_string()],
+			op_seq: 0,
+			op_head: "".to_string()
+		}
+	}
+	
+	fn member(&self, member_id: &str) -> Option<&Member> {
+		self.members.iter().find(|member| member.member_id == member_id)
+	}
+	
+	fn member_mut(&mut self, member_id: &str) -> Option<&mut Member> {
+		self.members.iter_mut().find(|member| member.member_id == member_id)
+	}
+	
+	fn pubkey_sig_of(&self, member_id: &str) -> Option<&str> {
+		if member_id == self.own_member_id { return Some(&self.own_pubkey_sig); }
+		self.member(member_id).map(|member| member.pubkey_sig.as_str())
+	}
+	
+	fn is_admin(&self, member_id: &str) -> bool {
+		self.admins.iter().any(|admin| admin == member_id)
+	}
+	
+	pub fn update(&self) -> GroupUpdate {
+		let mut members = vec![MemberInfo { member_id: self.own_member_id.clone(), pubkey_sig: self.own_pubkey_sig.clone() }];
+		members.extend(self.members.iter().map(|member| MemberInfo { member_id: member.member_id.clone(), pubkey_sig: member.pubkey_sig.clone() }));
+		GroupUpdate {
+			group_id: self.group_id.clone(),
+			name: self.name.clone(),
+			members,
+			admins: self.admins.clone(),
+			op_seq: self.op_seq,
+			op_head: self.op_head.clone()
+		}
+	}
+	
+	fn distribution(&self) -> SenderKeyDistribution {
+		SenderKeyDistribution {
+			group_id: self.group_id.clone(),
+			sender_id: self.own_member_id.clone(),
+			epoch: self.own_sender_key.epoch,
+			chain_key: self.own_sender_key.chain_key.clone(),
+			counter: self.own_sender_key.counter
+		}
+	}
+	
+	fn rekey(&mut self) {
+		self.own_sender_key = SenderKey::new(self.own_sender_key.epoch + 1);
+	}
+	
+	// Payloads distributing the own sender key to the given members
+	fn distribute_key<'a>(&self, recipients: impl Iterator<Item = &'a str>) -> Result<Vec<OutgoingPayload>, String> {
+		let payload = Payload::GroupKey(self.distribution());
+		recipients.map(|recipient| outgoing(recipient, &payload)).collect()
+	}
+	
+	// Checks that the operation continues the operation log, was signed by an admin and can be applied to the current state
+	pub fn validate_operation(&self, operation: &GroupOperation) -> Result<(), String> {
+		if operation.group_id != self.group_id { return Err("Group operation belongs to another group".to_string()); }
+		if operation.seq != self.op_seq + 1 || operation.prev != self.op_head { return Err("Group operation does not continue the operation log".to_string()); }
+		// every member may leave the group on its own
+		let leaving = matches!(&operation.operation, OperationKind::Remove { member_id } if *member_id == operation.actor);
+		if !leaving && !self.is_admin(&operation.actor) { return Err("Group operation was not made by an admin".to_string()); }
+		let pubkey_sig = match self.pubkey_sig_of(&operation.actor) {
+			Some(pubkey_sig) => decode(pubkey_sig).map_err(|_| "Admin signature key invalid".to_string())?,
+			None => return Err("Group operation was not made by a member".to_string())
+		};
+		let signature = BASE64.decode(&operation.signature).map_err(|_| "Group operation signature invalid".to_string())?;
+		verify_detached(&operation_signed_data(operation)?, &signature, &pubkey_sig)?;
+		match &operation.operation {
+			OperationKind::Add { member_id, pubkey_sig } => {
+				if self.pubkey_sig_of(member_id).is_some() { return Err("Member is already part of the group".to_string()); }
+				if decode(pubkey_sig).is_err() { return Err("Member signature key invalid".to_string()); }
+			},
+			OperationKind::Remove { member_id } => {
+				if self.pubkey_sig_of(member_id).is_none() { return Err("Member is not part of the group".to_string()); }
+			},
+			OperationKind::Promote { member_id } => {
+				if self.pubkey_sig_of(member_id).is_none() { return Err("Member is not part of the group".to_string()); }
+				if self.is_admin(member_id) { return Err("Member is already an admin".to_string()); }
+			},
+			OperationKind::Rename { name } => {
+				if name.is_empty() { return Err("Group name must not be empty".to_string()); }
+			}
+		}
+		Ok(())
+	}
+	
+	// Applies a validated operation. Returns the payloads to send through the pairwise sessions and whether the own member was removed.
+	pub fn apply_operation(&mut self, operation: &GroupOperation) -> Result<(Vec<OutgoingPayload>, bool), String> {
+		self.validate_operation(operation)?;
+		let signature = BASE64.decode(&operation.signature).map_err(|_| "Group operation signature invalid".to_string())?;
+		let mut entry = operation_signed_data(operation)?;
+		entry.extend_from_slice(&signature);
+		self.op_head = encode(hash(&entry));
+		self.op_seq = operation.seq;
+		
+		let own_operation = operation.actor == self.own_member_id;
+		// the actor forwards the operation to everyone who was a member before it was applied
+		let mut recipients: Vec<String> = self.members.iter().map(|member| member.member_id.clone()).collect();
+		let mut payloads = Vec::new();
+		match &operation.operation {
+			OperationKind::Add { member_id, pubkey_sig } => {
+				// the key of a member added by ourselves comes from our own contacts
+				self.members.push(Member { member_id: member_id.clone(), pubkey_sig: pubkey_sig.clone(), verified: own_operation, sender_key: None });
+				if own_operation {
+					payloads.push(outgoing(member_id, &Payload::GroupUpdate(self.update()))?);
+				}
+				payloads.append(&mut self.distribute_key([member_id.as_str()].into_iter())?);
+			},
+			OperationKind::Remove { member_id } => {
+				if *member_id == self.own_member_id {
+					// when leaving on our own, the other members still have to learn about it
+					if own_operation {
+						let forwarded = Payload::GroupOperation(operation.clone());
+						payloads = recipients.iter().map(|recipient| outgoing(recipient, &forwarded)).collect::<Result<Vec<_>, _>>()?;
+					}
+					self.members.clear();
+					self.admins.clear();
+					return Ok((payloads, true));
+				}
+				self.members.retain(|member| member.member_id != *member_id);
+				self.admins.retain(|admin| admin != member_id);
+				self.rekey();
+				payloads.append(&mut self.distribute_key(self.members.iter().map(|member| member.member_id.as_str()))?);
+			},
+			OperationKind::Promote { member_id } => {
+				self.admins.push(member_id.clone());
+			},
+			OperationKind::Rename { name } => {
+				self.name = name.clone();
+			}
+		}
+		if own_operation {
+			if let OperationKind::Add { member_id, .. } = &operation.operation {
+				recipients.retain(|recipient| recipient != member_id);
+			}
+			let forwarded = Payload::GroupOperation(operation.clone());
+			let mut forwards = recipients.iter().map(|recipient| outgoing(recipient, &forwarded)).collect::<Result<Vec<_>, _>>()?;
+			forwards.append(&mut payloads);
+			payloads = forwards;
+		}
+		Ok((payloads, false))
+	}
+	
+	pub fn sign_operation(&mut self, kind: OperationKind, own_seckey_sig: &[u8]) -> Result<(GroupOperation, Vec<OutgoingPayload>), String> {
+		let mut operation = GroupOperation {
+			group_id: self.group_id.clone(),
+			seq: self.op_seq + 1,
+			prev: self.op_head.clone(),
+			actor: self.own_member_id.clone(),
+			operation: kind,
+			signature: "".to_string()
+		};
+		operation.signature = BASE64.encode(sign_detached(&operation_signed_data(&operation)?, own_seckey_sig)?);
+		let (payloads, _) = self.apply_operation(&operation)?;
+		Ok((operation, payloads))
+	}
+	
+	// Validates a sequence of operations against the current state without changing it. Returns the index of the first invalid operation.
+	pub fn validate_operations(&self, operations: &[GroupOperation]) -> Result<(), (usize, String)> {
+		let mut group = self.clone();
+		for (index, operation) in operations.iter().enumerate() {
+			if let Err(err) = group.apply_operation(operation) { return Err((index, err)); }
+		}
+		Ok(())
+	}
+	
+	// Checks the member keys against the known contacts. Members that are not known contacts stay unverified,
+	// a known contact with a different key is rejected.
+	pub fn verify_members(&mut self, trust_store: &TrustStore) -> Result<(), String> {
+		for member in self.members.iter_mut() {
+			let entry = match trust_store.contacts.get(&member.member_id) {
+				Some(entry) if entry.state != TrustState::Changed => entry,
+				_ => continue
+			};
+			let pubkey_sig = decode(&member.pubkey_sig).map_err(|_| "Member signature key invalid".to_string())?;
+			if entry.fingerprint != fingerprint(&pubkey_sig) { return Err(format!("Signing key of member {} does not match the known contact", member.member_id)); }
+			member.verified = true;
+		}
+		Ok(())
+	}
+	
+	// Creates the group state when being invited to a group. The snapshot is supplied by the inviter, so the inviter is
+	// checked against the signing key of the pairwise session it arrived through and the other members against the known contacts.
+	pub fn join(update: &GroupUpdate, own_member_id: &str, sender_id: &str, sender_pubkey_sig: &[u8], trust_store: &TrustStore) -> Result<(Group, Vec<OutgoingPayload>), String> {
+		let own_pubkey_sig = match update.members.iter().find(|member| member.member_id == own_member_id) {
+			Some(member) => member.pubkey_sig.clone(),
+			None => return Err("Not a member of this group".to_string())
+		};
+		match update.members.iter().find(|member| member.member_id == sender_id) {
+			Some(member) if decode(&member.pubkey_sig).ok().as_deref() == Some(sender_pubkey_sig) => (),
+			Some(_) => return Err("Signing key of the inviter does not match the session".to_string()),
+			None => return Err("Invitation was not sent by a member".to_string())
+		}
+		if !update.admins.iter().any(|admin| admin == sender_id) { return Err("Invitation was not sent by an admin".to_string()); }
+		let mut group = Group {
+			group_id: update.group_id.clone(),
+			name: update.name.clone(),
+			own_member_id: own_member_id.to_string(),
+			own_pubkey_sig,
+			own_sender_key: SenderKey::new(0),
+			members: update.members.iter()
+				.filter(|member| member.member_id != own_member_id)
+				.map(|member| Member { member_id: member.member_id.clone(), pubkey_sig: member.pubkey_sig.clone(), verified: member.member_id == sender_id, sender_key: None })
+				.collect(),
+			admins: update.admins.clone(),
+			op_seq: update.op_seq,
+			op_head: update.op_head.clone()
+		};
+		group.verify_members(trust_store)?;
+		let payloads = group.distribute_key(group.members.iter().map(|member| member.member_id.as_str()))?;
+		Ok((group, payloads))
+	}
+	
+	pub fn apply_key(&mut self, sender_id: &str, distribution: &SenderKeyDistribution) -> Result<(), String> {
+		if distribution.group_id != self.group_id { return Err("Sender key belongs to another group".to_string()); }
+		if distribution.sender_id != sender_id { return Err("Sender key was not sent by its owner".to_string()); }
+		let member = match self.member_mut(sender_id) {
+			Some(member) => member,
+			None => return Err("Sender key was not sent by a member".to_string())
+		};
+		if let Some(sender_key) = &member.sender_key {
+			if sender_key.epoch > distribution.epoch { return Err("Sender key is outdated".to_string()); }
+			if sender_key.epoch == distribution.epoch { return Ok(()); }
+		}
+		member.sender_key = Some(SenderKey {
+			epoch: distribution.epoch,
+			chain_key: distribution.chain_key.clone(),
+			counter: distribution.counter,
+			skipped: BTreeMap::new()
+		});
+		Ok(())
+	}
+	
+	pub fn encrypt(&mut self, msg: (u8, Option<&str>, Option<&[u8]>), options: &FrameOptions, own_seckey_sig: &[u8]) -> Result<Vec<u8>, String> {
+		// Group messages are ordered by the sender key counter instead
+		let frame = frame_msg(msg, options, None)?;
+		let chain_key = decode_key(&self.own_sender_key.chain_key)?;
+		let ciphertext = sym_encrypt(&message_key(&chain_key)?, &frame)?;
+		let counter = self.own_sender_key.counter;
+		let signature = sign_detached(&message_signed_data(&self.group_id, &self.own_member_id, self.own_sender_key.epoch, counter, &ciphertext), own_seckey_sig)?;
+		self.own_sender_key.chain_key = encode(next_chain_key(&chain_key)?);
+		self.own_sender_key.counter += 1;
+		let message = GroupMessage {
+			version: GROUP_MESSAGE_VERSION,
+			group_id: self.group_id.clone(),
+			sender_id: self.own_member_id.clone(),
+			epoch: self.own_sender_key.epoch,
+			counter,
+			ciphertext: BASE64.encode(ciphertext),
+			signature: BASE64.encode(signature)
+		};
+		serde_json::to_vec(&message).map_err(|_| "Could not serialize group message".to_string())
+	}
+	
+	pub fn decrypt(&mut self, message: &[u8]) -> Result<(String, MsgTriple), String> {
+		let message: GroupMessage = serde_json::from_slice(message).map_err(|_| "Group message invalid".to_string())?;
+		if message.version != GROUP_MESSAGE_VERSION { return Err(format!("Unsupported group message version: {}", message.version)); }
+		if message.group_id != self.group_id { return Err("Group message belongs to another group".to_string()); }
+		let ciphertext = BASE64.decode(&message.ciphertext).map_err(|_| "Group message ciphertext invalid".to_string())?;
+		let signature = BASE64.decode(&message.signature).map_err(|_| "Group message signature invalid".to_string())?;
+		let member = match self.member_mut(&message.sender_id) {
+			Some(member) => member,
+			None => return Err("Group message was not sent by a member".to_string())
+		};
+		let pubkey_sig = decode(&member.pubkey_sig).map_err(|_| "Member signature key invalid".to_string())?;
+		verify_detached(&message_signed_data(&message.group_id, &message.sender_id, message.epoch, message.counter, &ciphertext), &signature, &pubkey_sig)?;
+		let sender_key = match &mut member.sender_key {
+			Some(sender_key) if sender_key.epoch == message.epoch => sender_key,
+			_ => return Err("No sender key for this epoch".to_string())
+		};
+		// only commit the advanced chain once the message actually decrypted
+		let mut advanced = sender_key.clone();
+		let frame = sym_decrypt(&advanced.receive(message.counter)?, &ciphertext)?;
+		*sender_key = advanced;
+		Ok((message.sender_id, unframe_msg((FRAME_MSG_TYPE, None, Some(frame)))?))
+	}
+}
+
+// Handles the group related payloads received through a pairwise session. For invitations there is no group state yet.
+pub fn process_group_payload(group: Option<Group>, own_member_id: &str, sender_id: &str, sender_pubkey_sig: &[u8], trust_store: &TrustStore, payload: &Payload) -> Result<(Group, Vec<OutgoingPayload>, bool), String> {
+	match (group, payload) {
+		(None, Payload::GroupUpdate(update)) => {
+			let (group, payloads) = Group::join(update, own_member_id, sender_id, sender_pubkey_sig, trust_store)?;
+			Ok((group, payloads, false))
+		},
+		(Some(_), Payload::GroupUpdate(_)) => Err("Group changes are only accepted as signed group operations".to_string()),
+		(Some(mut group), Payload::GroupOperation(operation)) => {
+			let (payloads, removed) = group.apply_operation(operation)?;
+			group.verify_members(trust_store)?;
+			Ok((group, payloads, removed))
+		},
+		(Some(mut group), Payload::GroupKey(distribution)) => {
+			group.apply_key(sender_id, distribution)?;
+			Ok((group, Vec::new(), false))
+		},
+		(None, Payload::GroupKey(_)) => Err("Received sender key for an unknown group".to_string()),
+		(None, Payload::GroupOperation(_)) => Err("Received group operation for an unknown group".to_string()),
+		_ => Err("Not a group payload".to_string())
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_createGroup<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	name: JString<'local>,
+	own_member_id: JString<'local>,
+	own_pubkey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let name = env.get_string(&name);
+	if name.is_err() { error!(env, "Could not get java variable: name"); }
+	let name: String = name.unwrap().into();
+	
+	let own_member_id = env.get_string(&own_member_id);
+	if own_member_id.is_err() { error!(env, "Could not get java variable: own_member_id"); }
+	let own_member_id: String = own_member_id.unwrap().into();
+	
+	let own_pubkey_sig = env.get_string(&own_pubkey_sig);
+	if own_pubkey_sig.is_err() { error!(env, "Could not get java variable: own_pubkey_sig"); }
+	let own_pubkey_sig: String = own_pubkey_sig.unwrap().into();
+	if decode(&own_pubkey_sig).is_err() { error!(env, "own_pubkey_sig invalid"); }
+	
+	let group = Group::new(&name, &own_member_id, &own_pubkey_sig);
+	
+	let group_response = GroupResponse {
+		status: "ok",
+		group: &group,
+		removed: false,
+		payloads: Vec::new()
+	};
+	
+	let group_response_json = match serde_json::to_string(&group_response) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	group_response_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_addGroupMember<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	group: JString<'local>,
+	member_id: JString<'local>,
+	member_pubkey_sig: JString<'local>,
+	own_seckey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let group = env.get_string(&group);
+	if group.is_err() { error!(env, "Could not get java variable: group"); }
+	let group: String = group.unwrap().into();
+	let mut group: Group = match serde_json::from_str(&group) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "group invalid"); }
+	};
+	
+	let member_id = env.get_string(&member_id);
+	if member_id.is_err() { error!(env, "Could not get java variable: member_id"); }
+	let member_id: String = member_id.unwrap().into();
+	
+	let member_pubkey_sig = env.get_string(&member_pubkey_sig);
+	if member_pubkey_sig.is_err() { error!(env, "Could not get java variable: member_pubkey_sig"); }
+	let member_pubkey_sig: String = member_pubkey_sig.unwrap().into();
+	if decode(&member_pubkey_sig).is_err() { error!(env, "member_pubkey_sig invalid"); }
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let (_, payloads) = match group.sign_operation(OperationKind::Add { member_id, pubkey_sig: member_pubkey_sig }, &own_seckey_sig) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not add group member: {}", err)); }
+	};
+	
+	let group_response = GroupResponse {
+		status: "ok",
+		group: &group,
+		removed: false,
+		payloads
+	};
+	
+	let group_response_json = match serde_json::to_string(&group_response) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	group_response_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_removeGroupMember<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	group: JString<'local>,
+	member_id: JString<'local>,
+	own_seckey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let group = env.get_string(&group);
+	if group.is_err() { error!(env, "Could not get java variable: group"); }
+	let group: String = group.unwrap().into();
+	let mut group: Group = match serde_json::from_str(&group) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "group invalid"); }
+	};
+	
+	let member_id = env.get_string(&member_id);
+	if member_id.is_err() { error!(env, "Could not get java variable: member_id"); }
+	let member_id: String = member_id.unwrap().into();
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	// removing the own member leaves the group, which does not require admin rights
+	let removed = member_id == group.own_member_id;
+	let (_, payloads) = match group.sign_operation(OperationKind::Remove { member_id }, &own_seckey_sig) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not remove group member: {}", err)); }
+	};
+	
+	let group_response = GroupResponse {
+		status: "ok",
+		group: &group,
+		removed,
+		payloads
+	};
+	
+	let group_response_json = match serde_json::to_string(&group_response) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	group_response_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_processGroupPayload<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	group: JString<'local>,
+	own_member_id: JString<'local>,
+	sender_member_id: JString<'local>,
+	sender_pubkey_sig: JString<'local>,
+	trust_store: JString<'local>,
+	msg_type: jshort,
+	msg_text: JString<'local>,
+	msg_bytes: JByteArray<'local>
+) -> JString<'local> {
+	
+	let group = env.get_string(&group);
+	if group.is_err() { error!(env, "Could not get java variable: group"); }
+	let group: String = group.unwrap().into();
+	let group: Option<Group> = match group.as_str() {
+		"" => None,
+		_ => match serde_json::from_str(&group) {
+			Ok(res) => Some(res),
+			Err(_) => { error!(env, "group invalid"); }
+		}
+	};
+	
+	let own_member_id = env.get_string(&own_member_id);
+	if own_member_id.is_err() { error!(env, "Could not get java variable: own_member_id"); }
+	let own_member_id: String = own_member_id.unwrap().into();
+	
+	let sender_member_id = env.get_string(&sender_member_id);
+	if sender_member_id.is_err() { error!(env, "Could not get java variable: sender_member_id"); }
+	let sender_member_id: String = sender_member_id.unwrap().into();
+	
+	let sender_pubkey_sig = env.get_string(&sender_pubkey_sig);
+	if sender_pubkey_sig.is_err() { error!(env, "Could not get java variable: sender_pubkey_sig"); }
+	let sender_pubkey_sig: String = sender_pubkey_sig.unwrap().into();
+	let sender_pubkey_sig = match decode(sender_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "sender_pubkey_sig invalid"); }
+	};
+	
+	let trust_store = env.get_string(&trust_store);
+	if trust_store.is_err() { error!(env, "Could not get java variable: trust_store"); }
+	let trust_store: String = trust_store.unwrap().into();
+	let trust_store: TrustStore = match trust_store.as_str() {
+		"" => TrustStore::default(),
+		_ => match serde_json::from_str(&trust_store) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "trust_store invalid"); }
+		}
+	};
+	
+	let msg_type = match u8::try_from(msg_type) {
+		Ok(n) => n,
+		Err(_) => { error!(env, &format!("Invalid message type provided: {}", msg_type)); },
+	};
+	
+	let msg_text = env.get_string(&msg_text);
+	if msg_text.is_err() { error!(env, "Could not get java variable: msg_text"); }
+	let msg_text: String = msg_text.unwrap().into();
+	let msg_text = match msg_text.as_str() {
+		"" => None,
+		_ => Some(msg_text.as_str())
+	};
+	
+	let msg_bytes = env.convert_byte_array(msg_bytes);
+	if msg_bytes.is_err() { error!(env, "Could not get java variable: msg_bytes"); }
+	let msg_bytes = msg_bytes.unwrap();
+	let msg_bytes = match msg_bytes.len() {
+		0 => None,
+		_ => Some(msg_bytes.as_slice())
+	};
+	
+	let payload = match Payload::decode(msg_type, msg_text, msg_bytes) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not decode payload: {}", err)); }
+	};
+	
+	let (group, payloads, removed) = match process_group_payload(group, &own_member_id, &sender_member_id, &sender_pubkey_sig, &trust_store, &payload) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not process group payload: {}", err)); }
+	};
+	
+	let group_response = GroupResponse {
+		status: "ok",
+		group: &group,
+		removed,
+		payloads
+	};
+	
+	let group_response_json = match serde_json::to_string(&group_response) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	group_response_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_signGroupOperation<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	group: JString<'local>,
+	operation: JString<'local>,
+	own_seckey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let group = env.get_string(&group);
+	if group.is_err() { error!(env, "Could not get java variable: group"); }
+	let group: String = group.unwrap().into();
+	let mut group: Group = match serde_json::from_str(&group) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "group invalid"); }
+	};
+	
+	let operation = env.get_string(&operation);
+	if operation.is_err() { error!(env, "Could not get java variable: operation"); }
+	let operation: String = operation.unwrap().into();
+	let operation: OperationKind = match serde_json::from_str(&operation) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "operation invalid"); }
+	};
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let (operation, payloads) = match group.sign_operation(operation, &own_seckey_sig) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not sign group operation: {}", err)); }
+	};
+	
+	let sign_group_operation = SignGroupOperation {
+		status: "ok",
+		group: &group,
+		operation: &operation,
+		payloads
+	};
+	
+	let sign_group_operation_json = match serde_json::to_string(&sign_group_operation) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	sign_group_operation_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_applyGroupOperation<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	group: JString<'local>,
+	operation: JString<'local>
+) -> JString<'local> {
+	
+	let group = env.get_string(&group);
+	if group.is_err() { error!(env, "Could not get java variable: group"); }
+	let group: String = group.unwrap().into();
+	let mut group: Group = match serde_json::from_str(&group) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "group invalid"); }
+	};
+	
+	let operation = env.get_string(&operation);
+	if operation.is_err() { error!(env, "Could not get java variable: operation"); }
+	let operation: String = operation.unwrap().into();
+	let operation: GroupOperation = match serde_json::from_str(&operation) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "operation invalid"); }
+	};
+	
+	let (payloads, removed) = match group.apply_operation(&operation) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not apply group operation: {}", err)); }
+	};
+	
+	let group_response = GroupResponse {
+		status: "ok",
+		group: &group,
+		removed,
+		payloads
+	};
+	
+	let group_response_json = match serde_json::to_string(&group_response) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	group_response_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_validateGroupOperations<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	group: JString<'local>,
+	operations: JString<'local>
+) -> JString<'local> {
+	
+	let group = env.get_string(&group);
+	if group.is_err() { error!(env, "Could not get java variable: group"); }
+	let group: String = group.unwrap().into();
+	let group: Group = match serde_json::from_str(&group) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "group invalid"); }
+	};
+	
+	let operations = env.get_string(&operations);
+	if operations.is_err() { error!(env, "Could not get java variable: operations"); }
+	let operations: String = operations.unwrap().into();
+	let operations: Vec<GroupOperation> = match serde_json::from_str(&operations) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "operations invalid"); }
+	};
+	
+	let (valid, invalid_index, reason) = match group.validate_operations(&operations) {
+		Ok(()) => (true, None, None),
+		Err((index, err)) => (false, Some(index), Some(err))
+	};
+	
+	let validate_group_operations = ValidateGroupOperations {
+		status: "ok",
+		valid,
+		invalid_index,
+		reason: reason.as_deref()
+	};
+	
+	let validate_group_operations_json = match serde_json::to_string(&validate_group_operations) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	validate_group_operations_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_encryptGroupMsg<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	group: JString<'local>,
+	msg_type: jshort,
+	msg_string: JString<'local>,
+	msg_bytes: JByteArray<'local>,
+	own_seckey_sig: JString<'local>,
+	options: JString<'local>
+) -> JString<'local> {
+	
+	let group = env.get_string(&group);
+	if group.is_err() { error!(env, "Could not get java variable: group"); }
+	let group: String = group.unwrap().into();
+	let mut group: Group = match serde_json::from_str(&group) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "group invalid"); }
+	};
+	
+	let msg_type = match u8::try_from(msg_type) {
+		Ok(n) => n,
+		Err(_) => { error!(env, &format!("Invalid message type provided: {}", msg_type)); },
+	};
+	
+	let msg_string = env.get_string(&msg_string);
+	if msg_string.is_err() { error!(env, "Could not get java variable: msg_string"); }
+	let msg_string: String = msg_string.unwrap().into();
+	let msg_string = match msg_string.as_str() {
+		"" => None,
+		_ => Some(msg_string.as_str())
+	};
+	
+	let msg_bytes = env.convert_byte_array(msg_bytes);
+	if msg_bytes.is_err() { error!(env, "Could not get java variable: msg_bytes"); }
+	let msg_bytes = msg_bytes.unwrap();
+	let msg_bytes = match msg_bytes.len() {
+		0 => None,
+		_ => Some(msg_bytes.as_slice())
+	};
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let options = env.get_string(&options);
+	if options.is_err() { error!(env, "Could not get java variable: options"); }
+	let options: String = options.unwrap().into();
+	let options: FrameOptions = match options.as_str() {
+		"" => FrameOptions::default(),
+		_ => match serde_json::from_str(&options) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "options invalid"); }
+		}
+	};
+	
+	let ciphertext = match group.encrypt((msg_type, msg_string, msg_bytes), &options, &own_seckey_sig) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not encrypt group message: {}", err)); }
+	};
+	
+	let encrypt_group_message = EncryptGroupMessage {
+		status: "ok",
+		group: &group,
+		ciphertext: &BASE64.encode(ciphertext)
+	};
+	
+	let encrypt_group_message_json = match serde_json::to_string(&encrypt_group_message) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	encrypt_group_message_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_decryptGroupMsg<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	group: JString<'local>,
+	ciphertext: JByteArray<'local>
+) -> JString<'local> {
+	
+	let group = env.get_string(&group);
+	if group.is_err() { error!(env, "Could not get java variable: group"); }
+	let group: String = group.unwrap().into();
+	let mut group: Group = match serde_json::from_str(&group) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "group invalid"); }
+	};
+	
+	let ciphertext = env.convert_byte_array(ciphertext);
+	if ciphertext.is_err() { error!(env, "Could not get java variable: ciphertext"); }
+	let ciphertext = ciphertext.unwrap();
+	
+	let (sender_id, (msg_type, msg_text, msg_bytes)) = match group.decrypt(&ciphertext) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not decrypt group message: {}", err)); }
+	};
+	
+	let msg_text = msg_text.unwrap_or_default();
+	let msg_bytes = match msg_bytes {
+		Some(bytes) => BASE64.encode(bytes),
+		None => "".to_string()
+	};
+	
+	let decrypt_group_message = DecryptGroupMessage {
+		status: "ok",
+		group: &group,
+		sender_id: &sender_id,
+		msg_type,
+		msg_text: &msg_text,
+		msg_bytes: &msg_bytes
+	};
+	
+	let decrypt_group_message_json = match serde_json::to_string(&decrypt_group_message) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	decrypt_group_message_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn keys() -> (String, Vec<u8>) {
+		let (pubkey_sig, seckey_sig) = sign_keygen();
+		(encode(pubkey_sig), seckey_sig)
+	}
+	
+	fn sign(operation: &mut GroupOperation, seckey_sig: &[u8]) {
+		operation.signature = BASE64.encode(sign_detached(&operation_signed_data(operation).unwrap(), seckey_sig).unwrap());
+	}
+	
+	// A group of alice with bob added and then renamed, the state before these operations and bob's signature key
+	fn group() -> (Group, Group, Vec<GroupOperation>, Vec<u8>) {
+		let (alice_pubkey_sig, alice_seckey_sig) = keys();
+		let (bob_pubkey_sig, bob_seckey_sig) = keys();
+		let mut group = Group::new("group", "alice", &alice_pubkey_sig);
+		let initial = group.clone();
+		let (add, _) = group.sign_operation(OperationKind::Add { member_id: "bob".to_string(), pubkey_sig: bob_pubkey_sig }, &alice_seckey_sig).unwrap();
+		let (rename, _) = group.sign_operation(OperationKind::Rename { name: "renamed".to_string() }, &alice_seckey_sig).unwrap();
+		(group, initial, vec![add, rename], bob_seckey_sig)
+	}
+	
+	#[test]
+	fn operations_form_a_chain() {
+		let (group, initial, operations, _) = group();
+		assert!(initial.validate_operations(&operations).is_ok());
+		let mut replayed = initial;
+		for operation in &operations {
+			assert_eq!(operation.prev, replayed.op_head);
+			replayed.apply_operation(operation).unwrap();
+		}
+		assert_eq!((replayed.op_seq, replayed.op_head), (2, group.op_head));
+		assert_eq!(replayed.name, "renamed");
+		assert_eq!(replayed.members.len(), 1);
+	}
+	
+	#[test]
+	fn rejects_operations_out_of_order() {
+		let (group, initial, operations, _) = group();
+		assert_eq!(initial.validate_operations(&operations[1..]).unwrap_err().0, 0);
+		assert!(group.validate_operation(&operations[1]).is_err());
+		let duplicated = vec![operations[0].clone(), operations[0].clone()];
+		assert_eq!(initial.validate_operations(&duplicated).unwrap_err().0, 1);
+	}
+	
+	#[test]
+	fn rejects_tampered_operations() {
+		let (_, initial, operations, _) = group();
+		let mut tampered = operations[0].clone();
+		tampered.operation = OperationKind::Add { member_id: "mallory".to_string(), pubkey_sig: encode(b"mallory") };
+		assert!(initial.validate_operation(&tampered).is_err());
+		let mut other_group = operations[0].clone();
+		other_group.group_id = "other".to_string();
+		assert!(initial.validate_operation(&other_group).is_err());
+	}
+	
+	#[test]
+	fn only_admins_change_the_group() {
+		let (mut group, _, _, bob_seckey_sig) = group();
+		let mut operation = GroupOperation {
+			group_id: group.group_id.clone(),
+			seq: group.op_seq + 1,
+			prev: group.op_head.clone(),
+			actor: "bob".to_string(),
+			operation: OperationKind::Rename { name: "bob's".to_string() },
+			signature: "".to_string()
+		};
+		sign(&mut operation, &bob_seckey_sig);
+		assert!(group.validate_operation(&operation).is_err());
+		// leaving does not need an admin
+		operation.operation = OperationKind::Remove { member_id: "bob".to_string() };
+		sign(&mut operation, &bob_seckey_sig);
+		let (_, removed) = group.apply_operation(&operation).unwrap();
+		assert!(!removed);
+		assert!(group.members.is_empty());
+		assert_eq!(group.own_sender_key.epoch, 1);
+	}
+	
+	#[test]
+	fn sender_key_skips_and_reuses_keys() {
+		let mut sender = SenderKey::new(0);
+		let mut receiver = sender.clone();
+		let keys: Vec<Vec<u8>> = (0..3).map(|counter| sender.receive(counter).unwrap()).collect();
+		assert_eq!(receiver.receive(2).unwrap(), keys[2]);
+		assert_eq!(receiver.receive(0).unwrap(), keys[0]);
+		assert!(receiver.receive(0).is_err());
+		assert!(receiver.receive(2).is_err());
+		assert!(receiver.receive(3 + MAX_SKIP + 1).is_err());
+	}
+}
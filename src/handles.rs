@@ -21,9 +21,259 @@ use jni::JNIEnv;
 use jni::objects::{JByteArray, JClass, JString};
 use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
 use hex::{encode, decode};
-use crate::{Error, GenHandle, ParseHandle};
+use serde::{Serialize, Deserialize};
+use crate::{Error, GenHandle, GenHandleWithOptions, ParseHandle, HandleStoreResult};
+use crate::pow::MAX_POW_DIFFICULTY;
+use crate::features::SUPPORTED_FEATURES;
+use crate::signatures::{sign_detached, verify_detached, fingerprint, signed_data, split_signed_data};
 use crate::error;
 
+// Signed handles are the magic followed by the length prefixed handle, public signing key and signature
+const SIGNED_HANDLE_MAGIC: &[u8] = b"DWNH";
+
+// Expiry, one-time token, proof of work difficulty and the supported features are packed into the handle mdc, which senders pass through unchanged and parse_init_request
+// returns to the receiver. The packed values only tell senders what to expect, senders can change them at will. The receiver looks the
+// echoed mdc up in its HandleStore instead.
+const HANDLE_MDC_PREFIX: &str = "dawn-h1";
+const HANDLE_MDC_SEPARATOR: char = ':';
+const HANDLE_TOKEN_SIZE: usize = 16;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct HandleOptions {
+	// timestamp as returned by getCurrentTimestamp
+	pub expires: Option<String>,
+	pub one_time: bool,
+	// proof of work senders have to attach to init requests, in leading zero bits
+	pub pow_difficulty: Option<u8>,
+	// tells senders that the feature announcement in init requests can be parsed
+	pub announce_features: bool
+}
+
+pub struct HandleMdc {
+	pub mdc: String,
+	pub expires: Option<String>,
+	pub token: Option<String>,
+	pub pow_difficulty: Option<u8>,
+	pub features: Option<u32>
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleStatus {
+	Valid,
+	// not issued with genHandleWithOptions, e.g. plain handles or a tampered mdc
+	Unknown,
+	Expired,
+	Used,
+	Revoked
+}
+
+// Issued handle as remembered by the receiver, keyed by the complete mdc string put into the handle
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HandleRecord {
+	pub mdc: String,
+	#[serde(default)]
+	pub expires: Option<String>,
+	#[serde(default)]
+	pub one_time: bool,
+	#[serde(default)]
+	pub pow_difficulty: Option<u8>,
+	#[serde(default)]
+	pub used: bool,
+	#[serde(default)]
+	pub revoked: bool
+}
+
+// Handles issued with genHandleWithOptions. The store is kept by the app and passed in on every call, like the trust store.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HandleStore {
+	#[serde(default)]
+	pub handles: Vec<HandleRecord>
+}
+
+impl HandleStore {
+	pub fn get(&self, mdc: &str) -> Option<&HandleRecord> {
+		self.handles.iter().find(|record| record.mdc == mdc)
+	}
+	
+	pub fn status(&self, mdc: &str, now: u64) -> Result<HandleStatus, String> {
+		let record = match self.get(mdc) {
+			Some(record) => record,
+			None => return Ok(HandleStatus::Unknown)
+		};
+		if record.revoked { return Ok(HandleStatus::Revoked); }
+		if let Some(expires) = &record.expires {
+			if now > timestamp_value(expires)? { return Ok(HandleStatus::Expired); }
+		}
+		if record.one_time && record.used { return Ok(HandleStatus::Used); }
+		Ok(HandleStatus::Valid)
+	}
+	
+	// Called once a request to the handle was accepted, one-time handles are used up afterwards
+	pub fn mark_used(&mut self, mdc: &str) -> Result<(), String> {
+		match self.handles.iter_mut().find(|record| record.mdc == mdc) {
+			Some(record) => {
+				record.used = true;
+				Ok(())
+			},
+			None => Err("Unknown handle".to_string())
+		}
+	}
+	
+	pub fn revoke(&mut self, mdc: &str) -> Result<(), String> {
+		match self.handles.iter_mut().find(|record| record.mdc == mdc) {
+			Some(record) => {
+				record.revoked = true;
+				Ok(())
+			},
+			None => Err("Unknown handle".to_string())
+		}
+	}
+}
+
+pub fn parse_handle_store(store: &str) -> Result<HandleStore, String> {
+	match store {
+		"" => Ok(HandleStore::default()),
+		_ => serde_json::from_str(store).map_err(|_| "store invalid".to_string())
+	}
+}
+
+pub fn timestamp_value(timestamp: &str) -> Result<u64, String> {
+	match timestamp.parse::<u64>() {
+		Ok(value) => Ok(value),
+		Err(_) => Err(format!("Timestamp invalid: {}", timestamp))
+	}
+}
+
+pub fn handle_expired(expires: &str) -> Result<bool, String> {
+	Ok(timestamp_value(&get_current_timestamp()?)? > timestamp_value(expires)?)
+}
+
+pub fn encode_handle_mdc(mdc: &str, options: &HandleOptions) -> Result<HandleMdc, String> {
+	if mdc.contains(HANDLE_MDC_SEPARATOR) { return Err("mdc must not contain a colon".to_string()); }
+	if let Some(expires) = &options.expires {
+		if handle_expired(expires)? { return Err("Expiry lies in the past".to_string()); }
+	}
+	if let Some(difficulty) = options.pow_difficulty {
+		if difficulty > MAX_POW_DIFFICULTY { return Err(format!("Proof of work difficulty must not exceed {}", MAX_POW_DIFFICULTY)); }
+	}
+	let token = match options.one_time {
+		true => Some(encode(&sym_key_gen()[..HANDLE_TOKEN_SIZE])),
+		false => None
+	};
+	let features = match options.announce_features {
+		true => Some(SUPPORTED_FEATURES),
+		false => None
+	};
+	Ok(HandleMdc { mdc: mdc.to_string(), expires: options.expires.clone(), token, pow_difficulty: options.pow_difficulty, features })
+}
+
+impl HandleMdc {
+	pub fn to_mdc_string(&self) -> String {
+		if self.expires.is_none() && self.token.is_none() && self.pow_difficulty.is_none() && self.features.is_none() { return self.mdc.clone(); }
+		let mut mdc = format!("{}:{}:{}:{}", HANDLE_MDC_PREFIX, self.mdc, self.expires.as_deref().unwrap_or(""), self.token.as_deref().unwrap_or(""));
+		if self.pow_difficulty.is_some() || self.features.is_some() {
+			mdc.push_str(&format!(":{}", self.pow_difficulty.map(|difficulty| difficulty.to_string()).unwrap_or_default()));
+		}
+		if let Some(features) = self.features {
+			mdc.push_str(&format!(":{:08x}", features));
+		}
+		mdc
+	}
+	
+	// Plain mdcs of handles without expiry or token are returned as they are
+	pub fn from_mdc_string(mdc: &str) -> Result<HandleMdc, String> {
+		let parts: Vec<&str> = mdc.split(HANDLE_MDC_SEPARATOR).collect();
+		if parts[0] != HANDLE_MDC_PREFIX { return Ok(HandleMdc { mdc: mdc.to_string(), expires: None, token: None, pow_difficulty: None, features: None }); }
+		if parts.len() < 4 || parts.len() > 6 { return Err("Handle mdc malformed".to_string()); }
+		let optional = |part: &str| match part.is_empty() {
+			true => None,
+			false => Some(part.to_string())
+		};
+		let pow_difficulty = match parts.get(4).filter(|difficulty| !difficulty.is_empty()).map(|difficulty| difficulty.parse::<u8>()) {
+			Some(Ok(difficulty)) if difficulty <= MAX_POW_DIFFICULTY => Some(difficulty),
+			Some(_) => return Err("Handle proof of work difficulty invalid".to_string()),
+			None => None
+		};
+		let features = match parts.get(5).map(|features| u32::from_str_radix(features, 16)) {
+			Some(Ok(features)) => Some(features),
+			Some(Err(_)) => return Err("Handle features invalid".to_string()),
+			None => None
+		};
+		Ok(HandleMdc { mdc: parts[1].to_string(), expires: optional(parts[2]), token: optional(parts[3]), pow_difficulty, features })
+	}
+}
+
+pub struct OpenedHandle {
+	pub handle: Vec<u8>,
+	pub signer_pubkey_sig: Option<Vec<u8>>
+}
+
+// Contents of a handle as returned by parseHandle, parseScannedHandle and parseHandleUri
+#[derive(Serialize)]
+pub struct HandleInfo {
+	pub init_pk_kyber: String,
+	pub init_pk_curve: String,
+	pub init_pk_curve_pfs_2: String,
+	pub init_pk_kyber_for_salt: String,
+	pub init_pk_curve_for_salt: String,
+	pub name: String,
+	pub mdc: String,
+	// Only says that the handle carries a valid signature by signer_pubkey_sig, anyone can sign a handle with their own key,
+	// so the app has to compare signer_fingerprint with a key it trusts before treating the handle as verified
+	pub signed: bool,
+	pub signer_pubkey_sig: Option<String>,
+	pub signer_fingerprint: Option<String>
+}
+
+pub fn sign_handle(handle: &[u8], pubkey_sig: &[u8], seckey_sig: &[u8]) -> Result<Vec<u8>, String> {
+	if handle.starts_with(SIGNED_HANDLE_MAGIC) { return Err("Handle is already signed".to_string()); }
+	let data = signed_data(&[b"dawn-handle", handle]);
+	let signature = sign_detached(&data, seckey_sig)?;
+	// catches a public key that does not belong to the signing key before the handle is shared
+	verify_detached(&data, &signature, pubkey_sig)?;
+	let mut signed_handle = SIGNED_HANDLE_MAGIC.to_vec();
+	signed_handle.extend_from_slice(&signed_data(&[handle, pubkey_sig, &signature]));
+	Ok(signed_handle)
+}
+
+// Unsigned handles are passed through, a signed handle with an invalid signature is rejected instead of being treated as unsigned
+pub fn open_handle(handle: Vec<u8>) -> Result<OpenedHandle, String> {
+	let signed_handle = match handle.strip_prefix(SIGNED_HANDLE_MAGIC) {
+		Some(signed_handle) => signed_handle,
+		None => return Ok(OpenedHandle { handle, signer_pubkey_sig: None })
+	};
+	let mut fields = split_signed_data(signed_handle, 3)?.into_iter();
+	let (handle, pubkey_sig, signature) = match (fields.next(), fields.next(), fields.next()) {
+		(Some(handle), Some(pubkey_sig), Some(signature)) => (handle, pubkey_sig, signature),
+		_ => return Err("Signed handle malformed".to_string())
+	};
+	if let Err(error) = verify_detached(&signed_data(&[b"dawn-handle", &handle]), &signature, &pubkey_sig) { return Err(format!("Handle signature invalid: {}", error)); }
+	Ok(OpenedHandle { handle, signer_pubkey_sig: Some(pubkey_sig) })
+}
+
+// Opens a possibly signed handle and parses the handle inside
+pub fn handle_info(handle: Vec<u8>) -> Result<HandleInfo, String> {
+	let handle = open_handle(handle)?;
+	let (init_pubkey_kyber, init_pubkey_curve, init_pubkey_curve_pfs_2, init_pubkey_kyber_for_salt, init_pubkey_curve_for_salt, name, mdc) = match parse_handle(handle.handle) {
+		Ok(res) => res,
+		Err(err) => return Err(format!("Standard Library returned error: {}", err))
+	};
+	Ok(HandleInfo {
+		init_pk_kyber: encode(init_pubkey_kyber),
+		init_pk_curve: encode(init_pubkey_curve),
+		init_pk_curve_pfs_2: encode(init_pubkey_curve_pfs_2),
+		init_pk_kyber_for_salt: encode(init_pubkey_kyber_for_salt),
+		init_pk_curve_for_salt: encode(init_pubkey_curve_for_salt),
+		name,
+		mdc,
+		signed: handle.signer_pubkey_sig.is_some(),
+		signer_pubkey_sig: handle.signer_pubkey_sig.as_ref().map(encode),
+		signer_fingerprint: handle.signer_pubkey_sig.as_deref().map(fingerprint)
+	})
+}
+
 #[no_mangle]
 pub extern "C" fn Java_dawn_android_LibraryConnector_genHandle<'local> (
 	mut env: JNIEnv<'local>,
@@ -108,20 +358,14 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseHandle<'local> (
 	if handle.is_err() { error!(env, "Could not get java variable: handle"); }
 	let handle = handle.unwrap();
 	
-	let (init_pubkey_kyber, init_pubkey_curve, init_pubkey_curve_pfs_2, init_pubkey_kyber_for_salt, init_pubkey_curve_for_salt, name, mdc) = match parse_handle(handle) {
+	let info = match handle_info(handle) {
 		Ok(res) => res,
-		Err(err) => { error!(env, &format!("Standard Library returned error: {}", err)); }
+		Err(error) => { error!(env, &error); }
 	};
 	
 	let parse_handle = ParseHandle {
 		status: "ok",
-		init_pk_kyber: &encode(init_pubkey_kyber),
-		init_pk_curve: &encode(init_pubkey_curve),
-		init_pk_curve_pfs_2: &encode(init_pubkey_curve_pfs_2),
-		init_pk_kyber_for_salt: &encode(init_pubkey_kyber_for_salt),
-		init_pk_curve_for_salt: &encode(init_pubkey_curve_for_salt),
-		name: &name,
-		mdc: &mdc,
+		info: &info
 	};
 	
 	let parse_handle_json = match serde_json::to_string(&parse_handle) {
@@ -133,3 +377,342 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseHandle<'local> (
 	};
 	parse_handle_json
 }
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_signHandle<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	handle: JString<'local>,
+	own_pubkey_sig: JString<'local>,
+	own_seckey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let handle = env.get_string(&handle);
+	if handle.is_err() { error!(env, "Could not get java variable: handle"); }
+	let handle: String = handle.unwrap().into();
+	let handle = match BASE64.decode(handle) {
+		Ok(bytes) => bytes,
+		Err(_) => { error!(env, "handle invalid"); }
+	};
+	
+	let own_pubkey_sig = env.get_string(&own_pubkey_sig);
+	if own_pubkey_sig.is_err() { error!(env, "Could not get java variable: own_pubkey_sig"); }
+	let own_pubkey_sig: String = own_pubkey_sig.unwrap().into();
+	let own_pubkey_sig = match decode(own_pubkey_sig) {
+		Ok(bytes) => bytes,
+		Err(_) => { error!(env, "own_pubkey_sig invalid"); }
+	};
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(bytes) => bytes,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let signed_handle = match sign_handle(&handle, &own_pubkey_sig, &own_seckey_sig) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &format!("Could not sign handle: {}", error)); }
+	};
+	
+	let handle = GenHandle {
+		status: "ok",
+		handle: &BASE64.encode(signed_handle)
+	};
+	
+	let handle_json = match serde_json::to_string(&handle) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	handle_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_genHandleWithOptions<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	store: JString<'local>,
+	init_pubkey_kyber: JString<'local>,
+	init_pubkey_curve: JString<'local>,
+	init_pubkey_curve_pfs_2: JString<'local>,
+	init_pubkey_kyber_for_salt: JString<'local>,
+	init_pubkey_curve_for_salt: JString<'local>,
+	name: JString<'local>,
+	mdc: JString<'local>,
+	options: JString<'local>
+) -> JString<'local> {
+	
+	let store = env.get_string(&store);
+	if store.is_err() { error!(env, "Could not get java variable: store"); }
+	let store: String = store.unwrap().into();
+	let mut store = match parse_handle_store(&store) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let init_pubkey_kyber = env.get_string(&init_pubkey_kyber);
+	if init_pubkey_kyber.is_err() { error!(env, "Could not get java variable: init_pubkey_kyber"); }
+	let init_pubkey_kyber: String = init_pubkey_kyber.unwrap().into();
+	let init_pubkey_kyber = match decode(init_pubkey_kyber) {
+		Ok(bytes) => bytes,
+		Err(_) => { error!(env, "init_pubkey_kyber invalid"); }
+	};
+	
+	let init_pubkey_curve = env.get_string(&init_pubkey_curve);
+	if init_pubkey_curve.is_err() { error!(env, "Could not get java variable: init_pubkey_curve"); }
+	let init_pubkey_curve: String = init_pubkey_curve.unwrap().into();
+	let init_pubkey_curve = match decode(init_pubkey_curve) {
+		Ok(bytes) => bytes,
+		Err(_) => { error!(env, "init_pubkey_curve invalid"); }
+	};
+	let init_pubkey_curve_pfs_2 = env.get_string(&init_pubkey_curve_pfs_2);
+	if init_pubkey_curve_pfs_2.is_err() { error!(env, "Could not get java variable: init_pubkey_curve_pfs_2"); }
+	let init_pubkey_curve_pfs_2: String = init_pubkey_curve_pfs_2.unwrap().into();
+	let init_pubkey_curve_pfs_2 = match decode(init_pubkey_curve_pfs_2) {
+		Ok(bytes) => bytes,
+		Err(_) => { error!(env, "init_pubkey_curve_pfs_2 invalid"); }
+	};
+	let init_pubkey_kyber_for_salt = env.get_string(&init_pubkey_kyber_for_salt);
+	if init_pubkey_kyber_for_salt.is_err() { error!(env, "Could not get java variable: init_pubkey_kyber_for_salt"); }
+	let init_pubkey_kyber_for_salt: String = init_pubkey_kyber_for_salt.unwrap().into();
+	let init_pubkey_kyber_for_salt = match decode(init_pubkey_kyber_for_salt) {
+		Ok(bytes) => bytes,
+		Err(_) => { error!(env, "init_pubkey_kyber_for_salt invalid"); }
+	};
+	let init_pubkey_curve_for_salt = env.get_string(&init_pubkey_curve_for_salt);
+	if init_pubkey_curve_for_salt.is_err() { error!(env, "Could not get java variable: init_pubkey_curve_for_salt"); }
+	let init_pubkey_curve_for_salt: String = init_pubkey_curve_for_salt.unwrap().into();
+	let init_pubkey_curve_for_salt = match decode(init_pubkey_curve_for_salt) {
+		Ok(bytes) => bytes,
+		Err(_) => { error!(env, "init_pubkey_curve_for_salt invalid"); }
+	};
+	
+	let name = env.get_string(&name);
+	if name.is_err() { error!(env, "Could not get java variable: name"); }
+	let name: String = name.unwrap().into();
+	
+	let mdc = env.get_string(&mdc);
+	if mdc.is_err() { error!(env, "Could not get java variable: mdc"); }
+	let mdc: String = mdc.unwrap().into();
+	
+	let options = env.get_string(&options);
+	if options.is_err() { error!(env, "Could not get java variable: options"); }
+	let options: String = options.unwrap().into();
+	let options: HandleOptions = match options.as_str() {
+		"" => HandleOptions::default(),
+		_ => match serde_json::from_str(&options) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "options invalid"); }
+		}
+	};
+	
+	let handle_mdc = match encode_handle_mdc(&mdc, &options) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	let mdc_string = handle_mdc.to_mdc_string();
+	if store.get(&mdc_string).is_some() { error!(env, "A handle with this mdc was already issued"); }
+	store.handles.push(HandleRecord {
+		mdc: mdc_string.clone(),
+		expires: handle_mdc.expires.clone(),
+		one_time: options.one_time,
+		pow_difficulty: handle_mdc.pow_difficulty,
+		used: false,
+		revoked: false
+	});
+	
+	let handle = GenHandleWithOptions {
+		status: "ok",
+		handle: &BASE64.encode(gen_handle(&init_pubkey_kyber, &init_pubkey_curve, &init_pubkey_curve_pfs_2, &init_pubkey_kyber_for_salt, &init_pubkey_curve_for_salt, &name, &mdc_string)),
+		mdc: &mdc_string,
+		expires: handle_mdc.expires.as_deref(),
+		token: handle_mdc.token.as_deref(),
+		store: &store
+	};
+	
+	let handle_json = match serde_json::to_string(&handle) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	handle_json
+}
+
+// Marks a handle as used after a request to it was accepted, one-time handles report HandleStatus::Used afterwards
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_useHandle<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	store: JString<'local>,
+	mdc: JString<'local>
+) -> JString<'local> {
+	
+	let store = env.get_string(&store);
+	if store.is_err() { error!(env, "Could not get java variable: store"); }
+	let store: String = store.unwrap().into();
+	let mut store = match parse_handle_store(&store) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let mdc = env.get_string(&mdc);
+	if mdc.is_err() { error!(env, "Could not get java variable: mdc"); }
+	let mdc: String = mdc.unwrap().into();
+	
+	let now = match get_current_timestamp().and_then(|timestamp| timestamp_value(&timestamp)) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &format!("Could not get timestamp: {}", error)); }
+	};
+	let handle_status = match store.status(&mdc, now) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	if handle_status != HandleStatus::Valid { error!(env, &format!("Handle can not be used: {:?}", handle_status)); }
+	if let Err(error) = store.mark_used(&mdc) { error!(env, &error); }
+	
+	let handle_store_result = HandleStoreResult {
+		status: "ok",
+		handle_status: store.status(&mdc, now).unwrap_or(HandleStatus::Unknown),
+		store: &store
+	};
+	
+	let handle_store_result_json = match serde_json::to_string(&handle_store_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	handle_store_result_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_revokeHandle<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	store: JString<'local>,
+	mdc: JString<'local>
+) -> JString<'local> {
+	
+	let store = env.get_string(&store);
+	if store.is_err() { error!(env, "Could not get java variable: store"); }
+	let store: String = store.unwrap().into();
+	let mut store = match parse_handle_store(&store) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let mdc = env.get_string(&mdc);
+	if mdc.is_err() { error!(env, "Could not get java variable: mdc"); }
+	let mdc: String = mdc.unwrap().into();
+	
+	if let Err(error) = store.revoke(&mdc) { error!(env, &error); }
+	
+	let handle_store_result = HandleStoreResult {
+		status: "ok",
+		handle_status: HandleStatus::Revoked,
+		store: &store
+	};
+	
+	let handle_store_result_json = match serde_json::to_string(&handle_store_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	handle_store_result_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn signed_handle_roundtrip() {
+		let (pubkey_sig, seckey_sig) = sign_keygen();
+		let signed_handle = sign_handle(b"handle", &pubkey_sig, &seckey_sig).unwrap();
+		let opened = open_handle(signed_handle.clone()).unwrap();
+		assert_eq!(opened.handle, b"handle");
+		assert_eq!(opened.signer_pubkey_sig, Some(pubkey_sig));
+		assert!(sign_handle(&signed_handle, &opened.signer_pubkey_sig.unwrap(), &seckey_sig).is_err());
+		let mut tampered = signed_handle;
+		tampered[SIGNED_HANDLE_MAGIC.len() + 8] ^= 1;
+		assert!(open_handle(tampered).is_err());
+		assert_eq!(open_handle(b"handle".to_vec()).unwrap().signer_pubkey_sig, None);
+	}
+	
+	fn roundtrip(handle_mdc: &HandleMdc) -> HandleMdc {
+		HandleMdc::from_mdc_string(&handle_mdc.to_mdc_string()).unwrap()
+	}
+	
+	#[test]
+	fn mdc_string_roundtrip() {
+		let options = HandleOptions { expires: Some("2000000".to_string()), one_time: true, ..Default::default() };
+		let handle_mdc = encode_handle_mdc("mdc", &options).unwrap();
+		let parsed = roundtrip(&handle_mdc);
+		assert_eq!(parsed.mdc, "mdc");
+		assert_eq!(parsed.expires.as_deref(), Some("2000000"));
+		assert!(parsed.token.is_some());
+		assert_eq!(parsed.token, handle_mdc.token);
+	}
+	
+	#[test]
+	fn plain_mdc_passes_through() {
+		let handle_mdc = encode_handle_mdc("mdc", &HandleOptions::default()).unwrap();
+		assert_eq!(handle_mdc.to_mdc_string(), "mdc");
+		assert_eq!(HandleMdc::from_mdc_string("mdc").unwrap().token, None);
+	}
+	
+	#[test]
+	fn rejects_invalid_mdcs() {
+		assert!(encode_handle_mdc("m:dc", &HandleOptions::default()).is_err());
+		assert!(encode_handle_mdc("mdc", &HandleOptions { expires: Some("1".to_string()), ..Default::default() }).is_err());
+		for mdc in ["dawn-h1:mdc", "dawn-h1:mdc:::1:0:x"] {
+			assert!(HandleMdc::from_mdc_string(mdc).is_err());
+		}
+	}
+	
+	#[test]
+	fn store_status() {
+		let record = |mdc: &str, expires: Option<&str>, one_time| HandleRecord { mdc: mdc.to_string(), expires: expires.map(str::to_string), one_time, pow_difficulty: None, used: false, revoked: false };
+		let mut store = HandleStore { handles: vec![record("a", Some("10"), false), record("b", None, true)] };
+		assert_eq!(store.status("a", 10).unwrap(), HandleStatus::Valid);
+		assert_eq!(store.status("a", 11).unwrap(), HandleStatus::Expired);
+		assert_eq!(store.status("c", 0).unwrap(), HandleStatus::Unknown);
+		store.mark_used("a").unwrap();
+		store.mark_used("b").unwrap();
+		assert_eq!(store.status("a", 0).unwrap(), HandleStatus::Valid);
+		assert_eq!(store.status("b", 0).unwrap(), HandleStatus::Used);
+		store.revoke("a").unwrap();
+		assert_eq!(store.status("a", 0).unwrap(), HandleStatus::Revoked);
+		assert!(store.revoke("c").is_err());
+	}
+	
+	#[test]
+	fn mdc_carries_pow_difficulty() {
+		let handle_mdc = encode_handle_mdc("mdc", &HandleOptions { pow_difficulty: Some(8), ..Default::default() }).unwrap();
+		assert_eq!(handle_mdc.to_mdc_string(), "dawn-h1:mdc:::8");
+		assert_eq!(roundtrip(&handle_mdc).pow_difficulty, Some(8));
+		assert!(encode_handle_mdc("mdc", &HandleOptions { pow_difficulty: Some(MAX_POW_DIFFICULTY + 1), ..Default::default() }).is_err());
+		assert!(HandleMdc::from_mdc_string("dawn-h1:mdc:::33").is_err());
+	}
+	
+	#[test]
+	fn mdc_carries_features() {
+		let options = HandleOptions { pow_difficulty: Some(8), announce_features: true, ..Default::default() };
+		let parsed = roundtrip(&encode_handle_mdc("mdc", &options).unwrap());
+		assert_eq!((parsed.pow_difficulty, parsed.features), (Some(8), Some(SUPPORTED_FEATURES)));
+		// the difficulty is left empty when only the features follow
+		let handle_mdc = encode_handle_mdc("mdc", &HandleOptions { announce_features: true, ..Default::default() }).unwrap();
+		assert_eq!(handle_mdc.to_mdc_string(), format!("dawn-h1:mdc::::{:08x}", SUPPORTED_FEATURES));
+		assert_eq!(roundtrip(&handle_mdc).pow_difficulty, None);
+		assert!(HandleMdc::from_mdc_string("dawn-h1:mdc:::8:zz").is_err());
+	}
+}
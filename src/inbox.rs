@@ -0,0 +1,609 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JClass, JString};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use hex::{encode, decode};
+use serde::{Serialize, Deserialize};
+use crate::{Error, InboxResult, InboxList, InboxAccept, InboxReject};
+use crate::handles::{HandleStatus, parse_handle_store, timestamp_value};
+use crate::features::{FEATURE_ENVELOPE, announce_in_response};
+use crate::session::Session;
+use crate::init::{InitRejection, parse_init_request_options, open_init_request, check_handle_stamp, parse_opened_init_request};
+use crate::payload::Payload;
+use crate::signatures::fingerprint;
+use crate::envelope::{Kind, seal};
+use crate::error;
+
+pub const STATUS_DUPLICATE: &str = "duplicate";
+pub const STATUS_RATE_LIMITED: &str = "rate_limited";
+pub const STATUS_INVALID: &str = "invalid";
+// The request was sent to an expired, used up or revoked handle. It is still listed, so it can be rejected with a reason.
+pub const STATUS_HANDLE_REJECTED: &str = "handle_rejected";
+
+// All durations are given in timestamp units
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct InboxConfig {
+	pub max_per_sender: usize,
+	pub max_per_handle: usize,
+	pub rate_window: u64,
+	pub pending_ttl: u64,
+	pub max_history: usize
+}
+
+impl Default for InboxConfig {
+	fn default() -> Self {
+		InboxConfig { max_per_sender: 3, max_per_handle: 20, rate_window: 24, pending_ttl: 168, max_history: 5000 }
+	}
+}
+
+// Every received ciphertext, including ones that could not be parsed, so retransmissions are dropped before any decapsulation
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InboxRecord {
+	pub request_hash: String,
+	pub received: u64,
+	pub sender_fingerprint: Option<String>,
+	// mdc of the own handle record the request was sent to, none for unknown handles and unparseable requests
+	pub handle_mdc: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingInitRequest {
+	pub request_hash: String,
+	pub received: u64,
+	pub sender_fingerprint: String,
+	pub id: String,
+	pub id_salt: String,
+	pub mdc: String,
+	pub remote_pubkey_kyber: String,
+	pub remote_pubkey_sig: String,
+	pub own_pfs_key: String,
+	pub remote_pfs_key: String,
+	pub pfs_salt: String,
+	pub name: String,
+	pub comment: String,
+	pub mdc_seed: String,
+	pub handle_status: HandleStatus,
+	pub session: Session
+}
+
+// Kept by the app and passed in on every call, like the trust store
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct InitInbox {
+	#[serde(default)]
+	pub config: InboxConfig,
+	#[serde(default)]
+	pub history: Vec<InboxRecord>,
+	#[serde(default)]
+	pub pending: Vec<PendingInitRequest>
+}
+
+impl InitInbox {
+	pub fn prune(&mut self, now: u64) {
+		let history_ttl = self.config.rate_window.max(self.config.pending_ttl);
+		self.history.retain(|record| record.received.saturating_add(history_ttl) >= now);
+		let pending_ttl = self.config.pending_ttl;
+		self.pending.retain(|entry| entry.received.saturating_add(pending_ttl) >= now);
+	}
+	
+	pub fn is_duplicate(&self, request_hash: &str) -> bool {
+		self.history.iter().any(|record| record.request_hash == request_hash)
+	}
+	
+	// Counts earlier requests inside the rate window, so it has to be called before the new request is recorded.
+	// handle_mdc is the mdc of the own handle record, requests to unknown handles share one limit.
+	pub fn is_rate_limited(&self, sender_fingerprint: &str, handle_mdc: Option<&str>, now: u64) -> bool {
+		let recent = self.history.iter().filter(|record| record.received.saturating_add(self.config.rate_window) >= now);
+		let (mut from_sender, mut to_handle) = (0, 0);
+		for record in recent {
+			// unparseable requests have no sender and must not use up the limit of a handle
+			if record.sender_fingerprint.is_none() { continue; }
+			if record.sender_fingerprint.as_deref() == Some(sender_fingerprint) { from_sender += 1; }
+			if record.handle_mdc.as_deref() == handle_mdc { to_handle += 1; }
+		}
+		from_sender >= self.config.max_per_sender || to_handle >= self.config.max_per_handle
+	}
+	
+	// Keeps the history bounded, unparseable requests are dropped first and the oldest records before newer ones
+	pub fn record(&mut self, record: InboxRecord) {
+		self.history.push(record);
+		while self.history.len() > self.config.max_history.max(1) {
+			let index = self.history.iter().position(|record| record.sender_fingerprint.is_none()).unwrap_or(0);
+			self.history.remove(index);
+		}
+	}
+	
+	pub fn take(&mut self, request_hash: &str) -> Result<PendingInitRequest, String> {
+		match self.pending.iter().position(|entry| entry.request_hash == request_hash) {
+			Some(index) => Ok(self.pending.remove(index)),
+			None => Err("Unknown pending init request".to_string())
+		}
+	}
+}
+
+fn parse_inbox(inbox: &str) -> Result<InitInbox, String> {
+	match inbox {
+		"" => Ok(InitInbox::default()),
+		_ => serde_json::from_str(inbox).map_err(|_| "inbox invalid".to_string())
+	}
+}
+
+fn current_timestamp() -> Result<u64, String> {
+	timestamp_value(&get_current_timestamp()?)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_inboxAddInitRequest<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	inbox: JString<'local>,
+	handles: JString<'local>,
+	options: JString<'local>,
+	ciphertext: JByteArray<'local>,
+	own_seckey_kyber: JString<'local>,
+	own_seckey_curve: JString<'local>,
+	own_seckey_curve_pfs_2: JString<'local>,
+	own_seckey_kyber_for_salt: JString<'local>,
+	own_seckey_curve_for_salt: JString<'local>
+) -> JString<'local> {
+	
+	let inbox = env.get_string(&inbox);
+	if inbox.is_err() { error!(env, "Could not get java variable: inbox"); }
+	let inbox: String = inbox.unwrap().into();
+	let mut inbox = match parse_inbox(&inbox) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let handles = env.get_string(&handles);
+	if handles.is_err() { error!(env, "Could not get java variable: handles"); }
+	let handles: String = handles.unwrap().into();
+	let handles = match parse_handle_store(&handles) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	// The same options as parseInitRequestWithOptions, handles given there are ignored in favour of the store above
+	let options = env.get_string(&options);
+	if options.is_err() { error!(env, "Could not get java variable: options"); }
+	let options: String = options.unwrap().into();
+	let options = match parse_init_request_options(&options) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let ciphertext = env.convert_byte_array(ciphertext);
+	if ciphertext.is_err() { error!(env, "Could not get java variable: ciphertext"); }
+	let ciphertext = ciphertext.unwrap();
+	
+	let own_seckey_kyber = env.get_string(&own_seckey_kyber);
+	if own_seckey_kyber.is_err() { error!(env, "Could not get java variable: own_seckey_kyber"); }
+	let own_seckey_kyber: String = own_seckey_kyber.unwrap().into();
+	let own_seckey_kyber = match decode(own_seckey_kyber) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_kyber invalid"); }
+	};
+	
+	let own_seckey_curve = env.get_string(&own_seckey_curve);
+	if own_seckey_curve.is_err() { error!(env, "Could not get java variable: own_seckey_curve"); }
+	let own_seckey_curve: String = own_seckey_curve.unwrap().into();
+	let own_seckey_curve = match decode(own_seckey_curve) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_curve invalid"); }
+	};
+	
+	let own_seckey_curve_pfs_2 = env.get_string(&own_seckey_curve_pfs_2);
+	if own_seckey_curve_pfs_2.is_err() { error!(env, "Could not get java variable: own_seckey_curve_pfs_2"); }
+	let own_seckey_curve_pfs_2: String = own_seckey_curve_pfs_2.unwrap().into();
+	let own_seckey_curve_pfs_2 = match decode(own_seckey_curve_pfs_2) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_curve_pfs_2 invalid"); }
+	};
+	
+	let own_seckey_kyber_for_salt = env.get_string(&own_seckey_kyber_for_salt);
+	if own_seckey_kyber_for_salt.is_err() { error!(env, "Could not get java variable: own_seckey_kyber_for_salt"); }
+	let own_seckey_kyber_for_salt: String = own_seckey_kyber_for_salt.unwrap().into();
+	let own_seckey_kyber_for_salt = match decode(own_seckey_kyber_for_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_kyber_for_salt invalid"); }
+	};
+	
+	let own_seckey_curve_for_salt = env.get_string(&own_seckey_curve_for_salt);
+	if own_seckey_curve_for_salt.is_err() { error!(env, "Could not get java variable: own_seckey_curve_for_salt"); }
+	let own_seckey_curve_for_salt: String = own_seckey_curve_for_salt.unwrap().into();
+	let own_seckey_curve_for_salt = match decode(own_seckey_curve_for_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_curve_for_salt invalid"); }
+	};
+	
+	let now = match current_timestamp() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not get timestamp: {}", err)); }
+	};
+	inbox.prune(now);
+	
+	// The stamp is not part of the hash, so the same request with a recomputed stamp is still a duplicate.
+	// The minimum difficulty of the options is checked right away, the one of the addressed handle once the request is parsed.
+	let (envelope, stamp, ciphertext) = match open_init_request(&ciphertext, &options) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let request_hash = encode(hash(ciphertext));
+	
+	let (status, entry) = if inbox.is_duplicate(&request_hash) {
+		(STATUS_DUPLICATE, None)
+	}
+	else {
+		let request = parse_opened_init_request(envelope.as_ref(), ciphertext, &own_seckey_kyber, &own_seckey_curve, &own_seckey_curve_pfs_2, &own_seckey_kyber_for_salt, &own_seckey_curve_for_salt)
+			.and_then(|request| check_handle_stamp(stamp.as_ref(), ciphertext, &options, &handles, &request.mdc).map(|_| request));
+		match request {
+			Ok(request) => {
+				// the mdc is echoed by the sender, only the own records say which handle it belongs to and whether it is still valid
+				let handle_status = match handles.status(&request.mdc, now) {
+					Ok(res) => res,
+					Err(err) => { error!(env, &format!("Could not check handle: {}", err)); }
+				};
+				let handle_mdc = handles.get(&request.mdc).map(|record| record.mdc.clone());
+				let sender_fingerprint = fingerprint(&request.remote_pubkey_sig);
+				let rate_limited = inbox.is_rate_limited(&sender_fingerprint, handle_mdc.as_deref(), now);
+				inbox.record(InboxRecord { request_hash: request_hash.clone(), received: now, sender_fingerprint: Some(sender_fingerprint.clone()), handle_mdc });
+				match rate_limited {
+					true => (STATUS_RATE_LIMITED, None),
+					false => {
+						inbox.pending.push(PendingInitRequest {
+							request_hash,
+							received: now,
+							sender_fingerprint,
+							id: request.id,
+							id_salt: encode(request.id_salt),
+							mdc: request.mdc,
+							remote_pubkey_kyber: encode(request.remote_pubkey_kyber),
+							remote_pubkey_sig: encode(request.remote_pubkey_sig),
+							own_pfs_key: encode(request.own_pfs_key),
+							remote_pfs_key: encode(request.remote_pfs_key),
+							pfs_salt: encode(request.pfs_salt),
+							name: request.name,
+							comment: request.comment,
+							mdc_seed: request.mdc_seed,
+							handle_status,
+							session: Session::new(request.announcement)
+						});
+						let status = match handle_status {
+							HandleStatus::Valid | HandleStatus::Unknown => "ok",
+							_ => STATUS_HANDLE_REJECTED
+						};
+						(status, inbox.pending.last())
+					}
+				}
+			},
+			// Not an error, the record has to be stored so the ciphertext is dropped cheaply next time. This includes requests missing the stamp their handle requires.
+			Err(_) => {
+				inbox.record(InboxRecord { request_hash, received: now, sender_fingerprint: None, handle_mdc: None });
+				(STATUS_INVALID, None)
+			}
+		}
+	};
+	
+	let inbox_result = InboxResult {
+		status,
+		entry,
+		inbox: &inbox
+	};
+	
+	let inbox_result_json = match serde_json::to_string(&inbox_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	inbox_result_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_inboxList<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	inbox: JString<'local>
+) -> JString<'local> {
+	
+	let inbox = env.get_string(&inbox);
+	if inbox.is_err() { error!(env, "Could not get java variable: inbox"); }
+	let inbox: String = inbox.unwrap().into();
+	let mut inbox = match parse_inbox(&inbox) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let now = match current_timestamp() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not get timestamp: {}", err)); }
+	};
+	inbox.prune(now);
+	
+	let inbox_list = InboxList {
+		status: "ok",
+		pending: &inbox.pending,
+		inbox: &inbox
+	};
+	
+	let inbox_list_json = match serde_json::to_string(&inbox_list) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	inbox_list_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_inboxAccept<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	inbox: JString<'local>,
+	handles: JString<'local>,
+	request_hash: JString<'local>,
+	own_seckey_sig: JString<'local>,
+	own_pubkey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let inbox = env.get_string(&inbox);
+	if inbox.is_err() { error!(env, "Could not get java variable: inbox"); }
+	let inbox: String = inbox.unwrap().into();
+	let mut inbox = match parse_inbox(&inbox) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let handles = env.get_string(&handles);
+	if handles.is_err() { error!(env, "Could not get java variable: handles"); }
+	let handles: String = handles.unwrap().into();
+	let mut handles = match parse_handle_store(&handles) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let request_hash = env.get_string(&request_hash);
+	if request_hash.is_err() { error!(env, "Could not get java variable: request_hash"); }
+	let request_hash: String = request_hash.unwrap().into();
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let own_pubkey_sig = env.get_string(&own_pubkey_sig);
+	if own_pubkey_sig.is_err() { error!(env, "Could not get java variable: own_pubkey_sig"); }
+	let own_pubkey_sig: String = own_pubkey_sig.unwrap().into();
+	let own_pubkey_sig = match decode(own_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_pubkey_sig invalid"); }
+	};
+	
+	let entry = match inbox.take(&request_hash) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	// checked again, the handle may have expired or been used up while the request was pending
+	let now = match current_timestamp() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not get timestamp: {}", err)); }
+	};
+	match handles.status(&entry.mdc, now) {
+		Ok(HandleStatus::Valid) => {
+			if let Err(err) = handles.mark_used(&entry.mdc) { error!(env, &err); }
+		},
+		Ok(HandleStatus::Unknown) => (),
+		Ok(handle_status) => { error!(env, &format!("Handle can not be used: {:?}", handle_status)); }
+		Err(err) => { error!(env, &format!("Could not check handle: {}", err)); }
+	}
+	
+	let (remote_pubkey_kyber, pfs_key, pfs_salt) = match (decode(&entry.remote_pubkey_kyber), decode(&entry.own_pfs_key), decode(&entry.pfs_salt)) {
+		(Ok(remote_pubkey_kyber), Ok(pfs_key), Ok(pfs_salt)) => (remote_pubkey_kyber, pfs_key, pfs_salt),
+		_ => { error!(env, "inbox entry invalid"); }
+	};
+	
+	let (new_pfs_key, (own_pubkey_kyber, own_seckey_kyber), mdc, ciphertext) = match accept_init_request(&own_pubkey_sig, &own_seckey_sig, &remote_pubkey_kyber, &pfs_key, &pfs_salt, &entry.id, &entry.mdc_seed) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not accept init request: {}", err)); }
+	};
+	
+	// Only announce to peers that announced themselves, old clients can not parse the announcement
+	let ciphertext = match entry.session.peer_version {
+		Some(_) => match announce_in_response(&ciphertext, &own_seckey_sig) {
+			Ok(res) => res,
+			Err(err) => { error!(env, &format!("Could not announce features: {}", err)); }
+		},
+		None => ciphertext
+	};
+	let ciphertext = match entry.session.supports(FEATURE_ENVELOPE) {
+		true => seal(Kind::InitResponse, &ciphertext, &pfs_salt),
+		false => ciphertext
+	};
+	
+	let inbox_accept = InboxAccept {
+		status: "ok",
+		entry: &entry,
+		new_pfs_key: &encode(new_pfs_key),
+		own_pubkey_kyber: &encode(own_pubkey_kyber),
+		own_seckey_kyber: &encode(own_seckey_kyber),
+		mdc: &mdc,
+		ciphertext: &BASE64.encode(ciphertext),
+		session: &entry.session,
+		inbox: &inbox,
+		handles: &handles
+	};
+	
+	let inbox_accept_json = match serde_json::to_string(&inbox_accept) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	inbox_accept_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_inboxReject<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	inbox: JString<'local>,
+	request_hash: JString<'local>,
+	own_seckey_sig: JString<'local>,
+	rejection: JString<'local>
+) -> JString<'local> {
+	
+	let inbox = env.get_string(&inbox);
+	if inbox.is_err() { error!(env, "Could not get java variable: inbox"); }
+	let inbox: String = inbox.unwrap().into();
+	let mut inbox = match parse_inbox(&inbox) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let request_hash = env.get_string(&request_hash);
+	if request_hash.is_err() { error!(env, "Could not get java variable: request_hash"); }
+	let request_hash: String = request_hash.unwrap().into();
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	// "" drops the entry silently
+	let rejection = env.get_string(&rejection);
+	if rejection.is_err() { error!(env, "Could not get java variable: rejection"); }
+	let rejection: String = rejection.unwrap().into();
+	let rejection: Option<InitRejection> = match rejection.as_str() {
+		"" => None,
+		_ => match serde_json::from_str(&rejection) {
+			Ok(res) => Some(res),
+			Err(_) => { error!(env, "rejection invalid"); }
+		}
+	};
+	
+	let entry = match inbox.take(&request_hash) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let message = match rejection {
+		Some(rejection) => {
+			let (remote_pubkey_kyber, pfs_key, pfs_salt) = match (decode(&entry.remote_pubkey_kyber), decode(&entry.own_pfs_key), decode(&entry.pfs_salt)) {
+				(Ok(remote_pubkey_kyber), Ok(pfs_key), Ok(pfs_salt)) => (remote_pubkey_kyber, pfs_key, pfs_salt),
+				_ => { error!(env, "inbox entry invalid"); }
+			};
+			let (msg_type, msg_text, msg_bytes) = match Payload::InitRejection(rejection).encode() {
+				Ok(res) => res,
+				Err(err) => { error!(env, &err); }
+			};
+			let (mdc, ciphertext) = match send_msg((msg_type, msg_text.as_deref(), msg_bytes.as_deref()), &remote_pubkey_kyber, Some(&own_seckey_sig), &pfs_key, &pfs_salt, &entry.id, &entry.mdc_seed) {
+				Ok((_, mdc, ciphertext)) => (mdc, ciphertext),
+				Err(err) => { error!(env, &err); }
+			};
+			let ciphertext = match entry.session.supports(FEATURE_ENVELOPE) {
+				true => seal(Kind::InitResponse, &ciphertext, &pfs_salt),
+				false => ciphertext
+			};
+			Some((mdc, BASE64.encode(ciphertext)))
+		},
+		None => None
+	};
+	
+	let inbox_reject = InboxReject {
+		status: "ok",
+		entry: &entry,
+		mdc: message.as_ref().map(|(mdc, _)| mdc.as_str()),
+		ciphertext: message.as_ref().map(|(_, ciphertext)| ciphertext.as_str()),
+		inbox: &inbox
+	};
+	
+	let inbox_reject_json = match serde_json::to_string(&inbox_reject) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	inbox_reject_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn record(request_hash: &str, received: u64, sender_fingerprint: Option<&str>, handle_mdc: Option<&str>) -> InboxRecord {
+		InboxRecord { request_hash: request_hash.to_string(), received, sender_fingerprint: sender_fingerprint.map(str::to_string), handle_mdc: handle_mdc.map(str::to_string) }
+	}
+	
+	#[test]
+	fn limits_requests_per_sender_and_handle() {
+		let mut inbox = InitInbox { config: InboxConfig { max_per_sender: 2, max_per_handle: 3, ..Default::default() }, ..Default::default() };
+		inbox.record(record("1", 0, Some("alice"), Some("h")));
+		assert!(!inbox.is_rate_limited("alice", Some("h"), 0));
+		inbox.record(record("2", 0, Some("alice"), Some("h")));
+		assert!(inbox.is_rate_limited("alice", Some("h"), 0));
+		assert!(!inbox.is_rate_limited("bob", Some("h"), 0));
+		inbox.record(record("3", 0, Some("bob"), Some("h")));
+		assert!(inbox.is_rate_limited("carol", Some("h"), 0));
+		assert!(!inbox.is_rate_limited("carol", None, 0));
+		// unparseable requests count for neither
+		inbox.record(record("4", 0, None, None));
+		inbox.record(record("5", 0, None, None));
+		assert!(!inbox.is_rate_limited("carol", None, 0));
+		// the window moves on
+		assert!(!inbox.is_rate_limited("alice", Some("h"), inbox.config.rate_window + 1));
+		assert!(inbox.is_duplicate("4"));
+	}
+	
+	#[test]
+	fn history_drops_unparseable_requests_first() {
+		let mut inbox = InitInbox { config: InboxConfig { max_history: 2, ..Default::default() }, ..Default::default() };
+		inbox.record(record("1", 0, Some("alice"), None));
+		inbox.record(record("2", 0, None, None));
+		inbox.record(record("3", 0, Some("bob"), None));
+		assert!(!inbox.is_duplicate("2"));
+		inbox.record(record("4", 0, Some("carol"), None));
+		assert!(!inbox.is_duplicate("1"));
+		assert!(inbox.is_duplicate("3") && inbox.is_duplicate("4"));
+	}
+	
+	#[test]
+	fn prune_drops_old_entries() {
+		let mut inbox = InitInbox::default();
+		inbox.record(record("1", 0, Some("alice"), None));
+		inbox.record(record("2", 10, Some("alice"), None));
+		inbox.prune(inbox.config.pending_ttl + 1);
+		assert!(!inbox.is_duplicate("1"));
+		assert!(inbox.is_duplicate("2"));
+		assert!(inbox.take("2").is_err());
+	}
+}
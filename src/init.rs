@@ -21,9 +21,57 @@ use jni::JNIEnv;
 use jni::objects::{JByteArray, JClass, JString};
 use hex::{encode, decode};
 use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
-use crate::{Error, GenInitRequest, ParseInitRequest, AcceptInitRequest, ParseInitResponse};
+use serde::{Serialize, Deserialize};
+use crate::{Error, GenInitRequest, ParseInitRequest, AcceptInitRequest, ParseInitResponse, SendMessage, ParseInitRejection};
+use crate::handles::{HandleMdc, HandleStore, timestamp_value};
+use crate::payload::Payload;
+use crate::pow::{Stamp, add_stamp, split_stamp, verify_stamp};
+use crate::features::{FEATURE_ENVELOPE, Announcement, announce_in_comment, split_comment, announce_in_response, split_response, verify_response_announcement};
+use crate::envelope::{Kind, Unverified, seal, open_unverified, open_expecting};
+use crate::session::{Session, parse_session};
 use crate::error;
 
+pub const STATUS_REJECTED: &str = "rejected";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct InitRequestOptions {
+	// init public key of the handle the request was sent to, hex encoded
+	own_pubkey_kyber: Option<String>,
+	// minimum proof of work in leading zero bits for every request, 0 accepts requests without a stamp unless the handle requires one
+	pow_difficulty: u8,
+	// in timestamp units
+	pow_max_age: Option<u64>,
+	// handles issued with genHandleWithOptions, the status of the handle the request was sent to is only reported
+	// and the proof of work difficulty it was issued with only enforced if given
+	handles: Option<HandleStore>
+}
+
+pub fn parse_init_request_options(options: &str) -> Result<InitRequestOptions, String> {
+	match options {
+		"" => Ok(InitRequestOptions::default()),
+		_ => serde_json::from_str(options).map_err(|_| "options invalid".to_string())
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+	Declined,
+	HandleExpired,
+	HandleUsed,
+	Spam
+}
+
+// Sent instead of an init response. It is encrypted with the pfs key of the init request, which only the owner of the handle can derive.
+// block asks the sender's client to stop retrying and not to send further init requests to this handle.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct InitRejection {
+	pub reason: Option<RejectReason>,
+	pub block: bool
+}
+
 #[no_mangle]
 pub extern "C" fn Java_dawn_android_LibraryConnector_genInitRequest<'local> (
 	mut env: JNIEnv<'local>,
@@ -108,11 +156,34 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_genInitRequest<'local> (
 	if mdc.is_err() { error!(env, "Could not get java variable: mdc"); }
 	let mdc: String = mdc.unwrap().into();
 	
+	// Handles can ask for a proof of work in their mdc and advertise that their owner parses the feature announcement
+	let handle_mdc = match HandleMdc::from_mdc_string(&mdc) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "mdc invalid"); }
+	};
+	let pow_difficulty = handle_mdc.pow_difficulty;
+	let comment = match handle_mdc.features {
+		Some(_) => announce_in_comment(&comment),
+		None => comment
+	};
+	
 	let ((own_pubkey_kyber, own_seckey_kyber), (own_pubkey_curve, own_seckey_curve), own_pfs_key, remote_pfs_key, pfs_salt, id, id_salt, mdc, mdc_seed, ciphertext) = match gen_init_request(&remote_pubkey_kyber, &remote_pubkey_kyber_for_salt, &remote_pubkey_curve, &remote_pubkey_curve_pfs_2, &remote_pubkey_curve_for_salt, &own_pubkey_sig, &own_seckey_sig, &name, &comment, &mdc) {
 		Ok(res) => res,
 		Err(err) => { error!(env, &format!("Could not generate init request: {}", err)); }
 	};
 	
+	let ciphertext = match pow_difficulty {
+		Some(difficulty) => match add_stamp(&ciphertext, &remote_pubkey_kyber, difficulty) {
+			Ok(res) => res,
+			Err(err) => { error!(env, &format!("Could not compute proof of work: {}", err)); }
+		},
+		None => ciphertext
+	};
+	let ciphertext = match handle_mdc.features {
+		Some(features) if features & FEATURE_ENVELOPE != 0 => seal(Kind::InitRequest, &ciphertext, &pfs_salt),
+		_ => ciphertext
+	};
+	
 	let gen_init_request = GenInitRequest {
 		status: "ok",
 		own_pubkey_kyber: &encode(own_pubkey_kyber),
@@ -139,22 +210,107 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_genInitRequest<'local> (
 	gen_init_request_json
 }
 
-#[no_mangle]
-pub extern "C" fn Java_dawn_android_LibraryConnector_parseInitRequest<'local> (
+// Init request as parsed by parseInitRequest, parseInitRequestWithOptions and the inbox
+pub struct ParsedInitRequest {
+	pub id: String,
+	pub id_salt: Vec<u8>,
+	// as echoed by the sender, only meaningful after looking it up in the HandleStore
+	pub mdc: String,
+	pub remote_pubkey_kyber: Vec<u8>,
+	pub remote_pubkey_sig: Vec<u8>,
+	pub own_pfs_key: Vec<u8>,
+	pub remote_pfs_key: Vec<u8>,
+	pub pfs_salt: Vec<u8>,
+	pub name: String,
+	// without the feature announcement
+	pub comment: String,
+	pub mdc_seed: String,
+	pub announcement: Option<Announcement>
+}
+
+// Envelope, stamp and the ciphertext inside them
+pub type OpenedInitRequest<'a> = (Option<Unverified<'a>>, Option<Stamp>, &'a [u8]);
+
+// Removes envelope and stamp. The stamp is checked before any kyber decapsulation, so unstamped floods are cheap to drop.
+// The envelope tag can only be checked by parse_opened_init_request, which learns the pfs_salt.
+// The stamp is returned for check_handle_stamp, which needs the mdc inside the request.
+pub fn open_init_request<'a>(ciphertext: &'a [u8], options: &InitRequestOptions) -> Result<OpenedInitRequest<'a>, String> {
+	let (envelope, ciphertext) = open_unverified(ciphertext, Kind::InitRequest)?;
+	let (stamp, ciphertext) = split_stamp(ciphertext);
+	check_stamp(stamp.as_ref(), ciphertext, options, options.pow_difficulty)?;
+	Ok((envelope, stamp, ciphertext))
+}
+
+fn check_stamp(stamp: Option<&Stamp>, ciphertext: &[u8], options: &InitRequestOptions, difficulty: u8) -> Result<(), String> {
+	if difficulty == 0 { return Ok(()); }
+	let stamp = match stamp {
+		Some(stamp) => stamp,
+		None => return Err("Proof of work missing".to_string())
+	};
+	let own_pubkey_kyber = match options.own_pubkey_kyber.as_deref().map(decode) {
+		Some(Ok(res)) => res,
+		_ => return Err("own_pubkey_kyber invalid".to_string())
+	};
+	verify_stamp(stamp, ciphertext, &own_pubkey_kyber, difficulty, options.pow_max_age)
+}
+
+// A handle issued with a proof of work difficulty only accepts requests stamped with at least that difficulty.
+// Which handle a request was sent to is only known once it is parsed, so this is checked in addition to open_init_request.
+pub fn check_handle_stamp(stamp: Option<&Stamp>, ciphertext: &[u8], options: &InitRequestOptions, handles: &HandleStore, mdc: &str) -> Result<(), String> {
+	let difficulty = handles.get(mdc).and_then(|record| record.pow_difficulty).unwrap_or(0);
+	// already checked by open_init_request
+	if difficulty <= options.pow_difficulty { return Ok(()); }
+	check_stamp(stamp, ciphertext, options, difficulty)
+}
+
+// Parses a ciphertext returned by open_init_request
+#[allow(clippy::too_many_arguments)]
+pub fn parse_opened_init_request(envelope: Option<&Unverified>, ciphertext: &[u8], own_seckey_kyber: &[u8], own_seckey_curve: &[u8], own_seckey_curve_pfs_2: &[u8], own_seckey_kyber_for_salt: &[u8], own_seckey_curve_for_salt: &[u8]) -> Result<ParsedInitRequest, String> {
+	let (id, id_salt, mdc, remote_pubkey_kyber, remote_pubkey_sig, own_pfs_key, remote_pfs_key, pfs_salt, name, comment, mdc_seed) = match parse_init_request(ciphertext, own_seckey_kyber, own_seckey_curve, own_seckey_curve_pfs_2, own_seckey_kyber_for_salt, own_seckey_curve_for_salt) {
+		Ok(res) => res,
+		Err(err) => return Err(format!("Could not parse init request: {}", err))
+	};
+	if let Some(envelope) = envelope { envelope.verify(&pfs_salt)?; }
+	let (announcement, comment) = split_comment(&comment);
+	Ok(ParsedInitRequest { id, id_salt, mdc, remote_pubkey_kyber, remote_pubkey_sig, own_pfs_key, remote_pfs_key, pfs_salt, name, comment, mdc_seed, announcement })
+}
+
+// Shared by parseInitRequest and parseInitRequestWithOptions, parseInitRequest uses the default options
+#[allow(clippy::too_many_arguments)]
+fn parse_init_request_with_options<'local> (
 	mut env: JNIEnv<'local>,
-	_class: JClass<'local>,
 	ciphertext: JByteArray<'local>,
 	own_seckey_kyber: JString<'local>,
 	own_seckey_curve: JString<'local>,
 	own_seckey_curve_pfs_2: JString<'local>,
 	own_seckey_kyber_for_salt: JString<'local>,
-	own_seckey_curve_for_salt: JString<'local>
+	own_seckey_curve_for_salt: JString<'local>,
+	options: Option<JString<'local>>
 ) -> JString<'local> {
 	
 	let ciphertext = env.convert_byte_array(ciphertext);
 	if ciphertext.is_err() { error!(env, "Could not get java variable: ciphertext"); }
 	let ciphertext = ciphertext.unwrap();
 	
+	// Without options stamps are not checked, use parseInitRequestWithOptions to require them
+	let options: InitRequestOptions = match options {
+		Some(options) => {
+			let options = env.get_string(&options);
+			if options.is_err() { error!(env, "Could not get java variable: options"); }
+			let options: String = options.unwrap().into();
+			match parse_init_request_options(&options) {
+				Ok(res) => res,
+				Err(err) => { error!(env, &err); }
+			}
+		},
+		None => InitRequestOptions::default()
+	};
+	
+	let (envelope, stamp, ciphertext) = match open_init_request(&ciphertext, &options) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
 	let own_seckey_kyber = env.get_string(&own_seckey_kyber);
 	if own_seckey_kyber.is_err() { error!(env, "Could not get java variable: own_seckey_kyber"); }
 	let own_seckey_kyber: String = own_seckey_kyber.unwrap().into();
@@ -195,24 +351,39 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseInitRequest<'local> (
 		Err(_) => { error!(env, "own_seckey_curve_for_salt invalid"); }
 	};
 	
-	let (id, id_salt, mdc, remote_pubkey_kyber, remote_pubkey_sig, own_pfs_key, remote_pfs_key, pfs_salt, name, comment, mdc_seed) = match parse_init_request(&ciphertext, &own_seckey_kyber, &own_seckey_curve, &own_seckey_curve_pfs_2, &own_seckey_kyber_for_salt, &own_seckey_curve_for_salt) {
+	let request = match parse_opened_init_request(envelope.as_ref(), ciphertext, &own_seckey_kyber, &own_seckey_curve, &own_seckey_curve_pfs_2, &own_seckey_kyber_for_salt, &own_seckey_curve_for_salt) {
 		Ok(res) => res,
-		Err(err) => { error!(env, &format!("Could not parse init request: {}", err)); }
+		Err(err) => { error!(env, &err); }
+	};
+	
+	if let Some(store) = &options.handles {
+		if let Err(err) = check_handle_stamp(stamp.as_ref(), ciphertext, &options, store, &request.mdc) { error!(env, &err); }
+	}
+	
+	// The receiver learns which handle was used. What the mdc claims about expiry and tokens is chosen by the sender, so only the own records count.
+	let handle_status = match &options.handles {
+		Some(store) => match get_current_timestamp().and_then(|timestamp| timestamp_value(&timestamp)).and_then(|now| store.status(&request.mdc, now)) {
+			Ok(res) => Some(res),
+			Err(err) => { error!(env, &format!("Could not check handle: {}", err)); }
+		},
+		None => None
 	};
 	
 	let parse_init_request = ParseInitRequest {
 		status: "ok",
-		id: &id,
-		id_salt: &encode(id_salt),
-		mdc: &mdc,
-		remote_pubkey_kyber: &encode(remote_pubkey_kyber),
-		remote_pubkey_sig: &encode(remote_pubkey_sig),
-		own_pfs_key: &encode(own_pfs_key),
-		remote_pfs_key: &encode(remote_pfs_key),
-		pfs_salt: &encode(pfs_salt),
-		name: &name,
-		comment: &comment,
-		mdc_seed: &mdc_seed
+		id: &request.id,
+		id_salt: &encode(&request.id_salt),
+		mdc: &request.mdc,
+		remote_pubkey_kyber: &encode(&request.remote_pubkey_kyber),
+		remote_pubkey_sig: &encode(&request.remote_pubkey_sig),
+		own_pfs_key: &encode(&request.own_pfs_key),
+		remote_pfs_key: &encode(&request.remote_pfs_key),
+		pfs_salt: &encode(&request.pfs_salt),
+		name: &request.name,
+		comment: &request.comment,
+		mdc_seed: &request.mdc_seed,
+		handle_status,
+		session: &Session::new(request.announcement)
 	};
 	
 	let parse_init_request_json = match serde_json::to_string(&parse_init_request) {
@@ -225,6 +396,35 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseInitRequest<'local> (
 	parse_init_request_json
 }
 
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_parseInitRequest<'local> (
+	env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	ciphertext: JByteArray<'local>,
+	own_seckey_kyber: JString<'local>,
+	own_seckey_curve: JString<'local>,
+	own_seckey_curve_pfs_2: JString<'local>,
+	own_seckey_kyber_for_salt: JString<'local>,
+	own_seckey_curve_for_salt: JString<'local>
+) -> JString<'local> {
+	parse_init_request_with_options(env, ciphertext, own_seckey_kyber, own_seckey_curve, own_seckey_curve_pfs_2, own_seckey_kyber_for_salt, own_seckey_curve_for_salt, None)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_parseInitRequestWithOptions<'local> (
+	env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	ciphertext: JByteArray<'local>,
+	own_seckey_kyber: JString<'local>,
+	own_seckey_curve: JString<'local>,
+	own_seckey_curve_pfs_2: JString<'local>,
+	own_seckey_kyber_for_salt: JString<'local>,
+	own_seckey_curve_for_salt: JString<'local>,
+	options: JString<'local>
+) -> JString<'local> {
+	parse_init_request_with_options(env, ciphertext, own_seckey_kyber, own_seckey_curve, own_seckey_curve_pfs_2, own_seckey_kyber_for_salt, own_seckey_curve_for_salt, Some(options))
+}
+
 #[no_mangle]
 pub extern "C" fn Java_dawn_android_LibraryConnector_acceptInitRequest<'local> (
 	mut env: JNIEnv<'local>,
@@ -310,6 +510,114 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_acceptInitRequest<'local> (
 	accept_init_request_json
 }
 
+// Takes the session returned by parseInitRequest, the response only announces features and uses an envelope if the request announced them
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_acceptInitRequestWithSession<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	own_seckey_sig: JString<'local>,
+	own_pubkey_sig: JString<'local>,
+	remote_pubkey_kyber: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>,
+	id: JString<'local>,
+	mdc_seed: JString<'local>,
+	session: JString<'local>
+) -> JString<'local> {
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let own_pubkey_sig = env.get_string(&own_pubkey_sig);
+	if own_pubkey_sig.is_err() { error!(env, "Could not get java variable: own_pubkey_sig"); }
+	let own_pubkey_sig: String = own_pubkey_sig.unwrap().into();
+	let own_pubkey_sig = match decode(own_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_pubkey_sig invalid"); }
+	};
+	
+	let remote_pubkey_kyber = env.get_string(&remote_pubkey_kyber);
+	if remote_pubkey_kyber.is_err() { error!(env, "Could not get java variable: remote_pubkey_kyber"); }
+	let remote_pubkey_kyber: String = remote_pubkey_kyber.unwrap().into();
+	let remote_pubkey_kyber = match decode(remote_pubkey_kyber) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "remote_pubkey_kyber invalid"); }
+	};
+	
+	let pfs_key = env.get_string(&pfs_key);
+	if pfs_key.is_err() { error!(env, "Could not get java variable: pfs_key"); }
+	let pfs_key: String = pfs_key.unwrap().into();
+	let pfs_key = match decode(pfs_key) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_key invalid"); }
+	};
+	
+	let pfs_salt = env.get_string(&pfs_salt);
+	if pfs_salt.is_err() { error!(env, "Could not get java variable: pfs_salt"); }
+	let pfs_salt: String = pfs_salt.unwrap().into();
+	let pfs_salt = match decode(pfs_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_salt invalid"); }
+	};
+	
+	let id = env.get_string(&id);
+	if id.is_err() { error!(env, "Could not get java variable: id"); }
+	let id: String = id.unwrap().into();
+	
+	let mdc_seed = env.get_string(&mdc_seed);
+	if mdc_seed.is_err() { error!(env, "Could not get java variable: mdc_seed"); }
+	let mdc_seed: String = mdc_seed.unwrap().into();
+	
+	let session = env.get_string(&session);
+	if session.is_err() { error!(env, "Could not get java variable: session"); }
+	let session: String = session.unwrap().into();
+	let session = match parse_session(&session) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let (new_pfs_key, (own_pubkey_kyber, own_seckey_kyber), mdc, ciphertext) = match accept_init_request(&own_pubkey_sig, &own_seckey_sig, &remote_pubkey_kyber, &pfs_key, &pfs_salt, &id, &mdc_seed) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not create init accept message: {}", err)); }
+	};
+	
+	// Old clients can not parse the announcement or the envelope
+	let ciphertext = match session.peer_version {
+		Some(_) => match announce_in_response(&ciphertext, &own_seckey_sig) {
+			Ok(res) => res,
+			Err(err) => { error!(env, &format!("Could not announce features: {}", err)); }
+		},
+		None => ciphertext
+	};
+	let ciphertext = match session.supports(FEATURE_ENVELOPE) {
+		true => seal(Kind::InitResponse, &ciphertext, &pfs_salt),
+		false => ciphertext
+	};
+	
+	let accept_init_request = AcceptInitRequest {
+		status: "ok",
+		new_pfs_key: &encode(new_pfs_key),
+		own_pubkey_kyber: &encode(own_pubkey_kyber),
+		own_seckey_kyber: &encode(own_seckey_kyber),
+		mdc: &mdc,
+		ciphertext: &BASE64.encode(ciphertext)
+	};
+	
+	let accept_init_request_json = match serde_json::to_string(&accept_init_request) {
+		Ok(res) => match env.new_string(res) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		},
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	accept_init_request_json
+}
+
 #[no_mangle]
 pub extern "C" fn Java_dawn_android_LibraryConnector_parseInitResponse<'local> (
 	mut env: JNIEnv<'local>,
@@ -348,17 +656,56 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseInitResponse<'local> (
 		Err(_) => { error!(env, "pfs_salt invalid"); }
 	};
 	
-	let (remote_pubkey_kyber, remote_pubkey_sig, new_pfs_key, mdc) = match parse_init_response(&ciphertext, &own_seckey_kyber, None, &pfs_key, &pfs_salt) {
+	// Rejections are sealed as init responses as well
+	let ciphertext = match open_expecting(&ciphertext, Kind::InitResponse, &pfs_salt) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let (announcement, ciphertext) = match split_response(ciphertext) {
 		Ok(res) => res,
 		Err(err) => { error!(env, &format!("init response could not be parsed: {}", err)); }
 	};
 	
+	let (remote_pubkey_kyber, remote_pubkey_sig, new_pfs_key, mdc) = match parse_init_response(ciphertext, &own_seckey_kyber, None, &pfs_key, &pfs_salt) {
+		Ok(res) => res,
+		// A rejection is a regular message under the pfs key of the init request, the remote signing key is not known yet
+		Err(err) => match parse_msg(ciphertext, &own_seckey_kyber, None, &pfs_key, &pfs_salt) {
+			Ok(((msg_type, msg_text, msg_bytes), _, _)) => match Payload::decode(msg_type, msg_text.as_deref(), msg_bytes.as_deref()) {
+				Ok(Payload::InitRejection(rejection)) => {
+					let parse_init_rejection = ParseInitRejection {
+						status: STATUS_REJECTED,
+						reason: rejection.reason,
+						block: rejection.block
+					};
+					match serde_json::to_string(&parse_init_rejection) {
+						Ok(res) => match env.new_string(res) {
+							Ok(res) => return res,
+							Err(_) => { error!(env, "Could not create new java string"); }
+						},
+						Err(_) => { error!(env, "Could not serialize json"); }
+					}
+				},
+				_ => { error!(env, &format!("init response could not be parsed: {}", err)); }
+			},
+			Err(_) => { error!(env, &format!("init response could not be parsed: {}", err)); }
+		}
+	};
+	
+	let announcement: Option<Announcement> = match announcement {
+		Some(signed) => match verify_response_announcement(&signed, ciphertext, &remote_pubkey_sig) {
+			Ok(()) => Some(signed.announcement),
+			Err(err) => { error!(env, &format!("Feature announcement invalid: {}", err)); }
+		},
+		None => None
+	};
+	
 	let parse_init_response = ParseInitResponse {
 		status: "ok",
 		remote_pubkey_kyber: &encode(remote_pubkey_kyber),
 		remote_pubkey_sig: &encode(remote_pubkey_sig),
 		new_pfs_key: &encode(new_pfs_key),
-		mdc: &mdc
+		mdc: &mdc,
+		session: &Session::new(announcement)
 	};
 	
 	let parse_init_response_json = match serde_json::to_string(&parse_init_response) {
@@ -370,3 +717,152 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseInitResponse<'local> (
 	};
 	parse_init_response_json
 }
+
+// Takes the session returned by parseInitRequest, like acceptInitRequestWithSession
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_rejectInitRequest<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	own_seckey_sig: JString<'local>,
+	remote_pubkey_kyber: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>,
+	id: JString<'local>,
+	mdc_seed: JString<'local>,
+	session: JString<'local>,
+	rejection: JString<'local>
+) -> JString<'local> {
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let remote_pubkey_kyber = env.get_string(&remote_pubkey_kyber);
+	if remote_pubkey_kyber.is_err() { error!(env, "Could not get java variable: remote_pubkey_kyber"); }
+	let remote_pubkey_kyber: String = remote_pubkey_kyber.unwrap().into();
+	let remote_pubkey_kyber = match decode(remote_pubkey_kyber) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "remote_pubkey_kyber invalid"); }
+	};
+	
+	let pfs_key = env.get_string(&pfs_key);
+	if pfs_key.is_err() { error!(env, "Could not get java variable: pfs_key"); }
+	let pfs_key: String = pfs_key.unwrap().into();
+	let pfs_key = match decode(pfs_key) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_key invalid"); }
+	};
+	
+	let pfs_salt = env.get_string(&pfs_salt);
+	if pfs_salt.is_err() { error!(env, "Could not get java variable: pfs_salt"); }
+	let pfs_salt: String = pfs_salt.unwrap().into();
+	let pfs_salt = match decode(pfs_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_salt invalid"); }
+	};
+	
+	let id = env.get_string(&id);
+	if id.is_err() { error!(env, "Could not get java variable: id"); }
+	let id: String = id.unwrap().into();
+	
+	let mdc_seed = env.get_string(&mdc_seed);
+	if mdc_seed.is_err() { error!(env, "Could not get java variable: mdc_seed"); }
+	let mdc_seed: String = mdc_seed.unwrap().into();
+	
+	let session = env.get_string(&session);
+	if session.is_err() { error!(env, "Could not get java variable: session"); }
+	let session: String = session.unwrap().into();
+	let session = match parse_session(&session) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let rejection = env.get_string(&rejection);
+	if rejection.is_err() { error!(env, "Could not get java variable: rejection"); }
+	let rejection: String = rejection.unwrap().into();
+	let rejection: InitRejection = match rejection.as_str() {
+		"" => InitRejection::default(),
+		_ => match serde_json::from_str(&rejection) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "rejection invalid"); }
+		}
+	};
+	
+	let (msg_type, msg_text, msg_bytes) = match Payload::InitRejection(rejection).encode() {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let (new_pfs_key, mdc, ciphertext) = match send_msg((msg_type, msg_text.as_deref(), msg_bytes.as_deref()), &remote_pubkey_kyber, Some(&own_seckey_sig), &pfs_key, &pfs_salt, &id, &mdc_seed) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let ciphertext = match session.supports(FEATURE_ENVELOPE) {
+		true => seal(Kind::InitResponse, &ciphertext, &pfs_salt),
+		false => ciphertext
+	};
+	
+	let send_message = SendMessage {
+		status: "ok",
+		new_pfs_key: &encode(new_pfs_key),
+		mdc: &mdc,
+		ciphertext: &BASE64.encode(ciphertext)
+	};
+	
+	let send_message_json = match serde_json::to_string(&send_message) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	send_message_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::handles::HandleRecord;
+	
+	fn record(mdc: &str, pow_difficulty: Option<u8>) -> HandleRecord {
+		HandleRecord { mdc: mdc.to_string(), expires: None, one_time: false, pow_difficulty, used: false, revoked: false }
+	}
+	
+	#[test]
+	fn handle_difficulty_is_enforced() {
+		let handles = HandleStore { handles: vec![record("stamped", Some(8)), record("plain", None)] };
+		let options = InitRequestOptions { own_pubkey_kyber: Some(encode(b"pubkey")), ..InitRequestOptions::default() };
+		let check = |ciphertext: &[u8], mdc: &str| {
+			let (stamp, ciphertext) = split_stamp(ciphertext);
+			check_handle_stamp(stamp.as_ref(), ciphertext, &options, &handles, mdc)
+		};
+		assert!(check(b"request", "stamped").is_err());
+		assert!(check(&add_stamp(b"request", b"pubkey", 4).unwrap(), "stamped").is_err());
+		assert!(check(&add_stamp(b"request", b"pubkey", 8).unwrap(), "stamped").is_ok());
+		assert!(check(&add_stamp(b"request", b"other", 8).unwrap(), "stamped").is_err());
+		assert!(check(b"request", "plain").is_ok());
+		assert!(check(b"request", "unknown").is_ok());
+	}
+	
+	#[test]
+	fn handle_difficulty_needs_own_pubkey() {
+		let handles = HandleStore { handles: vec![record("stamped", Some(8))] };
+		let stamped = add_stamp(b"request", b"pubkey", 8).unwrap();
+		let (stamp, ciphertext) = split_stamp(&stamped);
+		assert!(check_handle_stamp(stamp.as_ref(), ciphertext, &InitRequestOptions::default(), &handles, "stamped").is_err());
+	}
+	
+	#[test]
+	fn parses_options() {
+		assert_eq!(parse_init_request_options("").unwrap().pow_difficulty, 0);
+		let options = parse_init_request_options("{\"pow_difficulty\": 8, \"handles\": {\"handles\": []}}").unwrap();
+		assert_eq!(options.pow_difficulty, 8);
+		assert!(options.handles.is_some());
+		assert!(parse_init_request_options("{\"pow_difficulty\": 256}").is_err());
+		assert!(parse_init_request_options("{").is_err());
+	}
+}
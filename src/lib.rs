@@ -16,13 +16,36 @@
 	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+mod attachments;
 mod crypto;
+mod envelope;
+mod expiry;
+mod features;
+mod fragments;
+mod frame;
+mod groups;
 mod handles;
+mod inbox;
 mod init;
+mod linking;
 mod macros;
 mod messaging;
+mod padding;
+mod payload;
+mod polling;
+mod pow;
+mod qr;
+mod receipts;
+mod replay;
+mod rotation;
+mod security_number;
+mod session;
+mod signatures;
+mod trust;
+mod uri;
+mod wordlists;
 
-use serde::{Serialize};
+use serde::{Serialize, Deserialize};
 
 #[derive(Serialize)]
 struct Error<'a> {
@@ -143,6 +166,13 @@ struct SendMessage<'a> {
 	ciphertext: &'a str
 }
 
+#[derive(Serialize)]
+struct SendMessageWithSession<'a> {
+	#[serde(flatten)]
+	message: &'a SendMessage<'a>,
+	session: &'a session::Session
+}
+
 #[derive(Serialize)]
 struct ParseMessage<'a> {
 	status: &'a str,
@@ -150,7 +180,90 @@ struct ParseMessage<'a> {
 	msg_text: &'a str,
 	msg_bytes: &'a str,
 	new_pfs_key: &'a str,
-	mdc: &'a str
+	mdc: &'a str,
+	// gaps and messages arriving out of order are reported by parseMsgWithReplayWindow, which remembers the counters
+	counter: Option<u64>,
+	sent: Option<&'a str>,
+	clock_skew: Option<i64>,
+	expires: Option<&'a str>
+}
+
+// Used in the attachments module:
+
+#[derive(Serialize)]
+struct EncryptAttachment<'a> {
+	status: &'a str,
+	ciphertext: &'a str,
+	descriptor: &'a attachments::AttachmentDescriptor
+}
+
+// Used in the payload module:
+
+#[derive(Serialize)]
+struct EncodePayload<'a> {
+	status: &'a str,
+	msg_type: u8,
+	msg_text: &'a str,
+	msg_bytes: &'a str
+}
+
+#[derive(Serialize)]
+struct DecodePayload<'a> {
+	status: &'a str,
+	msg_type: u8,
+	#[serde(flatten)]
+	payload: &'a payload::Payload
+}
+
+// Used in the groups module:
+
+#[derive(Serialize)]
+struct OutgoingPayload {
+	recipient: String,
+	msg_type: u8,
+	msg_text: String,
+	msg_bytes: String
+}
+
+#[derive(Serialize)]
+struct GroupResponse<'a> {
+	status: &'a str,
+	group: &'a groups::Group,
+	removed: bool,
+	payloads: Vec<OutgoingPayload>
+}
+
+#[derive(Serialize)]
+struct SignGroupOperation<'a> {
+	status: &'a str,
+	group: &'a groups::Group,
+	operation: &'a groups::GroupOperation,
+	payloads: Vec<OutgoingPayload>
+}
+
+#[derive(Serialize)]
+struct ValidateGroupOperations<'a> {
+	status: &'a str,
+	valid: bool,
+	invalid_index: Option<usize>,
+	reason: Option<&'a str>
+}
+
+#[derive(Serialize)]
+struct EncryptGroupMessage<'a> {
+	status: &'a str,
+	group: &'a groups::Group,
+	ciphertext: &'a str
+}
+
+#[derive(Serialize)]
+struct DecryptGroupMessage<'a> {
+	status: &'a str,
+	group: &'a groups::Group,
+	sender_id: &'a str,
+	msg_type: u8,
+	msg_text: &'a str,
+	msg_bytes: &'a str
 }
 
 // Used in the handles module:
@@ -162,15 +275,27 @@ struct GenHandle<'a> {
 }
 
 #[derive(Serialize)]
-struct ParseHandle<'a> {
+struct GenHandleWithOptions<'a> {
 	status: &'a str,
-	init_pk_kyber: &'a str,
-	init_pk_curve: &'a str,
-	init_pk_curve_pfs_2: &'a str,
-	init_pk_kyber_for_salt: &'a str,
-	init_pk_curve_for_salt: &'a str,
-	name: &'a str,
+	handle: &'a str,
 	mdc: &'a str,
+	expires: Option<&'a str>,
+	token: Option<&'a str>,
+	store: &'a handles::HandleStore
+}
+
+#[derive(Serialize)]
+struct HandleStoreResult<'a> {
+	status: &'a str,
+	handle_status: handles::HandleStatus,
+	store: &'a handles::HandleStore
+}
+
+#[derive(Serialize)]
+struct ParseHandle<'a> {
+	status: &'a str,
+	#[serde(flatten)]
+	info: &'a handles::HandleInfo
 }
 
 // Used in the init module:
@@ -205,7 +330,9 @@ struct ParseInitRequest<'a> {
 	pfs_salt: &'a str,
 	name: &'a str,
 	comment: &'a str,
-	mdc_seed: &'a str
+	mdc_seed: &'a str,
+	handle_status: Option<handles::HandleStatus>,
+	session: &'a session::Session
 }
 
 #[derive(Serialize)]
@@ -224,5 +351,355 @@ struct ParseInitResponse<'a> {
 	remote_pubkey_kyber: &'a str,
 	remote_pubkey_sig: &'a str,
 	new_pfs_key: &'a str,
+	mdc: &'a str,
+	session: &'a session::Session
+}
+
+#[derive(Serialize)]
+struct ParseInitRejection<'a> {
+	status: &'a str,
+	reason: Option<init::RejectReason>,
+	block: bool
+}
+
+// Used in the polling module:
+
+#[derive(Deserialize)]
+struct PollContact {
+	id: String,
+	mdc_seed: String
+}
+
+#[derive(Serialize)]
+struct PollEntry {
+	contact_id: String,
+	timestamp: String,
+	temp_id: String,
+	mdc: String
+}
+
+#[derive(Serialize)]
+struct PollPlan<'a> {
+	status: &'a str,
+	entries: Vec<PollEntry>
+}
+
+// Used in the linking module:
+
+#[derive(Serialize)]
+struct GenLinkingCode<'a> {
+	status: &'a str,
+	code: &'a str,
+	mdc: &'a str,
+	own_seckey_kyber: &'a str,
+	own_seckey_curve: &'a str,
+	own_seckey_curve_pfs_2: &'a str,
+	own_seckey_kyber_for_salt: &'a str,
+	own_seckey_curve_for_salt: &'a str
+}
+
+#[derive(Serialize)]
+struct GenLinkRequest<'a> {
+	status: &'a str,
+	own_pubkey_kyber: &'a str,
+	own_seckey_kyber: &'a str,
+	own_pubkey_curve: &'a str,
+	own_seckey_curve: &'a str,
+	own_pfs_key: &'a str,
+	remote_pfs_key: &'a str,
+	pfs_salt: &'a str,
+	id: &'a str,
+	id_salt: &'a str,
+	mdc: &'a str,
+	mdc_seed: &'a str,
+	ciphertext: &'a str,
+	primary_fingerprint: &'a str,
+	confirmation_code: &'a str
+}
+
+#[derive(Serialize)]
+struct ParseLinkRequest<'a> {
+	status: &'a str,
+	id: &'a str,
+	id_salt: &'a str,
+	mdc: &'a str,
+	own_pfs_key: &'a str,
+	remote_pfs_key: &'a str,
+	pfs_salt: &'a str,
+	mdc_seed: &'a str,
+	device: &'a linking::DeviceInfo
+}
+
+#[derive(Serialize)]
+struct ParseLinkBundle<'a> {
+	status: &'a str,
+	bundle: &'a linking::LinkBundle,
+	new_pfs_key: &'a str,
 	mdc: &'a str
 }
+
+// Used in the rotation module:
+
+#[derive(Serialize)]
+struct GenKeyRotation<'a> {
+	status: &'a str,
+	rotation: &'a rotation::KeyRotation
+}
+
+#[derive(Serialize)]
+struct ApplyKeyRotation<'a> {
+	status: &'a str,
+	remote_pubkey_sig: &'a str,
+	history: &'a rotation::RotationHistory
+}
+
+#[derive(Serialize)]
+struct ValidateRotationChain<'a> {
+	status: &'a str,
+	remote_pubkey_sig: &'a str,
+	rotations: usize,
+	history: &'a rotation::RotationHistory
+}
+
+// Used in the trust module:
+
+#[derive(Serialize)]
+struct TrustResult<'a> {
+	status: &'a str,
+	entry: &'a trust::TrustEntry,
+	store: &'a trust::TrustStore
+}
+
+#[derive(Serialize)]
+struct TrustRotation<'a> {
+	status: &'a str,
+	entry: &'a trust::TrustEntry,
+	// the signing key at the end of the chain, to be used for the contact from now on
+	remote_pubkey_sig: &'a str,
+	history: &'a rotation::RotationHistory,
+	store: &'a trust::TrustStore
+}
+
+// Used in the security_number module:
+
+#[derive(Serialize)]
+struct SecurityNumberFormats<'a> {
+	status: &'a str,
+	number: &'a str,
+	numeric: &'a str,
+	words: &'a [&'a str],
+	emoji: &'a [&'a str],
+	qr_payload: &'a str
+}
+
+#[derive(Serialize)]
+struct SecurityNumberVerification<'a> {
+	status: &'a str,
+	verified: bool
+}
+
+// Used in the qr module:
+
+#[derive(Serialize)]
+struct GenQrCode<'a> {
+	status: &'a str,
+	format: &'a str,
+	data: &'a str
+}
+
+#[derive(Serialize)]
+struct GenQrCodeSequence<'a> {
+	status: &'a str,
+	format: &'a str,
+	codes: &'a [String]
+}
+
+#[derive(Serialize)]
+struct AddScannedQrPart<'a> {
+	status: &'a str,
+	missing: &'a [usize],
+	sequence: &'a qr::QrSequence,
+	// only once all parts are scanned
+	#[serde(skip_serializing_if = "Option::is_none")]
+	info: Option<&'a handles::HandleInfo>
+}
+
+// Used in the uri module:
+
+#[derive(Serialize)]
+struct GenHandleUri<'a> {
+	status: &'a str,
+	uri: &'a str
+}
+
+#[derive(Serialize)]
+struct ParseHandleUri<'a> {
+	status: &'a str,
+	handle: &'a str,
+	name_hint: Option<&'a str>,
+	#[serde(flatten)]
+	info: &'a handles::HandleInfo
+}
+
+// Used in the inbox module:
+
+#[derive(Serialize)]
+struct InboxResult<'a> {
+	status: &'a str,
+	entry: Option<&'a inbox::PendingInitRequest>,
+	inbox: &'a inbox::InitInbox
+}
+
+#[derive(Serialize)]
+struct InboxList<'a> {
+	status: &'a str,
+	pending: &'a [inbox::PendingInitRequest],
+	inbox: &'a inbox::InitInbox
+}
+
+#[derive(Serialize)]
+struct InboxAccept<'a> {
+	status: &'a str,
+	entry: &'a inbox::PendingInitRequest,
+	new_pfs_key: &'a str,
+	own_pubkey_kyber: &'a str,
+	own_seckey_kyber: &'a str,
+	mdc: &'a str,
+	ciphertext: &'a str,
+	session: &'a session::Session,
+	inbox: &'a inbox::InitInbox,
+	handles: &'a handles::HandleStore
+}
+
+#[derive(Serialize)]
+struct InboxReject<'a> {
+	status: &'a str,
+	entry: &'a inbox::PendingInitRequest,
+	mdc: Option<&'a str>,
+	ciphertext: Option<&'a str>,
+	inbox: &'a inbox::InitInbox
+}
+
+// Used in the envelope module:
+
+#[derive(Serialize)]
+struct ClassifyCiphertext<'a> {
+	status: &'a str,
+	kind: Option<envelope::Kind>,
+	envelope_version: Option<u8>
+}
+
+// Used in the fragments module:
+
+#[derive(Serialize)]
+struct FragmentCiphertext {
+	mdc: String,
+	ciphertext: String
+}
+
+#[derive(Serialize)]
+struct SendFragments<'a> {
+	status: &'a str,
+	new_pfs_key: &'a str,
+	fragments: &'a [FragmentCiphertext],
+	session: &'a session::Session
+}
+
+#[derive(Serialize)]
+struct AddFragment<'a> {
+	status: &'a str,
+	message_id: &'a str,
+	missing: &'a [u32],
+	msg_type: Option<u8>,
+	msg_text: Option<&'a str>,
+	msg_bytes: Option<&'a str>,
+	// taken from the frame of the reassembled message, like parseMsg does
+	counter: Option<u64>,
+	sent: Option<&'a str>,
+	clock_skew: Option<i64>,
+	expires: Option<&'a str>,
+	buffer: &'a fragments::FragmentBuffer
+}
+
+#[derive(Serialize)]
+struct ExpireFragments<'a> {
+	status: &'a str,
+	expired: &'a [String],
+	buffer: &'a fragments::FragmentBuffer
+}
+
+// Used in the replay module:
+
+#[derive(Serialize)]
+struct ReplayCheck<'a> {
+	status: &'a str,
+	window: &'a replay::ReplayWindow
+}
+
+#[derive(Serialize)]
+struct ParseMessageWithWindow<'a> {
+	#[serde(flatten)]
+	message: &'a ParseMessage<'a>,
+	gap: u64,
+	out_of_order: bool,
+	window: &'a replay::ReplayWindow
+}
+
+#[derive(Serialize)]
+struct AddFragmentWithWindow<'a> {
+	#[serde(flatten)]
+	fragment: &'a AddFragment<'a>,
+	gap: u64,
+	out_of_order: bool,
+	window: &'a replay::ReplayWindow
+}
+
+// Used in the expiry module:
+
+#[derive(Serialize)]
+struct ExpiryResult<'a> {
+	status: &'a str,
+	scheduler: &'a expiry::ExpiryScheduler
+}
+
+#[derive(Serialize)]
+struct ExpiryDue<'a> {
+	status: &'a str,
+	due: &'a [String],
+	next: Option<&'a str>,
+	scheduler: &'a expiry::ExpiryScheduler
+}
+
+#[derive(Serialize)]
+struct ExpiryTimer<'a> {
+	status: &'a str,
+	ttl: Option<u64>
+}
+
+// Used in the receipts module:
+
+#[derive(Serialize)]
+struct ReceiptResult<'a> {
+	status: &'a str,
+	tracker: &'a receipts::ReceiptTracker
+}
+
+#[derive(Serialize)]
+struct ReceiptApply<'a> {
+	status: &'a str,
+	unknown: &'a [String],
+	tracker: &'a receipts::ReceiptTracker
+}
+
+#[derive(Serialize)]
+struct ReceiptFlush<'a> {
+	status: &'a str,
+	batches: &'a [receipts::ReceiptBatch],
+	tracker: &'a receipts::ReceiptTracker
+}
+
+#[derive(Serialize)]
+struct ReceiptQuery<'a> {
+	status: &'a str,
+	states: &'a [receipts::TrackedState<'a>]
+}
@@ -0,0 +1,514 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JClass, JString};
+use hex::{encode, decode};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use serde::{Serialize, Deserialize};
+use crate::{Error, GenLinkingCode, GenLinkRequest, ParseLinkRequest, SendMessage, ParseLinkBundle};
+use crate::envelope::{Kind, seal, open_unverified, open_expecting};
+use crate::payload::Payload;
+use crate::signatures::{fingerprint, sign_detached, verify_detached, signed_data};
+use crate::error;
+
+// A linking code is a regular handle for freshly generated init keys that is only shown on the primary device. Its name carries
+// the fingerprint of the primary signing key, so the new device can check the key it receives in the bundle. The new device
+// answers it with an init request carrying LINK_COMMENT and shows a confirmation code derived from the new session. Only after
+// that code was entered on the primary device, the primary device sends a LinkBundle through the new session.
+const LINK_HANDLE_PREFIX: &str = "dawn-link:";
+const LINK_COMMENT: &str = "dawn-link:1";
+const CONFIRMATION_DIGITS: usize = 8;
+
+// Someone else holding the linking code can send a link request as well, but can not know the pfs_salt of the session the new device set up
+fn derive_confirmation_code(pfs_salt: &[u8], device_pubkey_sig: &[u8]) -> Result<String, String> {
+	let material = hash(&signed_data(&[b"dawn-link-confirmation", pfs_salt, device_pubkey_sig]));
+	let material: [u8; 8] = match material.get(..8).map(|bytes| bytes.try_into()) {
+		Some(Ok(res)) => res,
+		_ => return Err("Hash output too short".to_string())
+	};
+	let digits = format!("{:0width$}", u64::from_be_bytes(material) % 10u64.pow(CONFIRMATION_DIGITS as u32), width = CONFIRMATION_DIGITS);
+	let (first, second) = digits.split_at(CONFIRMATION_DIGITS / 2);
+	Ok(format!("{} {}", first, second))
+}
+
+fn digits_of(code: &str) -> String {
+	code.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+	pub device_id: String,
+	pub name: String,
+	pub pubkey_sig: String,
+	pub pubkey_kyber: String
+}
+
+// Everything a linked device needs to act on behalf of the account. contacts holds the session objects of the app as is.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LinkBundle {
+	pub own_pubkey_sig: String,
+	pub own_seckey_sig: String,
+	#[serde(default)]
+	pub contacts: Vec<serde_json::Value>,
+	#[serde(default)]
+	pub devices: Vec<DeviceInfo>
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_genLinkingCode<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	own_pubkey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let own_pubkey_sig = env.get_string(&own_pubkey_sig);
+	if own_pubkey_sig.is_err() { error!(env, "Could not get java variable: own_pubkey_sig"); }
+	let own_pubkey_sig: String = own_pubkey_sig.unwrap().into();
+	let own_pubkey_sig = match decode(own_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_pubkey_sig invalid"); }
+	};
+	
+	let (own_pubkey_kyber, own_seckey_kyber) = kyber_keygen();
+	let (own_pubkey_curve, own_seckey_curve) = curve_keygen();
+	let (own_pubkey_curve_pfs_2, own_seckey_curve_pfs_2) = curve_keygen();
+	let (own_pubkey_kyber_for_salt, own_seckey_kyber_for_salt) = kyber_keygen();
+	let (own_pubkey_curve_for_salt, own_seckey_curve_for_salt) = curve_keygen();
+	let mdc = mdc_gen();
+	
+	let name = format!("{}{}", LINK_HANDLE_PREFIX, fingerprint(&own_pubkey_sig));
+	let code = gen_handle(&own_pubkey_kyber, &own_pubkey_curve, &own_pubkey_curve_pfs_2, &own_pubkey_kyber_for_salt, &own_pubkey_curve_for_salt, &name, &mdc);
+	
+	let gen_linking_code = GenLinkingCode {
+		status: "ok",
+		code: &BASE64.encode(code),
+		mdc: &mdc,
+		own_seckey_kyber: &encode(own_seckey_kyber),
+		own_seckey_curve: &encode(own_seckey_curve),
+		own_seckey_curve_pfs_2: &encode(own_seckey_curve_pfs_2),
+		own_seckey_kyber_for_salt: &encode(own_seckey_kyber_for_salt),
+		own_seckey_curve_for_salt: &encode(own_seckey_curve_for_salt)
+	};
+	
+	let gen_linking_code_json = match serde_json::to_string(&gen_linking_code) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	gen_linking_code_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_genLinkRequest<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	code: JByteArray<'local>,
+	device_pubkey_sig: JString<'local>,
+	device_seckey_sig: JString<'local>,
+	device_name: JString<'local>
+) -> JString<'local> {
+	
+	let code = env.convert_byte_array(code);
+	if code.is_err() { error!(env, "Could not get java variable: code"); }
+	let code = code.unwrap();
+	
+	let device_pubkey_sig = env.get_string(&device_pubkey_sig);
+	if device_pubkey_sig.is_err() { error!(env, "Could not get java variable: device_pubkey_sig"); }
+	let device_pubkey_sig: String = device_pubkey_sig.unwrap().into();
+	let device_pubkey_sig = match decode(device_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "device_pubkey_sig invalid"); }
+	};
+	
+	let device_seckey_sig = env.get_string(&device_seckey_sig);
+	if device_seckey_sig.is_err() { error!(env, "Could not get java variable: device_seckey_sig"); }
+	let device_seckey_sig: String = device_seckey_sig.unwrap().into();
+	let device_seckey_sig = match decode(device_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "device_seckey_sig invalid"); }
+	};
+	
+	let device_name = env.get_string(&device_name);
+	if device_name.is_err() { error!(env, "Could not get java variable: device_name"); }
+	let device_name: String = device_name.unwrap().into();
+	
+	let (remote_pubkey_kyber, remote_pubkey_curve, remote_pubkey_curve_pfs_2, remote_pubkey_kyber_for_salt, remote_pubkey_curve_for_salt, name, mdc) = match parse_handle(code) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Standard Library returned error: {}", err)); }
+	};
+	let primary_fingerprint = match name.strip_prefix(LINK_HANDLE_PREFIX) {
+		Some(res) => res.to_string(),
+		None => { error!(env, "Code is not a linking code"); }
+	};
+	
+	let ((own_pubkey_kyber, own_seckey_kyber), (own_pubkey_curve, own_seckey_curve), own_pfs_key, remote_pfs_key, pfs_salt, id, id_salt, mdc, mdc_seed, ciphertext) = match gen_init_request(&remote_pubkey_kyber, &remote_pubkey_kyber_for_salt, &remote_pubkey_curve, &remote_pubkey_curve_pfs_2, &remote_pubkey_curve_for_salt, &device_pubkey_sig, &device_seckey_sig, &device_name, LINK_COMMENT, &mdc) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not generate link request: {}", err)); }
+	};
+	// Both devices run a new client, so link requests and bundles are always sealed
+	let ciphertext = seal(Kind::InitRequest, &ciphertext, &pfs_salt);
+	
+	let confirmation_code = match derive_confirmation_code(&pfs_salt, &device_pubkey_sig) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not derive confirmation code: {}", err)); }
+	};
+	
+	let gen_link_request = GenLinkRequest {
+		status: "ok",
+		own_pubkey_kyber: &encode(own_pubkey_kyber),
+		own_seckey_kyber: &encode(own_seckey_kyber),
+		own_pubkey_curve: &encode(own_pubkey_curve),
+		own_seckey_curve: &encode(own_seckey_curve),
+		own_pfs_key: &encode(own_pfs_key),
+		remote_pfs_key: &encode(remote_pfs_key),
+		pfs_salt: &encode(pfs_salt),
+		id: &id,
+		id_salt: &encode(id_salt),
+		mdc: &mdc,
+		mdc_seed: &mdc_seed,
+		ciphertext: &BASE64.encode(ciphertext),
+		primary_fingerprint: &primary_fingerprint,
+		confirmation_code: &confirmation_code
+	};
+	
+	let gen_link_request_json = match serde_json::to_string(&gen_link_request) {
+		Ok(res) => match env.new_string(res) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		},
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	gen_link_request_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_parseLinkRequest<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	ciphertext: JByteArray<'local>,
+	own_seckey_kyber: JString<'local>,
+	own_seckey_curve: JString<'local>,
+	own_seckey_curve_pfs_2: JString<'local>,
+	own_seckey_kyber_for_salt: JString<'local>,
+	own_seckey_curve_for_salt: JString<'local>
+) -> JString<'local> {
+	
+	let ciphertext = env.convert_byte_array(ciphertext);
+	if ciphertext.is_err() { error!(env, "Could not get java variable: ciphertext"); }
+	let ciphertext = ciphertext.unwrap();
+	let (envelope, ciphertext) = match open_unverified(&ciphertext, Kind::InitRequest) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let own_seckey_kyber = env.get_string(&own_seckey_kyber);
+	if own_seckey_kyber.is_err() { error!(env, "Could not get java variable: own_seckey_kyber"); }
+	let own_seckey_kyber: String = own_seckey_kyber.unwrap().into();
+	let own_seckey_kyber = match decode(own_seckey_kyber) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_kyber invalid"); }
+	};
+	
+	let own_seckey_curve = env.get_string(&own_seckey_curve);
+	if own_seckey_curve.is_err() { error!(env, "Could not get java variable: own_seckey_curve"); }
+	let own_seckey_curve: String = own_seckey_curve.unwrap().into();
+	let own_seckey_curve = match decode(own_seckey_curve) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_curve invalid"); }
+	};
+	
+	let own_seckey_curve_pfs_2 = env.get_string(&own_seckey_curve_pfs_2);
+	if own_seckey_curve_pfs_2.is_err() { error!(env, "Could not get java variable: own_seckey_curve_pfs_2"); }
+	let own_seckey_curve_pfs_2: String = own_seckey_curve_pfs_2.unwrap().into();
+	let own_seckey_curve_pfs_2 = match decode(own_seckey_curve_pfs_2) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_curve_pfs_2 invalid"); }
+	};
+	
+	let own_seckey_kyber_for_salt = env.get_string(&own_seckey_kyber_for_salt);
+	if own_seckey_kyber_for_salt.is_err() { error!(env, "Could not get java variable: own_seckey_kyber_for_salt"); }
+	let own_seckey_kyber_for_salt: String = own_seckey_kyber_for_salt.unwrap().into();
+	let own_seckey_kyber_for_salt = match decode(own_seckey_kyber_for_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_kyber_for_salt invalid"); }
+	};
+	
+	let own_seckey_curve_for_salt = env.get_string(&own_seckey_curve_for_salt);
+	if own_seckey_curve_for_salt.is_err() { error!(env, "Could not get java variable: own_seckey_curve_for_salt"); }
+	let own_seckey_curve_for_salt: String = own_seckey_curve_for_salt.unwrap().into();
+	let own_seckey_curve_for_salt = match decode(own_seckey_curve_for_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_curve_for_salt invalid"); }
+	};
+	
+	let (id, id_salt, mdc, remote_pubkey_kyber, remote_pubkey_sig, own_pfs_key, remote_pfs_key, pfs_salt, name, comment, mdc_seed) = match parse_init_request(ciphertext, &own_seckey_kyber, &own_seckey_curve, &own_seckey_curve_pfs_2, &own_seckey_kyber_for_salt, &own_seckey_curve_for_salt) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not parse link request: {}", err)); }
+	};
+	if let Some(envelope) = envelope {
+		if let Err(err) = envelope.verify(&pfs_salt) { error!(env, &err); }
+	}
+	if comment != LINK_COMMENT { error!(env, "Init request is not a link request"); }
+	
+	let device = DeviceInfo {
+		device_id: id.clone(),
+		name,
+		pubkey_sig: encode(remote_pubkey_sig),
+		pubkey_kyber: encode(remote_pubkey_kyber)
+	};
+	
+	let parse_link_request = ParseLinkRequest {
+		status: "ok",
+		id: &id,
+		id_salt: &encode(id_salt),
+		mdc: &mdc,
+		own_pfs_key: &encode(own_pfs_key),
+		remote_pfs_key: &encode(remote_pfs_key),
+		pfs_salt: &encode(pfs_salt),
+		mdc_seed: &mdc_seed,
+		device: &device
+	};
+	
+	let parse_link_request_json = match serde_json::to_string(&parse_link_request) {
+		Ok(res) => match env.new_string(res) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		},
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	parse_link_request_json
+}
+
+// Sent by the primary device after accepting the link request (see acceptInitRequest). confirmation_code is the code shown
+// on the new device as entered or scanned on the primary device, device_pubkey_sig is taken from the parsed link request.
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_genLinkBundle<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	bundle: JString<'local>,
+	device_pubkey_sig: JString<'local>,
+	confirmation_code: JString<'local>,
+	remote_pubkey_kyber: JString<'local>,
+	own_seckey_sig: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>,
+	id: JString<'local>,
+	mdc_seed: JString<'local>
+) -> JString<'local> {
+	
+	let bundle = env.get_string(&bundle);
+	if bundle.is_err() { error!(env, "Could not get java variable: bundle"); }
+	let bundle: String = bundle.unwrap().into();
+	let bundle: LinkBundle = match serde_json::from_str(&bundle) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "bundle invalid"); }
+	};
+	let (msg_type, _, msg_bytes) = match Payload::LinkBundle(bundle).encode() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let device_pubkey_sig = env.get_string(&device_pubkey_sig);
+	if device_pubkey_sig.is_err() { error!(env, "Could not get java variable: device_pubkey_sig"); }
+	let device_pubkey_sig: String = device_pubkey_sig.unwrap().into();
+	let device_pubkey_sig = match decode(device_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "device_pubkey_sig invalid"); }
+	};
+	
+	let confirmation_code = env.get_string(&confirmation_code);
+	if confirmation_code.is_err() { error!(env, "Could not get java variable: confirmation_code"); }
+	let confirmation_code: String = confirmation_code.unwrap().into();
+	
+	let remote_pubkey_kyber = env.get_string(&remote_pubkey_kyber);
+	if remote_pubkey_kyber.is_err() { error!(env, "Could not get java variable: remote_pubkey_kyber"); }
+	let remote_pubkey_kyber: String = remote_pubkey_kyber.unwrap().into();
+	let remote_pubkey_kyber = match decode(remote_pubkey_kyber) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "remote_pubkey_kyber invalid"); }
+	};
+	
+	let own_seckey_sig = env.get_string(&own_seckey_sig);
+	if own_seckey_sig.is_err() { error!(env, "Could not get java variable: own_seckey_sig"); }
+	let own_seckey_sig: String = own_seckey_sig.unwrap().into();
+	let own_seckey_sig = match decode(own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_sig invalid"); }
+	};
+	
+	let pfs_key = env.get_string(&pfs_key);
+	if pfs_key.is_err() { error!(env, "Could not get java variable: pfs_key"); }
+	let pfs_key: String = pfs_key.unwrap().into();
+	let pfs_key = match decode(pfs_key) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_key invalid"); }
+	};
+	
+	let pfs_salt = env.get_string(&pfs_salt);
+	if pfs_salt.is_err() { error!(env, "Could not get java variable: pfs_salt"); }
+	let pfs_salt: String = pfs_salt.unwrap().into();
+	let pfs_salt = match decode(pfs_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_salt invalid"); }
+	};
+	
+	let id = env.get_string(&id);
+	if id.is_err() { error!(env, "Could not get java variable: id"); }
+	let id: String = id.unwrap().into();
+	
+	let mdc_seed = env.get_string(&mdc_seed);
+	if mdc_seed.is_err() { error!(env, "Could not get java variable: mdc_seed"); }
+	let mdc_seed: String = mdc_seed.unwrap().into();
+	
+	let expected_code = match derive_confirmation_code(&pfs_salt, &device_pubkey_sig) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not derive confirmation code: {}", err)); }
+	};
+	if digits_of(&confirmation_code) != digits_of(&expected_code) { error!(env, "Confirmation code does not match the link request"); }
+	
+	let (new_pfs_key, mdc, ciphertext) = match send_msg((msg_type, None, msg_bytes.as_deref()), &remote_pubkey_kyber, Some(&own_seckey_sig), &pfs_key, &pfs_salt, &id, &mdc_seed) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let ciphertext = seal(Kind::Message, &ciphertext, &pfs_salt);
+	
+	let send_message = SendMessage {
+		status: "ok",
+		new_pfs_key: &encode(new_pfs_key),
+		mdc: &mdc,
+		ciphertext: &BASE64.encode(ciphertext)
+	};
+	
+	let send_message_json = match serde_json::to_string(&send_message) {
+		Ok(res) => match env.new_string(res) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		},
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	send_message_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_parseLinkBundle<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	ciphertext: JByteArray<'local>,
+	own_seckey_kyber: JString<'local>,
+	primary_fingerprint: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>
+) -> JString<'local> {
+	
+	let ciphertext = env.convert_byte_array(ciphertext);
+	if ciphertext.is_err() { error!(env, "Could not get java variable: ciphertext"); }
+	let ciphertext = ciphertext.unwrap();
+	
+	let own_seckey_kyber = env.get_string(&own_seckey_kyber);
+	if own_seckey_kyber.is_err() { error!(env, "Could not get java variable: own_seckey_kyber"); }
+	let own_seckey_kyber: String = own_seckey_kyber.unwrap().into();
+	let own_seckey_kyber = match decode(own_seckey_kyber) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_seckey_kyber invalid"); }
+	};
+	
+	let primary_fingerprint = env.get_string(&primary_fingerprint);
+	if primary_fingerprint.is_err() { error!(env, "Could not get java variable: primary_fingerprint"); }
+	let primary_fingerprint: String = primary_fingerprint.unwrap().into();
+	
+	let pfs_key = env.get_string(&pfs_key);
+	if pfs_key.is_err() { error!(env, "Could not get java variable: pfs_key"); }
+	let pfs_key: String = pfs_key.unwrap().into();
+	let pfs_key = match decode(pfs_key) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_key invalid"); }
+	};
+	
+	let pfs_salt = env.get_string(&pfs_salt);
+	if pfs_salt.is_err() { error!(env, "Could not get java variable: pfs_salt"); }
+	let pfs_salt: String = pfs_salt.unwrap().into();
+	let pfs_salt = match decode(pfs_salt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "pfs_salt invalid"); }
+	};
+	
+	let ciphertext = match open_expecting(&ciphertext, Kind::Message, &pfs_salt) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	// the new device has no copy of the primary signing key yet, it is taken from the bundle and checked against the fingerprint of the linking code
+	let ((msg_type, msg_text, msg_bytes), new_pfs_key, mdc) = match parse_msg(ciphertext, &own_seckey_kyber, None, &pfs_key, &pfs_salt) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	let bundle = match Payload::decode(msg_type, msg_text.as_deref(), msg_bytes.as_deref()) {
+		Ok(Payload::LinkBundle(bundle)) => bundle,
+		Ok(_) => { error!(env, "Message is not a link bundle"); }
+		Err(_) => { error!(env, "Link bundle invalid"); }
+	};
+	
+	let own_pubkey_sig = match decode(&bundle.own_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "Link bundle invalid"); }
+	};
+	if fingerprint(&own_pubkey_sig) != primary_fingerprint { error!(env, "Signing key of the link bundle does not match the linking code"); }
+	// only the primary device holds the secret key matching the fingerprint, so this also authenticates the bundle
+	let own_seckey_sig = match decode(&bundle.own_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "Link bundle invalid"); }
+	};
+	let key_pair_valid = sign_detached(b"dawn-link-key-pair", &own_seckey_sig).and_then(|signature| verify_detached(b"dawn-link-key-pair", &signature, &own_pubkey_sig));
+	if key_pair_valid.is_err() { error!(env, "Signing keys of the link bundle do not match"); }
+	
+	let parse_link_bundle = ParseLinkBundle {
+		status: "ok",
+		bundle: &bundle,
+		new_pfs_key: &encode(new_pfs_key),
+		mdc: &mdc
+	};
+	
+	let parse_link_bundle_json = match serde_json::to_string(&parse_link_bundle) {
+		Ok(res) => match env.new_string(res) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		},
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	parse_link_bundle_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn confirmation_code_format() {
+		let code = derive_confirmation_code(b"salt", b"pubkey").unwrap();
+		assert_eq!(code.len(), CONFIRMATION_DIGITS + 1);
+		assert_eq!(digits_of(&code).len(), CONFIRMATION_DIGITS);
+		assert_eq!(code, derive_confirmation_code(b"salt", b"pubkey").unwrap());
+		assert_ne!(code, derive_confirmation_code(b"other salt", b"pubkey").unwrap());
+		assert_eq!(digits_of("1234 5678\n"), "12345678");
+	}
+}
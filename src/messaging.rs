@@ -23,12 +23,17 @@ use jni::objects::{JByteArray, JClass, JString};
 use jni::sys::jshort;
 use hex::{encode, decode};
 use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
-use crate::{Error, SendMessage, ParseMessage, EncryptFile, DecryptFile};
+use crate::{Error, SendMessage, SendMessageWithSession, ParseMessage, ParseMessageWithWindow, EncryptFile, DecryptFile};
+use crate::frame::{FrameOptions, FRAME_MSG_TYPE, frame_msg, unframe_msg_info, frame_file, unframe_file};
+use crate::features::{FEATURE_ENVELOPE, required_features};
+use crate::session::{Session, parse_session};
+use crate::envelope::{Kind, seal, open_expecting};
+use crate::replay::{ReplayWindow, parse_window, replay_status};
 use crate::error;
 
 #[no_mangle]
 pub extern "C" fn Java_dawn_android_LibraryConnector_sendMsg<'local> (
-	mut env: JNIEnv<'local>,
+	env: JNIEnv<'local>,
 	_class: JClass<'local>,
 	msg_type: jshort,
 	msg_string: JString<'local>,
@@ -40,6 +45,44 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_sendMsg<'local> (
 	id: JString<'local>,
 	mdc_seed: JString<'local>
 ) -> JString<'local> {
+	send_message(env, msg_type, msg_string, msg_bytes, remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, id, mdc_seed, None, None)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_sendMsgWithOptions<'local> (
+	env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	msg_type: jshort,
+	msg_string: JString<'local>,
+	msg_bytes: JByteArray<'local>,
+	remote_pubkey_kyber: JString<'local>,
+	own_seckey_sig: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>,
+	id: JString<'local>,
+	mdc_seed: JString<'local>,
+	session: JString<'local>,
+	options: JString<'local>
+) -> JString<'local> {
+	send_message(env, msg_type, msg_string, msg_bytes, remote_pubkey_kyber, own_seckey_sig, pfs_key, pfs_salt, id, mdc_seed, Some(session), Some(options))
+}
+
+// Shared by sendMsg and sendMsgWithOptions. Messages are only framed when options are given, so the output of sendMsg stays readable for old clients.
+#[allow(clippy::too_many_arguments)]
+fn send_message<'local> (
+	mut env: JNIEnv<'local>,
+	msg_type: jshort,
+	msg_string: JString<'local>,
+	msg_bytes: JByteArray<'local>,
+	remote_pubkey_kyber: JString<'local>,
+	own_seckey_sig: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>,
+	id: JString<'local>,
+	mdc_seed: JString<'local>,
+	session: Option<JString<'local>>,
+	options: Option<JString<'local>>
+) -> JString<'local> {
 	
 	let msg_type = match u8::try_from(msg_type) {
 		Ok(n) => n,
@@ -102,10 +145,40 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_sendMsg<'local> (
 	if mdc_seed.is_err() { error!(env, "Could not get java variable: mdc_seed"); }
 	let mdc_seed: String = mdc_seed.unwrap().into();
 	
-	let (new_pfs_key, mdc, ciphertext) = match send_msg((msg_type, msg_string, msg_bytes), &remote_pubkey_kyber, Some(&own_seckey_sig), &pfs_key, &pfs_salt, &id, &mdc_seed) {
+	let options: Option<FrameOptions> = match options {
+		Some(options) => {
+			let options = env.get_string(&options);
+			if options.is_err() { error!(env, "Could not get java variable: options"); }
+			let options: String = options.unwrap().into();
+			match options.as_str() {
+				"" => Some(FrameOptions::default()),
+				_ => match serde_json::from_str(&options) {
+					Ok(res) => Some(res),
+					Err(_) => { error!(env, "options invalid"); }
+				}
+			}
+		},
+		None => None
+	};
+	
+	let mut session: Option<Session> = match session {
+		Some(session) => {
+			let session = env.get_string(&session);
+			if session.is_err() { error!(env, "Could not get java variable: session"); }
+			let session: String = session.unwrap().into();
+			match parse_session(&session) {
+				Ok(res) => Some(res),
+				Err(err) => { error!(env, &err); }
+			}
+		},
+		None => None
+	};
+	
+	let (new_pfs_key, mdc, ciphertext) = match encrypt_message((msg_type, msg_string, msg_bytes), &remote_pubkey_kyber, &own_seckey_sig, &pfs_key, &pfs_salt, &id, &mdc_seed, session.as_mut(), options.as_ref()) {
 		Ok(res) => res,
 		Err(err) => { error!(env, &err); }
 	};
+	
 	let send_message = SendMessage {
 		status: "ok",
 		new_pfs_key: &encode(new_pfs_key),
@@ -113,7 +186,16 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_sendMsg<'local> (
 		ciphertext: &BASE64.encode(ciphertext)
 	};
 	
-	let send_message_json = match serde_json::to_string(&send_message) {
+	// The session advanced its message counter and has to be stored again
+	let send_message_json = match &session {
+		Some(session) => serde_json::to_string(&SendMessageWithSession {
+			message: &send_message,
+			session
+		}),
+		None => serde_json::to_string(&send_message)
+	};
+	
+	let send_message_json = match send_message_json {
 		Ok(res) => match env.new_string(res) {
 			Ok(res) => res,
 			Err(_) => { error!(env, "Could not create new java string"); }
@@ -123,9 +205,35 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_sendMsg<'local> (
 	send_message_json
 }
 
+// Without a session nothing is known about the peer, so the message is sent like by sendMsg before feature negotiation existed:
+// unframed, unsealed and with the msg_type chosen by the app. Features are only enforced once a session is passed.
+#[allow(clippy::too_many_arguments)]
+fn encrypt_message(msg: (u8, Option<&str>, Option<&[u8]>), remote_pubkey_kyber: &[u8], own_seckey_sig: &[u8], pfs_key: &[u8], pfs_salt: &[u8], id: &str, mdc_seed: &str, mut session: Option<&mut Session>, options: Option<&FrameOptions>) -> Result<(Vec<u8>, String, Vec<u8>), String> {
+	if let Some(session) = &session {
+		session.check_features(required_features(msg.0, options))?;
+	}
+	let (new_pfs_key, mdc, ciphertext) = match options {
+		Some(options) => {
+			let counter = match session.as_deref_mut() {
+				Some(session) => session.take_counter()?,
+				None => None
+			};
+			let frame = frame_msg(msg, options, counter).map_err(|err| format!("Could not frame message: {}", err))?;
+			send_msg((FRAME_MSG_TYPE, None, Some(&frame)), remote_pubkey_kyber, Some(own_seckey_sig), pfs_key, pfs_salt, id, mdc_seed)?
+		},
+		None => send_msg(msg, remote_pubkey_kyber, Some(own_seckey_sig), pfs_key, pfs_salt, id, mdc_seed)?
+	};
+	// Sealed for every peer that announced support for envelopes
+	let ciphertext = match session {
+		Some(session) if session.supports(FEATURE_ENVELOPE) => seal(Kind::Message, &ciphertext, pfs_salt),
+		_ => ciphertext
+	};
+	Ok((new_pfs_key, mdc, ciphertext))
+}
+
 #[no_mangle]
 pub extern "C" fn Java_dawn_android_LibraryConnector_parseMsg<'local> (
-	mut env: JNIEnv<'local>,
+	env: JNIEnv<'local>,
 	_class: JClass<'local>,
 	msg_ciphertext: JByteArray<'local>,
 	own_seckey_kyber: JString<'local>,
@@ -133,6 +241,33 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseMsg<'local> (
 	pfs_key: JString<'local>,
 	pfs_salt: JString<'local>
 ) -> JString<'local> {
+	parse_message(env, msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, None)
+}
+
+// Shared by parseMsg and parseMsgWithReplayWindow. Nothing is remembered between calls without a window,
+// so replays, gaps and messages arriving out of order are only reported when one is given.
+pub fn parse_message<'local> (
+	mut env: JNIEnv<'local>,
+	msg_ciphertext: JByteArray<'local>,
+	own_seckey_kyber: JString<'local>,
+	remote_pubkey_sig: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>,
+	window: Option<JString<'local>>
+) -> JString<'local> {
+	
+	let mut window: Option<ReplayWindow> = match window {
+		Some(window) => {
+			let window = env.get_string(&window);
+			if window.is_err() { error!(env, "Could not get java variable: window"); }
+			let window: String = window.unwrap().into();
+			match parse_window(&window) {
+				Ok(res) => Some(res),
+				Err(err) => { error!(env, &err); }
+			}
+		},
+		None => None
+	};
 	
 	let msg_ciphertext = env.convert_byte_array(msg_ciphertext);
 	if msg_ciphertext.is_err() { error!(env, "Could not get java variable: msg_ciphertext"); }
@@ -177,11 +312,50 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseMsg<'local> (
 		Err(_) => { error!(env, "pfs_salt invalid"); }
 	};
 	
-	let ((msg_type, msg_text, msg_bytes), new_pfs_key, mdc) = match parse_msg(&msg_ciphertext, &own_seckey_kyber, optional_remote_pubkey_sig, &pfs_key, &pfs_salt) {
+	let msg_ciphertext = match open_expecting(&msg_ciphertext, Kind::Message, &pfs_salt) {
 		Ok(res) => res,
 		Err(err) => { error!(env, &err); }
 	};
 	
+	// Checked before decryption, but only recorded once the message parsed, so garbage can not push real entries out of the window
+	let ciphertext_hash = ReplayWindow::ciphertext_hash(msg_ciphertext);
+	if let Some(window) = &window {
+		if window.contains(&ciphertext_hash) { return replay_status(env, window); }
+	}
+	
+	let (msg, new_pfs_key, mdc) = match parse_msg(msg_ciphertext, &own_seckey_kyber, optional_remote_pubkey_sig, &pfs_key, &pfs_salt) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let ((msg_type, msg_text, msg_bytes), info) = match unframe_msg_info(msg) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not unframe message: {}", err)); }
+	};
+	let sequence = info.sequence;
+	
+	let expires = match info.expires() {
+		Ok(res) => res.map(|expires| expires.to_string()),
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let clock_skew = match sequence {
+		Some(sequence) => match sequence.clock_skew() {
+			Ok(res) => Some(res),
+			Err(err) => { error!(env, &err); }
+		},
+		None => None
+	};
+	let sent = sequence.map(|sequence| sequence.sent.to_string());
+	
+	let (gap, out_of_order) = match (&mut window, sequence) {
+		(Some(window), Some(sequence)) => {
+			if window.contains_counter(sequence.counter) { return replay_status(env, window); }
+			window.record_counter(sequence.counter)
+		},
+		_ => (0, false)
+	};
+	
 	let msg_text = match msg_text {
 		Some(text) => text,
 		None => "".to_string()
@@ -199,9 +373,26 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_parseMsg<'local> (
 		msg_bytes: &msg_bytes,
 		new_pfs_key: &encode(new_pfs_key),
 		mdc: &mdc,
+		counter: sequence.map(|sequence| sequence.counter),
+		sent: sent.as_deref(),
+		clock_skew,
+		expires: expires.as_deref()
+	};
+	
+	let parse_message_json = match &mut window {
+		Some(window) => {
+			window.record(ciphertext_hash);
+			serde_json::to_string(&ParseMessageWithWindow {
+				message: &parse_message,
+				gap,
+				out_of_order,
+				window
+			})
+		},
+		None => serde_json::to_string(&parse_message)
 	};
 	
-	let parse_message_json = match serde_json::to_string(&parse_message) {
+	let parse_message_json = match parse_message_json {
 		Ok(res) => match env.new_string(res) {
 			Ok(res) => res,
 			Err(_) => { error!(env, "Could not create new java string"); }
@@ -217,16 +408,64 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_encryptFile<'local> (
 	_class: JClass<'local>,
 	file: JByteArray<'local>,
 ) -> JString<'local> {
+	encrypt_file_with_options(env, file, None)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_encryptFileWithOptions<'local> (
+	env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	file: JByteArray<'local>,
+	options: JString<'local>
+) -> JString<'local> {
+	encrypt_file_with_options(env, file, Some(options))
+}
+
+// Shared by encryptFile and encryptFileWithOptions, files are only framed when options are given
+fn encrypt_file_with_options<'local> (
+	mut env: JNIEnv<'local>,
+	file: JByteArray<'local>,
+	options: Option<JString<'local>>
+) -> JString<'local> {
 	
 	let file = match env.convert_byte_array(file) {
 		Ok(res) => res,
 		Err(_) => { error!(env, "Could not read file"); }
 	};
 	
+	let options: Option<FrameOptions> = match options {
+		Some(options) => {
+			let options = env.get_string(&options);
+			if options.is_err() { error!(env, "Could not get java variable: options"); }
+			let options: String = options.unwrap().into();
+			match options.as_str() {
+				"" => Some(FrameOptions::default()),
+				_ => match serde_json::from_str(&options) {
+					Ok(res) => Some(res),
+					Err(_) => { error!(env, "options invalid"); }
+				}
+			}
+		},
+		None => None
+	};
+	
+	let file = match &options {
+		Some(options) => match frame_file(&file, options) {
+			Ok(res) => res,
+			Err(err) => { error!(env, &format!("Could not frame file: {}", err)); }
+		},
+		None => file
+	};
+	
 	let (ciphertext, key) = match encrypt_file(&file) {
 		Ok(res) => res,
 		Err(err) => { error!(env, &err); }
 	};
+	// Files with options can only be read by new clients, which also open envelopes
+	let ciphertext = match options {
+		Some(_) => seal(Kind::File, &ciphertext, &key),
+		None => ciphertext
+	};
 	
 	let enc_file = EncryptFile {
 		status: "ok",
@@ -265,11 +504,21 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_decryptFile<'local> (
 		Err(_) => { error!(env, "key invalid"); }
 	};
 	
-	let file = match decrypt_file(&ciphertext, &key) {
+	let ciphertext = match open_expecting(&ciphertext, Kind::File, &key) {
 		Ok(res) => res,
 		Err(err) => { error!(env, &err); }
 	};
 	
+	let file = match decrypt_file(ciphertext, &key) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let file = match unframe_file(file) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not unframe file: {}", err)); }
+	};
+	
 	let file = DecryptFile {
 		status: "ok",
 		file: &encode(file)
@@ -284,3 +533,51 @@ pub extern "C" fn Java_dawn_android_LibraryConnector_decryptFile<'local> (
 	};
 	file_json
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::features::{FEATURE_FRAME, FEATURE_SEQUENCE, FEATURE_TYPED_PAYLOADS, Announcement};
+	use crate::envelope::open_unverified;
+	use crate::payload::{MSG_TYPE_REPLY, MSG_TYPE_LINK_BUNDLE};
+	
+	struct Keys {
+		remote_pubkey_kyber: Vec<u8>,
+		own_seckey_sig: Vec<u8>,
+		pfs_key: Vec<u8>,
+		pfs_salt: Vec<u8>,
+		id: String,
+		mdc_seed: String
+	}
+	
+	fn keys() -> Keys {
+		Keys { remote_pubkey_kyber: kyber_keygen().0, own_seckey_sig: sign_keygen().1, pfs_key: sym_key_gen(), pfs_salt: sym_key_gen(), id: id_gen(), mdc_seed: mdc_gen() }
+	}
+	
+	fn encrypt(keys: &Keys, msg_type: u8, session: Option<&mut Session>, options: Option<&FrameOptions>) -> Result<(Vec<u8>, String, Vec<u8>), String> {
+		encrypt_message((msg_type, Some("hi"), None), &keys.remote_pubkey_kyber, &keys.own_seckey_sig, &keys.pfs_key, &keys.pfs_salt, &keys.id, &keys.mdc_seed, session, options)
+	}
+	
+	#[test]
+	fn plain_send_accepts_any_msg_type() {
+		let keys = keys();
+		for msg_type in [MSG_TYPE_REPLY, MSG_TYPE_LINK_BUNDLE, 100] {
+			let (_, _, ciphertext) = encrypt(&keys, msg_type, None, None).unwrap();
+			let (envelope, _) = open_unverified(&ciphertext, Kind::Message).unwrap();
+			assert!(envelope.is_none());
+		}
+	}
+	
+	#[test]
+	fn session_send_checks_features() {
+		let keys = keys();
+		assert!(encrypt(&keys, MSG_TYPE_REPLY, Some(&mut Session::default()), Some(&FrameOptions::default())).is_err());
+		let mut session = Session::new(Some(Announcement { version: 1, features: FEATURE_FRAME | FEATURE_TYPED_PAYLOADS | FEATURE_ENVELOPE | FEATURE_SEQUENCE }));
+		let (_, _, ciphertext) = encrypt(&keys, MSG_TYPE_REPLY, Some(&mut session), Some(&FrameOptions::default())).unwrap();
+		assert!(open_expecting(&ciphertext, Kind::Message, &keys.pfs_salt).is_ok());
+		assert_eq!(session.next_counter, 1);
+		let options = FrameOptions { ttl: Some(60), ..Default::default() };
+		assert!(encrypt(&keys, MSG_TYPE_REPLY, Some(&mut session), Some(&options)).is_err());
+		assert_eq!(session.next_counter, 1);
+	}
+}
@@ -0,0 +1,113 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::convert::TryFrom;
+use serde::{Serialize, Deserialize};
+
+// Padded data is laid out as the big endian u32 length of the original data, the data itself and zero bytes up to the padded length
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+// Block sizes come from the app's options, bigger ones would allow allocating arbitrary amounts of memory
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaddingPolicy {
+	#[default]
+	None,
+	// Padmé buckets: leaks at most O(log log n) bits about the length while keeping the overhead below 12%
+	Padme,
+	// Pads to the next multiple of the given block size
+	Block(usize)
+}
+
+fn padme_length(length: usize) -> usize {
+	if length < 2 { return length; }
+	let e = usize::BITS - 1 - length.leading_zeros();
+	let s = u32::BITS - e.leading_zeros();
+	let last_bits = e - s;
+	let bit_mask = (1usize << last_bits) - 1;
+	(length + bit_mask) & !bit_mask
+}
+
+impl PaddingPolicy {
+	pub fn padded_length(&self, length: usize) -> Result<usize, String> {
+		match self {
+			PaddingPolicy::None => Ok(length),
+			PaddingPolicy::Padme => Ok(padme_length(length)),
+			PaddingPolicy::Block(0) => Err("Padding block size must not be zero".to_string()),
+			PaddingPolicy::Block(size) if *size > MAX_BLOCK_SIZE => Err(format!("Padding block size must not exceed {}", MAX_BLOCK_SIZE)),
+			PaddingPolicy::Block(size) => length.div_ceil(*size).checked_mul(*size).ok_or("Padded length too large".to_string())
+		}
+	}
+}
+
+pub fn pad(data: &[u8], policy: &PaddingPolicy) -> Result<Vec<u8>, String> {
+	let length = match u32::try_from(data.len()) {
+		Ok(length) => length,
+		Err(_) => return Err("Data too large to be padded".to_string())
+	};
+	let padded_length = policy.padded_length(data.len() + LENGTH_PREFIX_SIZE)?;
+	let mut padded = Vec::with_capacity(padded_length);
+	padded.extend_from_slice(&length.to_be_bytes());
+	padded.extend_from_slice(data);
+	padded.resize(padded_length, 0);
+	Ok(padded)
+}
+
+pub fn unpad(padded: &[u8]) -> Result<Vec<u8>, String> {
+	if padded.len() < LENGTH_PREFIX_SIZE { return Err("Padded data too short".to_string()); }
+	let mut length = [0u8; LENGTH_PREFIX_SIZE];
+	length.copy_from_slice(&padded[..LENGTH_PREFIX_SIZE]);
+	let length = u32::from_be_bytes(length) as usize;
+	if length > padded.len() - LENGTH_PREFIX_SIZE { return Err("Padding length invalid".to_string()); }
+	Ok(padded[LENGTH_PREFIX_SIZE..LENGTH_PREFIX_SIZE + length].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn pad_roundtrip() {
+		for length in [0usize, 1, 3, 100, 1000, 5000, 65537] {
+			let data: Vec<u8> = (0..length).map(|i| i as u8).collect();
+			for policy in [PaddingPolicy::None, PaddingPolicy::Padme, PaddingPolicy::Block(256)] {
+				let padded = pad(&data, &policy).unwrap();
+				assert_eq!(padded.len(), policy.padded_length(length + LENGTH_PREFIX_SIZE).unwrap());
+				assert_eq!(unpad(&padded).unwrap(), data);
+			}
+		}
+	}
+	
+	#[test]
+	fn padded_lengths() {
+		assert_eq!(PaddingPolicy::Padme.padded_length(1000).unwrap(), 1024);
+		assert_eq!(PaddingPolicy::Padme.padded_length(1).unwrap(), 1);
+		assert_eq!(PaddingPolicy::Block(256).padded_length(257).unwrap(), 512);
+		assert!(PaddingPolicy::Block(0).padded_length(1).is_err());
+		assert!(PaddingPolicy::Block(MAX_BLOCK_SIZE + 1).padded_length(1).is_err());
+	}
+	
+	#[test]
+	fn unpad_rejects_invalid_length() {
+		assert!(unpad(&[0, 0]).is_err());
+		assert!(unpad(&[0, 0, 0, 5, 1, 2]).is_err());
+	}
+}
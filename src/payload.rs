@@ -0,0 +1,350 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::convert::TryFrom;
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::jshort;
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use serde::{Serialize, Deserialize};
+use crate::attachments::AttachmentDescriptor;
+use crate::groups::{GroupUpdate, SenderKeyDistribution, GroupOperation};
+use crate::rotation::KeyRotation;
+use crate::init::InitRejection;
+use crate::linking::LinkBundle;
+use crate::{Error, EncodePayload, DecodePayload};
+use crate::error;
+
+pub const MSG_TYPE_TEXT: u8 = 0;
+pub const MSG_TYPE_REPLY: u8 = 1;
+pub const MSG_TYPE_EDIT: u8 = 2;
+pub const MSG_TYPE_DELETE: u8 = 3;
+pub const MSG_TYPE_REACTION: u8 = 4;
+pub const MSG_TYPE_READ_RECEIPT: u8 = 5;
+pub const MSG_TYPE_DELIVERY_RECEIPT: u8 = 6;
+pub const MSG_TYPE_TYPING: u8 = 7;
+pub const MSG_TYPE_ATTACHMENT: u8 = 8;
+pub const MSG_TYPE_PROFILE_UPDATE: u8 = 9;
+pub const MSG_TYPE_GROUP_UPDATE: u8 = 10;
+pub const MSG_TYPE_GROUP_KEY: u8 = 11;
+pub const MSG_TYPE_GROUP_OPERATION: u8 = 12;
+pub const MSG_TYPE_KEY_ROTATION: u8 = 13;
+pub const MSG_TYPE_INIT_REJECTION: u8 = 14;
+pub const MSG_TYPE_EXPIRY_TIMER: u8 = 15;
+// only sent from the primary device to a newly linked one, taken from the top of the range next to the frame and fragment types
+pub const MSG_TYPE_LINK_BUNDLE: u8 = 254;
+
+pub type MsgTriple = (u8, Option<String>, Option<Vec<u8>>);
+
+// Plain text messages are readable by old clients, all other payload types need FEATURE_TYPED_PAYLOADS
+pub fn is_typed(msg_type: u8) -> bool {
+	matches!(msg_type, MSG_TYPE_REPLY..=MSG_TYPE_EXPIRY_TIMER | MSG_TYPE_LINK_BUNDLE)
+}
+
+// Typed representation of the (msg_type, msg_text, msg_bytes) triple that is passed to send_msg and returned by parse_msg.
+// The main text of a message (if any) is carried in msg_text, all other fields are carried as json in msg_bytes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Payload {
+	Text { text: String },
+	Reply { reply_to: String, text: String },
+	Edit { target: String, text: String },
+	Delete { target: String },
+	Reaction { target: String, reaction: String, remove: bool },
+	ReadReceipt { mdcs: Vec<String> },
+	DeliveryReceipt { mdcs: Vec<String> },
+	Typing { active: bool },
+	Attachment(AttachmentDescriptor),
+	ProfileUpdate { name: Option<String>, about: Option<String>, avatar: Option<String> },
+	GroupUpdate(GroupUpdate),
+	GroupKey(SenderKeyDistribution),
+	GroupOperation(GroupOperation),
+	KeyRotation(KeyRotation),
+	InitRejection(InitRejection),
+	// Changes the default ttl of the conversation, none turns disappearing messages off
+	ExpiryTimer { ttl: Option<u64> },
+	LinkBundle(LinkBundle)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Target { target: String }
+
+#[derive(Serialize, Deserialize)]
+struct ReplyTo { reply_to: String }
+
+#[derive(Serialize, Deserialize)]
+struct Reaction { target: String, remove: bool }
+
+#[derive(Serialize, Deserialize)]
+struct Mdcs { mdcs: Vec<String> }
+
+#[derive(Serialize, Deserialize)]
+struct Typing { active: bool }
+
+#[derive(Serialize, Deserialize)]
+struct ProfileUpdate { name: Option<String>, about: Option<String>, avatar: Option<String> }
+
+#[derive(Serialize, Deserialize)]
+struct ExpiryTimer { ttl: Option<u64> }
+
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+	serde_json::to_vec(value).map_err(|_| "Could not serialize payload".to_string())
+}
+
+fn from_json<'a, T: Deserialize<'a>>(msg_bytes: Option<&'a [u8]>) -> Result<T, String> {
+	match msg_bytes {
+		Some(bytes) => serde_json::from_slice(bytes).map_err(|_| "Payload bytes invalid".to_string()),
+		None => Err("Payload bytes missing".to_string())
+	}
+}
+
+impl Payload {
+	pub fn msg_type(&self) -> u8 {
+		match self {
+			Payload::Text { .. } => MSG_TYPE_TEXT,
+			Payload::Reply { .. } => MSG_TYPE_REPLY,
+			Payload::Edit { .. } => MSG_TYPE_EDIT,
+			Payload::Delete { .. } => MSG_TYPE_DELETE,
+			Payload::Reaction { .. } => MSG_TYPE_REACTION,
+			Payload::ReadReceipt { .. } => MSG_TYPE_READ_RECEIPT,
+			Payload::DeliveryReceipt { .. } => MSG_TYPE_DELIVERY_RECEIPT,
+			Payload::Typing { .. } => MSG_TYPE_TYPING,
+			Payload::Attachment { .. } => MSG_TYPE_ATTACHMENT,
+			Payload::ProfileUpdate { .. } => MSG_TYPE_PROFILE_UPDATE,
+			Payload::GroupUpdate(_) => MSG_TYPE_GROUP_UPDATE,
+			Payload::GroupKey(_) => MSG_TYPE_GROUP_KEY,
+			Payload::GroupOperation(_) => MSG_TYPE_GROUP_OPERATION,
+			Payload::KeyRotation(_) => MSG_TYPE_KEY_ROTATION,
+			Payload::InitRejection(_) => MSG_TYPE_INIT_REJECTION,
+			Payload::ExpiryTimer { .. } => MSG_TYPE_EXPIRY_TIMER,
+			Payload::LinkBundle(_) => MSG_TYPE_LINK_BUNDLE
+		}
+	}
+	
+	// Returns the triple in the form expected by send_msg (apart from borrowing)
+	pub fn encode(&self) -> Result<MsgTriple, String> {
+		let (msg_text, msg_bytes) = match self {
+			Payload::Text { text } => (Some(text.clone()), None),
+			Payload::Reply { reply_to, text } => (Some(text.clone()), Some(to_json(&ReplyTo { reply_to: reply_to.clone() })?)),
+			Payload::Edit { target, text } => (Some(text.clone()), Some(to_json(&Target { target: target.clone() })?)),
+			Payload::Delete { target } => (None, Some(to_json(&Target { target: target.clone() })?)),
+			Payload::Reaction { target, reaction, remove } => (Some(reaction.clone()), Some(to_json(&Reaction { target: target.clone(), remove: *remove })?)),
+			Payload::ReadReceipt { mdcs } | Payload::DeliveryReceipt { mdcs } => (None, Some(to_json(&Mdcs { mdcs: mdcs.clone() })?)),
+			Payload::Typing { active } => (None, Some(to_json(&Typing { active: *active })?)),
+			Payload::Attachment(descriptor) => (None, Some(to_json(descriptor)?)),
+			Payload::ProfileUpdate { name, about, avatar } => (None, Some(to_json(&ProfileUpdate { name: name.clone(), about: about.clone(), avatar: avatar.clone() })?)),
+			Payload::GroupUpdate(update) => (None, Some(to_json(update)?)),
+			Payload::GroupKey(distribution) => (None, Some(to_json(distribution)?)),
+			Payload::GroupOperation(operation) => (None, Some(to_json(operation)?)),
+			Payload::KeyRotation(rotation) => (None, Some(to_json(rotation)?)),
+			Payload::InitRejection(rejection) => (None, Some(to_json(rejection)?)),
+			Payload::ExpiryTimer { ttl } => (None, Some(to_json(&ExpiryTimer { ttl: *ttl })?)),
+			Payload::LinkBundle(bundle) => (None, Some(to_json(bundle)?))
+		};
+		// send_msg treats an empty string the same as no text at all, so do the same here
+		let msg_text = msg_text.filter(|text| !text.is_empty());
+		Ok((self.msg_type(), msg_text, msg_bytes))
+	}
+	
+	pub fn decode(msg_type: u8, msg_text: Option<&str>, msg_bytes: Option<&[u8]>) -> Result<Payload, String> {
+		let text = msg_text.unwrap_or("").to_string();
+		let payload = match msg_type {
+			MSG_TYPE_TEXT => Payload::Text { text },
+			MSG_TYPE_REPLY => {
+				let reply_to: ReplyTo = from_json(msg_bytes)?;
+				Payload::Reply { reply_to: reply_to.reply_to, text }
+			},
+			MSG_TYPE_EDIT => {
+				let target: Target = from_json(msg_bytes)?;
+				Payload::Edit { target: target.target, text }
+			},
+			MSG_TYPE_DELETE => {
+				let target: Target = from_json(msg_bytes)?;
+				Payload::Delete { target: target.target }
+			},
+			MSG_TYPE_REACTION => {
+				let reaction: Reaction = from_json(msg_bytes)?;
+				Payload::Reaction { target: reaction.target, reaction: text, remove: reaction.remove }
+			},
+			MSG_TYPE_READ_RECEIPT => {
+				let mdcs: Mdcs = from_json(msg_bytes)?;
+				Payload::ReadReceipt { mdcs: mdcs.mdcs }
+			},
+			MSG_TYPE_DELIVERY_RECEIPT => {
+				let mdcs: Mdcs = from_json(msg_bytes)?;
+				Payload::DeliveryReceipt { mdcs: mdcs.mdcs }
+			},
+			MSG_TYPE_TYPING => {
+				let typing: Typing = from_json(msg_bytes)?;
+				Payload::Typing { active: typing.active }
+			},
+			MSG_TYPE_ATTACHMENT => Payload::Attachment(from_json(msg_bytes)?),
+			MSG_TYPE_PROFILE_UPDATE => {
+				let profile_update: ProfileUpdate = from_json(msg_bytes)?;
+				Payload::ProfileUpdate { name: profile_update.name, about: profile_update.about, avatar: profile_update.avatar }
+			},
+			MSG_TYPE_GROUP_UPDATE => Payload::GroupUpdate(from_json(msg_bytes)?),
+			MSG_TYPE_GROUP_KEY => Payload::GroupKey(from_json(msg_bytes)?),
+			MSG_TYPE_GROUP_OPERATION => Payload::GroupOperation(from_json(msg_bytes)?),
+			MSG_TYPE_KEY_ROTATION => Payload::KeyRotation(from_json(msg_bytes)?),
+			MSG_TYPE_INIT_REJECTION => Payload::InitRejection(from_json(msg_bytes)?),
+			MSG_TYPE_EXPIRY_TIMER => {
+				let expiry_timer: ExpiryTimer = from_json(msg_bytes)?;
+				Payload::ExpiryTimer { ttl: expiry_timer.ttl }
+			},
+			MSG_TYPE_LINK_BUNDLE => Payload::LinkBundle(from_json(msg_bytes)?),
+			_ => return Err(format!("Unknown message type: {}", msg_type))
+		};
+		Ok(payload)
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_encodePayload<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	payload: JString<'local>
+) -> JString<'local> {
+	
+	let payload = env.get_string(&payload);
+	if payload.is_err() { error!(env, "Could not get java variable: payload"); }
+	let payload: String = payload.unwrap().into();
+	let payload: Payload = match serde_json::from_str(&payload) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "payload invalid"); }
+	};
+	
+	let (msg_type, msg_text, msg_bytes) = match payload.encode() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not encode payload: {}", err)); }
+	};
+	
+	let msg_text = msg_text.unwrap_or_default();
+	let msg_bytes = match msg_bytes {
+		Some(bytes) => BASE64.encode(bytes),
+		None => "".to_string()
+	};
+	
+	let encode_payload = EncodePayload {
+		status: "ok",
+		msg_type,
+		msg_text: &msg_text,
+		msg_bytes: &msg_bytes
+	};
+	
+	let encode_payload_json = match serde_json::to_string(&encode_payload) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	encode_payload_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_decodePayload<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	msg_type: jshort,
+	msg_text: JString<'local>,
+	msg_bytes: JByteArray<'local>
+) -> JString<'local> {
+	
+	let msg_type = match u8::try_from(msg_type) {
+		Ok(n) => n,
+		Err(_) => { error!(env, &format!("Invalid message type provided: {}", msg_type)); },
+	};
+	
+	let msg_text = env.get_string(&msg_text);
+	if msg_text.is_err() { error!(env, "Could not get java variable: msg_text"); }
+	let msg_text: String = msg_text.unwrap().into();
+	let msg_text = match msg_text.as_str() {
+		"" => None,
+		_ => Some(msg_text.as_str())
+	};
+	
+	let msg_bytes = env.convert_byte_array(msg_bytes);
+	if msg_bytes.is_err() { error!(env, "Could not get java variable: msg_bytes"); }
+	let msg_bytes = msg_bytes.unwrap();
+	let msg_bytes = match msg_bytes.len() {
+		0 => None,
+		_ => Some(msg_bytes.as_slice())
+	};
+	
+	let payload = match Payload::decode(msg_type, msg_text, msg_bytes) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not decode payload: {}", err)); }
+	};
+	
+	let decode_payload = DecodePayload {
+		status: "ok",
+		msg_type,
+		payload: &payload
+	};
+	
+	let decode_payload_json = match serde_json::to_string(&decode_payload) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	decode_payload_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn roundtrip(payload: Payload) {
+		let (msg_type, msg_text, msg_bytes) = payload.encode().unwrap();
+		assert_eq!(Payload::decode(msg_type, msg_text.as_deref(), msg_bytes.as_deref()).unwrap(), payload);
+	}
+	
+	#[test]
+	fn payload_roundtrip() {
+		roundtrip(Payload::Text { text: "hi".to_string() });
+		roundtrip(Payload::Reply { reply_to: "mdc".to_string(), text: "hi".to_string() });
+		roundtrip(Payload::Reaction { target: "mdc".to_string(), reaction: "+1".to_string(), remove: true });
+		roundtrip(Payload::ReadReceipt { mdcs: vec!["a".to_string(), "b".to_string()] });
+		roundtrip(Payload::ProfileUpdate { name: Some("alice".to_string()), about: None, avatar: None });
+		roundtrip(Payload::ExpiryTimer { ttl: Some(60) });
+	}
+	
+	#[test]
+	fn empty_text_is_sent_as_no_text() {
+		assert_eq!(Payload::Text { text: "".to_string() }.encode().unwrap(), (MSG_TYPE_TEXT, None, None));
+		roundtrip(Payload::Text { text: "".to_string() });
+	}
+	
+	#[test]
+	fn rejects_invalid_payloads() {
+		assert!(Payload::decode(MSG_TYPE_DELETE, None, None).is_err());
+		assert!(Payload::decode(MSG_TYPE_DELETE, None, Some(b"{}")).is_err());
+		assert!(Payload::decode(200, None, None).is_err());
+	}
+	
+	#[test]
+	fn typed_message_types() {
+		assert!(!is_typed(MSG_TYPE_TEXT));
+		assert!(is_typed(MSG_TYPE_REPLY) && is_typed(MSG_TYPE_EXPIRY_TIMER) && is_typed(MSG_TYPE_LINK_BUNDLE));
+		assert!(!is_typed(MSG_TYPE_EXPIRY_TIMER + 1));
+	}
+}
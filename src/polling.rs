@@ -0,0 +1,128 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::collections::HashSet;
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use crate::{Error, PollContact, PollEntry, PollPlan};
+use crate::error;
+
+// Derives every temporary id and predictable mdc that has to be queried for the given contacts since the given timestamp.
+// Entries are ordered by timestamp (and by contact order within one timestamp); duplicate (temp_id, mdc) pairs are only returned once.
+fn build_poll_plan(contacts: &[PollContact], timestamp: &str) -> Result<Vec<PollEntry>, String> {
+	let timestamps = get_all_timestamps_since(timestamp)?;
+	let mut seen = HashSet::new();
+	let mut entries = Vec::new();
+	for timestamp in timestamps {
+		for contact in contacts {
+			let temp_id = get_custom_temp_id(&contact.id, &timestamp)?;
+			let mdc = predictable_mdc_gen(&contact.mdc_seed, &temp_id);
+			if !seen.insert((temp_id.clone(), mdc.clone())) { continue; }
+			entries.push(PollEntry {
+				contact_id: contact.id.clone(),
+				timestamp: timestamp.clone(),
+				temp_id,
+				mdc
+			});
+		}
+	}
+	Ok(entries)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_buildPollPlan<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	contacts: JString<'local>,
+	timestamp: JString<'local>
+) -> JString<'local> {
+	
+	let contacts = env.get_string(&contacts);
+	if contacts.is_err() { error!(env, "Could not get java variable: contacts"); }
+	let contacts: String = contacts.unwrap().into();
+	let contacts: Vec<PollContact> = match serde_json::from_str(&contacts) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "contacts invalid"); }
+	};
+	
+	let timestamp = env.get_string(&timestamp);
+	if timestamp.is_err() { error!(env, "Could not get java variable: timestamp"); }
+	let timestamp: String = timestamp.unwrap().into();
+	
+	let entries = match build_poll_plan(&contacts, &timestamp) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not build poll plan: {}", err)); }
+	};
+	
+	let poll_plan = PollPlan {
+		status: "ok",
+		entries
+	};
+	
+	let poll_plan_json = match serde_json::to_string(&poll_plan) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	poll_plan_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::handles::timestamp_value;
+	
+	fn contact(id: &str, mdc_seed: &str) -> PollContact {
+		PollContact { id: id.to_string(), mdc_seed: mdc_seed.to_string() }
+	}
+	
+	fn since() -> String {
+		(timestamp_value(&get_current_timestamp().unwrap()).unwrap() - 2).to_string()
+	}
+	
+	#[test]
+	fn entries_are_ordered_by_timestamp_then_contact() {
+		let since = since();
+		let timestamps = get_all_timestamps_since(&since).unwrap();
+		assert!(timestamps.len() > 1);
+		let entries = build_poll_plan(&[contact("alice", "a"), contact("bob", "b")], &since).unwrap();
+		assert_eq!(entries.len(), 2 * timestamps.len());
+		for (index, entry) in entries.iter().enumerate() {
+			assert_eq!(entry.timestamp, timestamps[index / 2]);
+			assert_eq!(entry.contact_id, ["alice", "bob"][index % 2]);
+			assert_eq!(entry.temp_id, get_custom_temp_id(&entry.contact_id, &entry.timestamp).unwrap());
+			assert_eq!(entry.mdc, predictable_mdc_gen(["a", "b"][index % 2], &entry.temp_id));
+		}
+	}
+	
+	#[test]
+	fn duplicate_pairs_are_returned_once() {
+		let since = since();
+		let timestamps = get_all_timestamps_since(&since).unwrap();
+		// the same contact listed twice yields the same (temp_id, mdc) pairs, another mdc seed does not
+		let entries = build_poll_plan(&[contact("alice", "a"), contact("alice", "a"), contact("alice", "other")], &since).unwrap();
+		assert_eq!(entries.len(), 2 * timestamps.len());
+		let pairs: HashSet<(&str, &str)> = entries.iter().map(|entry| (entry.temp_id.as_str(), entry.mdc.as_str())).collect();
+		assert_eq!(pairs.len(), entries.len());
+		assert!(build_poll_plan(&[], &since).unwrap().is_empty());
+	}
+}
@@ -0,0 +1,130 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::convert::TryInto;
+use dawn_stdlib::*;
+use crate::handles::timestamp_value;
+use crate::signatures::signed_data;
+
+// Hashcash style stamps that are prepended to init request ciphertexts, laid out as magic, difficulty, timestamp and nonce.
+// The stamp commits to the init public key of the recipient handle and to the ciphertext, so it can neither be moved to another handle nor to another request.
+const STAMP_MAGIC: &[u8] = b"DWNP";
+const STAMP_SIZE: usize = 4 + 1 + 8 + 8;
+// 2^32 hashes on average would already take hours on a phone
+pub const MAX_POW_DIFFICULTY: u8 = 32;
+
+pub struct Stamp {
+	pub difficulty: u8,
+	pub timestamp: u64,
+	nonce: u64
+}
+
+fn stamp_hash(handle_pubkey_kyber: &[u8], timestamp: u64, ciphertext_hash: &[u8], nonce: u64) -> Vec<u8> {
+	hash(&signed_data(&[b"dawn-pow", &hash(handle_pubkey_kyber), &timestamp.to_be_bytes(), ciphertext_hash, &nonce.to_be_bytes()]))
+}
+
+fn leading_zero_bits(data: &[u8]) -> u32 {
+	let mut bits = 0;
+	for byte in data {
+		bits += byte.leading_zeros();
+		if *byte != 0 { break; }
+	}
+	bits
+}
+
+pub fn add_stamp(ciphertext: &[u8], handle_pubkey_kyber: &[u8], difficulty: u8) -> Result<Vec<u8>, String> {
+	if difficulty > MAX_POW_DIFFICULTY { return Err(format!("Proof of work difficulty must not exceed {}", MAX_POW_DIFFICULTY)); }
+	let timestamp = timestamp_value(&get_current_timestamp()?)?;
+	let ciphertext_hash = hash(ciphertext);
+	let mut nonce: u64 = 0;
+	while leading_zero_bits(&stamp_hash(handle_pubkey_kyber, timestamp, &ciphertext_hash, nonce)) < difficulty as u32 {
+		nonce += 1;
+	}
+	let mut stamped = Vec::with_capacity(STAMP_SIZE + ciphertext.len());
+	stamped.extend_from_slice(STAMP_MAGIC);
+	stamped.push(difficulty);
+	stamped.extend_from_slice(&timestamp.to_be_bytes());
+	stamped.extend_from_slice(&nonce.to_be_bytes());
+	stamped.extend_from_slice(ciphertext);
+	Ok(stamped)
+}
+
+// Ciphertexts without a stamp are returned unchanged
+pub fn split_stamp(data: &[u8]) -> (Option<Stamp>, &[u8]) {
+	if data.len() < STAMP_SIZE || !data.starts_with(STAMP_MAGIC) { return (None, data); }
+	let stamp = Stamp {
+		difficulty: data[4],
+		timestamp: u64::from_be_bytes(data[5..13].try_into().unwrap()),
+		nonce: u64::from_be_bytes(data[13..21].try_into().unwrap())
+	};
+	(Some(stamp), &data[STAMP_SIZE..])
+}
+
+// Costs two hashes, so it can run before any decapsulation. max_age is given in timestamp units.
+pub fn verify_stamp(stamp: &Stamp, ciphertext: &[u8], handle_pubkey_kyber: &[u8], min_difficulty: u8, max_age: Option<u64>) -> Result<(), String> {
+	if stamp.difficulty < min_difficulty { return Err("Proof of work difficulty too low".to_string()); }
+	let now = timestamp_value(&get_current_timestamp()?)?;
+	// allow one unit of clock skew
+	if stamp.timestamp > now + 1 { return Err("Proof of work timestamp lies in the future".to_string()); }
+	if let Some(max_age) = max_age {
+		if now.saturating_sub(stamp.timestamp) > max_age { return Err("Proof of work expired".to_string()); }
+	}
+	if leading_zero_bits(&stamp_hash(handle_pubkey_kyber, stamp.timestamp, &hash(ciphertext), stamp.nonce)) < stamp.difficulty as u32 {
+		return Err("Proof of work invalid".to_string());
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn counts_leading_zero_bits() {
+		assert_eq!(leading_zero_bits(&[0xff]), 0);
+		assert_eq!(leading_zero_bits(&[0x00, 0x10]), 11);
+		assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+	}
+	
+	#[test]
+	fn stamp_roundtrip() {
+		let stamped = add_stamp(b"ciphertext", b"pubkey", 8).unwrap();
+		let (stamp, ciphertext) = split_stamp(&stamped);
+		let stamp = stamp.unwrap();
+		assert_eq!(ciphertext, b"ciphertext");
+		assert_eq!(stamp.difficulty, 8);
+		assert!(verify_stamp(&stamp, ciphertext, b"pubkey", 8, Some(1)).is_ok());
+		assert!(verify_stamp(&stamp, b"other", b"pubkey", 8, None).is_err());
+	}
+	
+	#[test]
+	fn rejects_low_difficulty() {
+		let stamped = add_stamp(b"ciphertext", b"pubkey", 4).unwrap();
+		let stamp = split_stamp(&stamped).0.unwrap();
+		assert!(verify_stamp(&stamp, b"ciphertext", b"pubkey", 5, None).is_err());
+		assert!(add_stamp(b"ciphertext", b"pubkey", MAX_POW_DIFFICULTY + 1).is_err());
+	}
+	
+	#[test]
+	fn unstamped_data_passes_through() {
+		let (stamp, data) = split_stamp(b"short");
+		assert!(stamp.is_none());
+		assert_eq!(data, b"short");
+	}
+}
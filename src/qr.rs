@@ -0,0 +1,446 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::collections::BTreeMap;
+use dawn_stdlib::hash;
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use hex::encode;
+use serde::{Serialize, Deserialize};
+use qrcode::{QrCode, EcLevel, Color};
+use qrcode::types::QrError;
+use qrcode::render::svg;
+use crate::{Error, GenQrCode, GenQrCodeSequence, AddScannedQrPart, ParseHandle};
+use crate::uri::{URI_SCHEME, parse_handle_uri};
+use crate::handles::handle_info;
+use crate::fragments::STATUS_INCOMPLETE;
+use crate::error;
+
+// Medium error correction survives smudged or partly covered screens without making handles too dense to scan
+const EC_LEVEL: EcLevel = EcLevel::M;
+// Quiet zone in modules, as required by the QR specification
+const QUIET_ZONE: usize = 4;
+// Size of one module in pixels for PNG and SVG output
+const MODULE_SIZE: usize = 8;
+// Byte mode capacity of the largest QR code (version 40) at EC_LEVEL. Full handles with kyber keys are larger than that,
+// they are shown as a sequence of codes made by split_qr_sequence instead.
+pub const MAX_QR_BYTES: usize = 2331;
+// Parts of a sequence are "dawn-qr1:<index>/<count>:<digest>:<base64 chunk>". The digest tells which handle a part belongs to
+// and is checked once the handle is reassembled, the parts can be scanned in any order.
+const QR_PART_PREFIX: &str = "dawn-qr1:";
+// Handle bytes per part. Well below MAX_QR_BYTES, as dense codes are hard to scan from the screen of another phone.
+const QR_PART_BYTES: usize = 1024;
+const MAX_QR_PARTS: usize = 64;
+const QR_DIGEST_SIZE: usize = 8;
+
+fn qr_code(content: &str) -> Result<QrCode, String> {
+	let too_long = format!("Content too long for a QR code: {} bytes, at most {} bytes fit", content.len(), MAX_QR_BYTES);
+	if content.len() > MAX_QR_BYTES { return Err(too_long); }
+	match QrCode::with_error_correction_level(content.as_bytes(), EC_LEVEL) {
+		Ok(code) => Ok(code),
+		Err(QrError::DataTooLong) => Err(too_long),
+		Err(error) => Err(format!("Could not encode QR code: {}", error))
+	}
+}
+
+pub fn qr_matrix(content: &str) -> Result<(usize, Vec<bool>), String> {
+	let code = qr_code(content)?;
+	let modules = code.to_colors().iter().map(|color| *color == Color::Dark).collect();
+	Ok((code.width(), modules))
+}
+
+pub fn qr_png(content: &str) -> Result<Vec<u8>, String> {
+	let (width, modules) = qr_matrix(content)?;
+	let size = (width + 2 * QUIET_ZONE) * MODULE_SIZE;
+	// 8 bit grayscale, white background
+	let mut pixels = vec![255u8; size * size];
+	for y in 0..width {
+		for x in 0..width {
+			if !modules[y * width + x] { continue; }
+			for py in 0..MODULE_SIZE {
+				let row = ((y + QUIET_ZONE) * MODULE_SIZE + py) * size;
+				let start = row + (x + QUIET_ZONE) * MODULE_SIZE;
+				pixels[start..start + MODULE_SIZE].fill(0);
+			}
+		}
+	}
+	let mut png = Vec::new();
+	let mut encoder = png::Encoder::new(&mut png, size as u32, size as u32);
+	encoder.set_color(png::ColorType::Grayscale);
+	encoder.set_depth(png::BitDepth::Eight);
+	let mut writer = match encoder.write_header() {
+		Ok(writer) => writer,
+		Err(error) => return Err(format!("Could not write PNG header: {}", error))
+	};
+	if let Err(error) = writer.write_image_data(&pixels) { return Err(format!("Could not write PNG data: {}", error)); }
+	if let Err(error) = writer.finish() { return Err(format!("Could not finish PNG: {}", error)); }
+	Ok(png)
+}
+
+pub fn qr_svg(content: &str) -> Result<String, String> {
+	let code = qr_code(content)?;
+	Ok(code.render::<svg::Color>().quiet_zone(true).module_dimensions(MODULE_SIZE as u32, MODULE_SIZE as u32).build())
+}
+
+// Scanners may add line breaks or surrounding whitespace and some re-add base64 padding
+pub fn decode_scanned_handle(scanned: &str) -> Result<Vec<u8>, String> {
+	let handle: String = scanned.chars().filter(|c| !c.is_whitespace()).collect();
+	if handle.starts_with(URI_SCHEME) { return parse_handle_uri(&handle).map(|handle_uri| handle_uri.handle); }
+	if handle.starts_with(QR_PART_PREFIX) { return Err("Scanned text is part of a QR code sequence".to_string()); }
+	match BASE64.decode(handle.trim_end_matches('=')) {
+		Ok(handle) => Ok(handle),
+		Err(_) => Err("Scanned text is not a handle".to_string())
+	}
+}
+
+fn qr_digest(handle: &[u8]) -> String {
+	encode(&hash(handle)[..QR_DIGEST_SIZE])
+}
+
+pub fn split_qr_sequence(handle: &[u8]) -> Result<Vec<String>, String> {
+	if handle.is_empty() { return Err("Handle empty".to_string()); }
+	let chunks: Vec<&[u8]> = handle.chunks(QR_PART_BYTES).collect();
+	if chunks.len() > MAX_QR_PARTS { return Err(format!("Handle too long for a QR code sequence: {} bytes, at most {} bytes fit", handle.len(), MAX_QR_PARTS * QR_PART_BYTES)); }
+	let digest = qr_digest(handle);
+	Ok(chunks.iter().enumerate().map(|(index, chunk)| format!("{}{}/{}:{}:{}", QR_PART_PREFIX, index, chunks.len(), digest, BASE64.encode(chunk))).collect())
+}
+
+// Parts scanned so far, kept by the app while scanning and passed in on every call ("" to start)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct QrSequence {
+	pub digest: String,
+	pub count: usize,
+	// base64 chunks keyed by their index
+	pub parts: BTreeMap<usize, String>
+}
+
+impl QrSequence {
+	// Returns the handle once all parts are scanned and starts over afterwards.
+	// A part of another handle starts over as well, so a sequence scanned halfway does not block the next one.
+	pub fn add(&mut self, scanned: &str) -> Result<Option<Vec<u8>>, String> {
+		let part: String = scanned.chars().filter(|c| !c.is_whitespace()).collect();
+		let part = match part.strip_prefix(QR_PART_PREFIX) {
+			Some(part) => part,
+			None => return Err("Scanned text is not part of a QR code sequence".to_string())
+		};
+		let mut fields = part.splitn(3, ':');
+		let (position, digest, chunk) = match (fields.next(), fields.next(), fields.next()) {
+			(Some(position), Some(digest), Some(chunk)) => (position, digest, chunk),
+			_ => return Err("QR code part invalid".to_string())
+		};
+		let (index, count) = match position.split_once('/').map(|(index, count)| (index.parse::<usize>(), count.parse::<usize>())) {
+			Some((Ok(index), Ok(count))) if index < count && count <= MAX_QR_PARTS => (index, count),
+			_ => return Err("QR code part position invalid".to_string())
+		};
+		if digest.len() != 2 * QR_DIGEST_SIZE || BASE64.decode(chunk).is_err() { return Err("QR code part invalid".to_string()); }
+		if digest != self.digest || count != self.count {
+			*self = QrSequence { digest: digest.to_string(), count, parts: BTreeMap::new() };
+		}
+		self.parts.insert(index, chunk.to_string());
+		if self.parts.len() < self.count { return Ok(None); }
+		
+		let mut handle = Vec::new();
+		for chunk in self.parts.values() {
+			// checked when the part was added
+			handle.extend_from_slice(&BASE64.decode(chunk).map_err(|_| "QR code part invalid".to_string())?);
+		}
+		let matches = qr_digest(&handle) == self.digest;
+		*self = QrSequence::default();
+		match matches {
+			true => Ok(Some(handle)),
+			false => Err("Reassembled handle does not match its digest".to_string())
+		}
+	}
+	
+	pub fn missing(&self) -> Vec<usize> {
+		(0..self.count).filter(|index| !self.parts.contains_key(index)).collect()
+	}
+}
+
+fn parse_qr_sequence(sequence: &str) -> Result<QrSequence, String> {
+	match sequence {
+		"" => Ok(QrSequence::default()),
+		_ => serde_json::from_str(sequence).map_err(|_| "sequence invalid".to_string())
+	}
+}
+
+// matrix: one line of 0 and 1 per row of modules, without quiet zone
+// png: base64 encoded image
+// svg: svg document
+fn render_qr(content: &str, format: &str) -> Result<String, String> {
+	match format {
+		"matrix" => qr_matrix(content).map(|(width, modules)| modules.chunks(width).map(|row| row.iter().map(|dark| if *dark { '1' } else { '0' }).collect::<String>()).collect::<Vec<String>>().join("\n")),
+		"png" => qr_png(content).map(|png| BASE64.encode(png)),
+		"svg" => qr_svg(content),
+		_ => Err("format invalid".to_string())
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_genQrCode<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	content: JString<'local>,
+	format: JString<'local>
+) -> JString<'local> {
+	
+	let content = env.get_string(&content);
+	if content.is_err() { error!(env, "Could not get java variable: content"); }
+	let content: String = content.unwrap().into();
+	
+	let format = env.get_string(&format);
+	if format.is_err() { error!(env, "Could not get java variable: format"); }
+	let format: String = format.unwrap().into();
+	
+	let data = match render_qr(&content, &format) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let gen_qr_code = GenQrCode {
+		status: "ok",
+		format: &format,
+		data: &data
+	};
+	
+	let gen_qr_code_json = match serde_json::to_string(&gen_qr_code) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	gen_qr_code_json
+}
+
+// Takes a base64 encoded handle as returned by genHandle or signHandle. The codes are shown one after another
+// and scanned with addScannedQrPart.
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_genQrCodeSequence<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	handle: JString<'local>,
+	format: JString<'local>
+) -> JString<'local> {
+	
+	let handle = env.get_string(&handle);
+	if handle.is_err() { error!(env, "Could not get java variable: handle"); }
+	let handle: String = handle.unwrap().into();
+	let handle = match BASE64.decode(handle) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "handle invalid"); }
+	};
+	
+	let format = env.get_string(&format);
+	if format.is_err() { error!(env, "Could not get java variable: format"); }
+	let format: String = format.unwrap().into();
+	
+	let parts = match split_qr_sequence(&handle) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	let codes: Result<Vec<String>, String> = parts.iter().map(|part| render_qr(part, &format)).collect();
+	let codes = match codes {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let gen_qr_code_sequence = GenQrCodeSequence {
+		status: "ok",
+		format: &format,
+		codes: &codes
+	};
+	
+	let gen_qr_code_sequence_json = match serde_json::to_string(&gen_qr_code_sequence) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	gen_qr_code_sequence_json
+}
+
+// Returns the handle like parseScannedHandle once the last part is scanned, until then the status is incomplete
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_addScannedQrPart<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	sequence: JString<'local>,
+	scanned: JString<'local>
+) -> JString<'local> {
+	
+	let sequence = env.get_string(&sequence);
+	if sequence.is_err() { error!(env, "Could not get java variable: sequence"); }
+	let sequence: String = sequence.unwrap().into();
+	let mut sequence = match parse_qr_sequence(&sequence) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let scanned = env.get_string(&scanned);
+	if scanned.is_err() { error!(env, "Could not get java variable: scanned"); }
+	let scanned: String = scanned.unwrap().into();
+	
+	let handle = match sequence.add(&scanned) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let info = match handle.map(handle_info) {
+		Some(Ok(res)) => Some(res),
+		Some(Err(error)) => { error!(env, &error); },
+		None => None
+	};
+	
+	let add_scanned_qr_part = AddScannedQrPart {
+		status: match info { Some(_) => "ok", None => STATUS_INCOMPLETE },
+		missing: &sequence.missing(),
+		sequence: &sequence,
+		info: info.as_ref()
+	};
+	
+	let add_scanned_qr_part_json = match serde_json::to_string(&add_scanned_qr_part) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	add_scanned_qr_part_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_parseScannedHandle<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	scanned: JString<'local>
+) -> JString<'local> {
+	
+	let scanned = env.get_string(&scanned);
+	if scanned.is_err() { error!(env, "Could not get java variable: scanned"); }
+	let scanned: String = scanned.unwrap().into();
+	
+	let handle = match decode_scanned_handle(&scanned) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let info = match handle_info(handle) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let parse_handle = ParseHandle {
+		status: "ok",
+		info: &info
+	};
+	
+	let parse_handle_json = match serde_json::to_string(&parse_handle) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	parse_handle_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::uri::gen_handle_uri;
+	
+	#[test]
+	fn matrix_fits_content() {
+		let (width, modules) = qr_matrix("dawn").unwrap();
+		assert_eq!(modules.len(), width * width);
+		assert!(qr_matrix(&"a".repeat(MAX_QR_BYTES)).is_ok());
+		assert!(qr_matrix(&"a".repeat(MAX_QR_BYTES + 1)).is_err());
+		assert!(qr_png("dawn").unwrap().starts_with(b"\x89PNG"));
+	}
+	
+	#[test]
+	fn decodes_scanned_handles() {
+		assert_eq!(decode_scanned_handle(" aGFu\nZGxl== ").unwrap(), b"handle");
+		assert_eq!(decode_scanned_handle(&gen_handle_uri(b"handle", "name").unwrap()).unwrap(), b"handle");
+		assert!(decode_scanned_handle("not a handle!").is_err());
+	}
+	
+	#[test]
+	fn signed_handle_roundtrips_through_qr_sequence() {
+		use dawn_stdlib::{gen_handle, kyber_keygen, curve_keygen, sign_keygen, mdc_gen};
+		use crate::handles::sign_handle;
+		let (pubkey_kyber, pubkey_kyber_for_salt) = (kyber_keygen().0, kyber_keygen().0);
+		let handle = gen_handle(&pubkey_kyber, &curve_keygen().0, &curve_keygen().0, &pubkey_kyber_for_salt, &curve_keygen().0, "name", &mdc_gen());
+		let (pubkey_sig, seckey_sig) = sign_keygen();
+		let handle = sign_handle(&handle, &pubkey_sig, &seckey_sig).unwrap();
+		assert!(handle.len() > MAX_QR_BYTES);
+		
+		let parts = split_qr_sequence(&handle).unwrap();
+		assert!(parts.len() > 1);
+		for part in &parts {
+			assert!(qr_matrix(part).is_ok());
+		}
+		// scanned in reverse order, one part twice and with the line breaks some scanners add
+		let mut sequence = QrSequence::default();
+		assert_eq!(sequence.add(&parts[1].replace(':', ":\n")).unwrap(), None);
+		for part in parts.iter().skip(1).rev() {
+			assert_eq!(sequence.add(part).unwrap(), None);
+		}
+		assert_eq!(sequence.missing(), vec![0]);
+		let scanned = sequence.add(&parts[0]).unwrap().unwrap();
+		assert_eq!(sequence, QrSequence::default());
+		
+		let info = handle_info(scanned).unwrap();
+		assert_eq!((info.name.as_str(), info.signed), ("name", true));
+		assert_eq!(info.init_pk_kyber, encode(pubkey_kyber));
+		assert_eq!(info.signer_pubkey_sig, Some(encode(pubkey_sig)));
+		assert!(decode_scanned_handle(&parts[0]).is_err());
+	}
+	
+	#[test]
+	fn qr_sequence_rejects_invalid_parts() {
+		let handle: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+		let parts = split_qr_sequence(&handle).unwrap();
+		let mut sequence = QrSequence::default();
+		assert!(sequence.add("aGFuZGxl").is_err());
+		assert!(sequence.add(&parts[0].replacen("0/3", "3/3", 1)).is_err());
+		assert!(sequence.add(&parts[0].replacen("0/3", "0/65", 1)).is_err());
+		assert!(sequence.add(&format!("{}!", parts[0])).is_err());
+		assert!(split_qr_sequence(&[]).is_err());
+		assert!(split_qr_sequence(&vec![0; MAX_QR_PARTS * QR_PART_BYTES + 1]).is_err());
+		
+		// a part of another handle starts over
+		sequence.add(&parts[0]).unwrap();
+		let other = split_qr_sequence(&handle[1..]).unwrap();
+		sequence.add(&other[1]).unwrap();
+		assert_eq!(sequence.missing(), vec![0, 2]);
+		
+		// chunks with the digest of another handle are caught once reassembled
+		let mut sequence = QrSequence::default();
+		let tampered = parts[2].replacen(&qr_digest(&handle), &qr_digest(&handle[1..]), 1);
+		sequence.add(&other[0]).unwrap();
+		sequence.add(&other[1]).unwrap();
+		assert!(sequence.add(&tampered).is_err());
+		assert_eq!(sequence, QrSequence::default());
+	}
+}
@@ -0,0 +1,424 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use serde::{Serialize, Deserialize};
+use crate::{Error, ReceiptResult, ReceiptApply, ReceiptFlush, ReceiptQuery};
+use crate::payload::Payload;
+use crate::error;
+
+// Ordered, so the state of a message only ever moves forward
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptState {
+	Sent,
+	Delivered,
+	Read
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ReceiptConfig {
+	// most mdcs referenced by a single receipt message
+	pub max_batch: usize,
+	// the oldest sent messages are forgotten beyond this
+	pub max_tracked: usize
+}
+
+impl Default for ReceiptConfig {
+	fn default() -> Self {
+		ReceiptConfig { max_batch: 100, max_tracked: 10000 }
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrackedMessage {
+	pub mdc: String,
+	pub state: ReceiptState
+}
+
+// States of sent messages, oldest first, and the receipts still to be sent for received ones.
+// Kept by the app and passed in on every call, like the trust store. There is one tracker per session, mdcs are only unique within one.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ReceiptTracker {
+	#[serde(default)]
+	pub config: ReceiptConfig,
+	#[serde(default)]
+	pub sent: Vec<TrackedMessage>,
+	#[serde(default)]
+	pub pending_delivered: Vec<String>,
+	#[serde(default)]
+	pub pending_read: Vec<String>
+}
+
+// A receipt message ready to be passed to sendMsg
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ReceiptBatch {
+	pub msg_type: u8,
+	pub msg_bytes: String
+}
+
+#[derive(Serialize)]
+pub struct TrackedState<'a> {
+	pub mdc: &'a str,
+	pub state: Option<ReceiptState>
+}
+
+impl ReceiptTracker {
+	pub fn track(&mut self, mdc: &str) {
+		if self.state(mdc).is_some() { return; }
+		self.sent.push(TrackedMessage { mdc: mdc.to_string(), state: ReceiptState::Sent });
+		if self.sent.len() > self.config.max_tracked {
+			let excess = self.sent.len() - self.config.max_tracked;
+			self.sent.drain(..excess);
+		}
+	}
+	
+	pub fn state(&self, mdc: &str) -> Option<ReceiptState> {
+		self.sent.iter().find(|message| message.mdc == mdc).map(|message| message.state)
+	}
+	
+	// Returns the mdcs that are not tracked, e.g. because they were forgotten already
+	pub fn update(&mut self, mdcs: &[String], state: ReceiptState) -> Vec<String> {
+		let mut unknown = Vec::new();
+		for mdc in mdcs {
+			match self.sent.iter_mut().find(|message| &message.mdc == mdc) {
+				Some(message) => message.state = message.state.max(state),
+				None => unknown.push(mdc.clone())
+			}
+		}
+		unknown
+	}
+	
+	pub fn queue(&mut self, mdc: &str, state: ReceiptState) -> Result<(), String> {
+		let pending = match state {
+			ReceiptState::Delivered => &mut self.pending_delivered,
+			ReceiptState::Read => &mut self.pending_read,
+			ReceiptState::Sent => return Err("Receipts can only be sent for delivered or read messages".to_string())
+		};
+		if !pending.iter().any(|pending_mdc| pending_mdc == mdc) {
+			pending.push(mdc.to_string());
+		}
+		Ok(())
+	}
+	
+	// Read receipts imply delivery, so delivery receipts are skipped for messages that are also read
+	pub fn flush(&mut self) -> Result<Vec<ReceiptBatch>, String> {
+		let pending_read = std::mem::take(&mut self.pending_read);
+		let pending_delivered: Vec<String> = std::mem::take(&mut self.pending_delivered).into_iter().filter(|mdc| !pending_read.contains(mdc)).collect();
+		let max_batch = self.config.max_batch;
+		let mut batches = Vec::new();
+		for chunk in pending_delivered.chunks(max_batch) {
+			batches.push(Payload::DeliveryReceipt { mdcs: chunk.to_vec() });
+		}
+		for chunk in pending_read.chunks(max_batch) {
+			batches.push(Payload::ReadReceipt { mdcs: chunk.to_vec() });
+		}
+		batches.iter().map(|payload| {
+			let (msg_type, _, msg_bytes) = payload.encode()?;
+			Ok(ReceiptBatch { msg_type, msg_bytes: BASE64.encode(msg_bytes.unwrap_or_default()) })
+		}).collect()
+	}
+}
+
+// A limit of zero would forget every tracked message right away and split receipts into empty batches
+fn parse_tracker(tracker: &str) -> Result<ReceiptTracker, String> {
+	let tracker: ReceiptTracker = match tracker {
+		"" => return Ok(ReceiptTracker::default()),
+		_ => serde_json::from_str(tracker).map_err(|_| "tracker invalid".to_string())?
+	};
+	if tracker.config.max_batch == 0 { return Err("tracker invalid: max_batch must not be zero".to_string()); }
+	if tracker.config.max_tracked == 0 { return Err("tracker invalid: max_tracked must not be zero".to_string()); }
+	Ok(tracker)
+}
+
+// Called with the mdc returned by sendMsg
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_receiptTrackSent<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	tracker: JString<'local>,
+	mdc: JString<'local>
+) -> JString<'local> {
+	
+	let tracker = env.get_string(&tracker);
+	if tracker.is_err() { error!(env, "Could not get java variable: tracker"); }
+	let tracker: String = tracker.unwrap().into();
+	let mut tracker = match parse_tracker(&tracker) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let mdc = env.get_string(&mdc);
+	if mdc.is_err() { error!(env, "Could not get java variable: mdc"); }
+	let mdc: String = mdc.unwrap().into();
+	
+	tracker.track(&mdc);
+	
+	let receipt_result = ReceiptResult {
+		status: "ok",
+		tracker: &tracker
+	};
+	
+	let receipt_result_json = match serde_json::to_string(&receipt_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	receipt_result_json
+}
+
+// Takes a receipt payload as returned by decodePayload and updates the states of the messages it references
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_receiptApply<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	tracker: JString<'local>,
+	receipt: JString<'local>
+) -> JString<'local> {
+	
+	let tracker = env.get_string(&tracker);
+	if tracker.is_err() { error!(env, "Could not get java variable: tracker"); }
+	let tracker: String = tracker.unwrap().into();
+	let mut tracker = match parse_tracker(&tracker) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let receipt = env.get_string(&receipt);
+	if receipt.is_err() { error!(env, "Could not get java variable: receipt"); }
+	let receipt: String = receipt.unwrap().into();
+	let receipt: Payload = match serde_json::from_str(&receipt) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "receipt invalid"); }
+	};
+	
+	let unknown = match receipt {
+		Payload::DeliveryReceipt { mdcs } => tracker.update(&mdcs, ReceiptState::Delivered),
+		Payload::ReadReceipt { mdcs } => tracker.update(&mdcs, ReceiptState::Read),
+		_ => { error!(env, "Payload is not a receipt"); }
+	};
+	
+	let receipt_apply = ReceiptApply {
+		status: "ok",
+		unknown: &unknown,
+		tracker: &tracker
+	};
+	
+	let receipt_apply_json = match serde_json::to_string(&receipt_apply) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	receipt_apply_json
+}
+
+// Queues a receipt for a received message, state is either "delivered" or "read"
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_receiptQueue<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	tracker: JString<'local>,
+	mdc: JString<'local>,
+	state: JString<'local>
+) -> JString<'local> {
+	
+	let tracker = env.get_string(&tracker);
+	if tracker.is_err() { error!(env, "Could not get java variable: tracker"); }
+	let tracker: String = tracker.unwrap().into();
+	let mut tracker = match parse_tracker(&tracker) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let mdc = env.get_string(&mdc);
+	if mdc.is_err() { error!(env, "Could not get java variable: mdc"); }
+	let mdc: String = mdc.unwrap().into();
+	
+	let state = env.get_string(&state);
+	if state.is_err() { error!(env, "Could not get java variable: state"); }
+	let state: String = state.unwrap().into();
+	let state = match state.as_str() {
+		"delivered" => ReceiptState::Delivered,
+		"read" => ReceiptState::Read,
+		_ => { error!(env, "state invalid"); }
+	};
+	
+	if let Err(err) = tracker.queue(&mdc, state) { error!(env, &err); }
+	
+	let receipt_result = ReceiptResult {
+		status: "ok",
+		tracker: &tracker
+	};
+	
+	let receipt_result_json = match serde_json::to_string(&receipt_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	receipt_result_json
+}
+
+// Returns the queued receipts batched into as few messages as possible and clears the queue
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_receiptFlush<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	tracker: JString<'local>
+) -> JString<'local> {
+	
+	let tracker = env.get_string(&tracker);
+	if tracker.is_err() { error!(env, "Could not get java variable: tracker"); }
+	let tracker: String = tracker.unwrap().into();
+	let mut tracker = match parse_tracker(&tracker) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let batches = match tracker.flush() {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not encode receipts: {}", err)); }
+	};
+	
+	let receipt_flush = ReceiptFlush {
+		status: "ok",
+		batches: &batches,
+		tracker: &tracker
+	};
+	
+	let receipt_flush_json = match serde_json::to_string(&receipt_flush) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	receipt_flush_json
+}
+
+// Takes a json array of mdcs, the state of untracked ones is null
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_receiptQuery<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	tracker: JString<'local>,
+	mdcs: JString<'local>
+) -> JString<'local> {
+	
+	let tracker = env.get_string(&tracker);
+	if tracker.is_err() { error!(env, "Could not get java variable: tracker"); }
+	let tracker: String = tracker.unwrap().into();
+	let tracker = match parse_tracker(&tracker) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let mdcs = env.get_string(&mdcs);
+	if mdcs.is_err() { error!(env, "Could not get java variable: mdcs"); }
+	let mdcs: String = mdcs.unwrap().into();
+	let mdcs: Vec<String> = match serde_json::from_str(&mdcs) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "mdcs invalid"); }
+	};
+	
+	let states: Vec<TrackedState> = mdcs.iter().map(|mdc| TrackedState { mdc, state: tracker.state(mdc) }).collect();
+	
+	let receipt_query = ReceiptQuery {
+		status: "ok",
+		states: &states
+	};
+	
+	let receipt_query_json = match serde_json::to_string(&receipt_query) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	receipt_query_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::payload::{MSG_TYPE_DELIVERY_RECEIPT, MSG_TYPE_READ_RECEIPT};
+	
+	fn mdcs(batch: &ReceiptBatch) -> Vec<String> {
+		let msg_bytes = BASE64.decode(&batch.msg_bytes).unwrap();
+		match Payload::decode(batch.msg_type, None, Some(&msg_bytes)).unwrap() {
+			Payload::DeliveryReceipt { mdcs } | Payload::ReadReceipt { mdcs } => mdcs,
+			_ => panic!("not a receipt")
+		}
+	}
+	
+	#[test]
+	fn flush_batches_receipts() {
+		let mut tracker = ReceiptTracker { config: ReceiptConfig { max_batch: 2, max_tracked: 10 }, ..Default::default() };
+		for mdc in ["a", "b", "c", "d"] {
+			tracker.queue(mdc, ReceiptState::Delivered).unwrap();
+		}
+		tracker.queue("a", ReceiptState::Delivered).unwrap();
+		tracker.queue("d", ReceiptState::Read).unwrap();
+		let batches = tracker.flush().unwrap();
+		let types: Vec<u8> = batches.iter().map(|batch| batch.msg_type).collect();
+		assert_eq!(types, vec![MSG_TYPE_DELIVERY_RECEIPT, MSG_TYPE_DELIVERY_RECEIPT, MSG_TYPE_READ_RECEIPT]);
+		assert_eq!(mdcs(&batches[0]), vec!["a", "b"]);
+		assert_eq!(mdcs(&batches[1]), vec!["c"]);
+		assert_eq!(mdcs(&batches[2]), vec!["d"]);
+		assert!(tracker.flush().unwrap().is_empty());
+		assert!(tracker.queue("e", ReceiptState::Sent).is_err());
+	}
+	
+	#[test]
+	fn states_only_move_forward() {
+		let mut tracker = ReceiptTracker::default();
+		tracker.track("a");
+		assert_eq!(tracker.state("a"), Some(ReceiptState::Sent));
+		assert!(tracker.update(&["a".to_string()], ReceiptState::Read).is_empty());
+		tracker.update(&["a".to_string()], ReceiptState::Delivered);
+		assert_eq!(tracker.state("a"), Some(ReceiptState::Read));
+		assert_eq!(tracker.update(&["b".to_string()], ReceiptState::Read), vec!["b"]);
+	}
+	
+	#[test]
+	fn track_forgets_oldest_messages() {
+		let mut tracker = ReceiptTracker { config: ReceiptConfig { max_batch: 1, max_tracked: 2 }, ..Default::default() };
+		for mdc in ["a", "b", "a", "c"] {
+			tracker.track(mdc);
+		}
+		assert_eq!(tracker.state("a"), None);
+		assert_eq!(tracker.sent.len(), 2);
+	}
+	
+	#[test]
+	fn parse_tracker_checks_limits() {
+		assert_eq!(parse_tracker("").unwrap(), ReceiptTracker::default());
+		assert!(parse_tracker(r#"{"config":{"max_batch":0}}"#).is_err());
+		assert!(parse_tracker(r#"{"config":{"max_tracked":0}}"#).is_err());
+		assert!(parse_tracker(r#"{"config":{"max_batch":1,"max_tracked":1}}"#).is_ok());
+	}
+}
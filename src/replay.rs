@@ -0,0 +1,297 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::collections::VecDeque;
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JByteArray, JClass, JString};
+use hex::encode;
+use serde::{Serialize, Deserialize};
+use crate::{Error, ReplayCheck};
+use crate::messaging::parse_message;
+use crate::fragments::add_fragment;
+use crate::envelope::{Kind, open_unverified};
+use crate::error;
+
+// Returned as status when a ciphertext has already been seen in the session
+pub const STATUS_REPLAY: &str = "replay";
+
+const DEFAULT_CAPACITY: usize = 2000;
+
+// Hashes of the most recent ciphertexts of one session, oldest first. The window is kept by the app and passed in on every call,
+// persisting the returned json and passing it in again restores it.
+// Counters of sequenced messages are tracked as well, they also catch duplicates that were encrypted again.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ReplayWindow {
+	pub capacity: usize,
+	pub hashes: VecDeque<String>,
+	pub highest_counter: Option<u64>,
+	pub counters: VecDeque<u64>
+}
+
+impl Default for ReplayWindow {
+	fn default() -> Self {
+		ReplayWindow { capacity: DEFAULT_CAPACITY, hashes: VecDeque::new(), highest_counter: None, counters: VecDeque::new() }
+	}
+}
+
+impl ReplayWindow {
+	// Envelopes are opened first, so a re-sealed copy of a ciphertext is still recognized
+	pub fn ciphertext_hash(ciphertext: &[u8]) -> String {
+		encode(hash(ciphertext))
+	}
+	
+	pub fn contains(&self, ciphertext_hash: &str) -> bool {
+		self.hashes.iter().any(|hash| hash == ciphertext_hash)
+	}
+	
+	pub fn record(&mut self, ciphertext_hash: String) {
+		self.hashes.push_back(ciphertext_hash);
+		while self.hashes.len() > self.capacity {
+			self.hashes.pop_front();
+		}
+	}
+	
+	// Counters too far behind the highest one can not be told apart from replays anymore and are treated as such
+	pub fn contains_counter(&self, counter: u64) -> bool {
+		match self.highest_counter {
+			Some(highest) if highest.saturating_sub(counter) >= self.capacity as u64 => true,
+			_ => self.counters.contains(&counter)
+		}
+	}
+	
+	// Returns the number of counters skipped since the highest one and whether the message arrived out of order
+	pub fn record_counter(&mut self, counter: u64) -> (u64, bool) {
+		let (gap, out_of_order) = match self.highest_counter {
+			None => (0, false),
+			Some(highest) if counter > highest => (counter - highest - 1, false),
+			Some(_) => (0, true)
+		};
+		if !out_of_order { self.highest_counter = Some(counter); }
+		self.counters.push_back(counter);
+		while self.counters.len() > self.capacity {
+			self.counters.pop_front();
+		}
+		(gap, out_of_order)
+	}
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ReplayWindowOptions {
+	capacity: Option<usize>
+}
+
+// Restored windows are checked like created ones, a capacity of zero would report every counter as a replay
+pub fn parse_window(window: &str) -> Result<ReplayWindow, String> {
+	let window: ReplayWindow = match window {
+		"" => return Ok(ReplayWindow::default()),
+		_ => serde_json::from_str(window).map_err(|_| "window invalid".to_string())?
+	};
+	if window.capacity == 0 { return Err("window invalid: capacity must not be zero".to_string()); }
+	Ok(window)
+}
+
+// Returned instead of the message when it was already seen
+pub fn replay_status<'local> (env: JNIEnv<'local>, window: &ReplayWindow) -> JString<'local> {
+	let replay_check = ReplayCheck {
+		status: STATUS_REPLAY,
+		window
+	};
+	match serde_json::to_string(&replay_check) {
+		Ok(res) => match env.new_string(res) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		},
+		Err(_) => { error!(env, "Could not serialize json"); }
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_createReplayWindow<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	options: JString<'local>
+) -> JString<'local> {
+	
+	let options = env.get_string(&options);
+	if options.is_err() { error!(env, "Could not get java variable: options"); }
+	let options: String = options.unwrap().into();
+	let options: ReplayWindowOptions = match options.as_str() {
+		"" => ReplayWindowOptions::default(),
+		_ => match serde_json::from_str(&options) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "options invalid"); }
+		}
+	};
+	
+	let mut window = ReplayWindow::default();
+	if let Some(capacity) = options.capacity {
+		if capacity == 0 { error!(env, "capacity must not be zero"); }
+		window.capacity = capacity;
+	}
+	
+	let replay_check = ReplayCheck {
+		status: "ok",
+		window: &window
+	};
+	
+	let replay_check_json = match serde_json::to_string(&replay_check) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	replay_check_json
+}
+
+// For ciphertexts parsed by other functions than parseMsgWithReplayWindow. The ciphertext is recorded right away,
+// so this should be called once the ciphertext has been processed successfully.
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_checkReplay<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	window: JString<'local>,
+	ciphertext: JByteArray<'local>
+) -> JString<'local> {
+	
+	let window = env.get_string(&window);
+	if window.is_err() { error!(env, "Could not get java variable: window"); }
+	let window: String = window.unwrap().into();
+	let mut window = match parse_window(&window) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let ciphertext = env.convert_byte_array(ciphertext);
+	if ciphertext.is_err() { error!(env, "Could not get java variable: ciphertext"); }
+	let ciphertext = ciphertext.unwrap();
+	// Only the ciphertext inside is hashed, the tag is checked once the message is parsed
+	let ciphertext = match open_unverified(&ciphertext, Kind::Message) {
+		Ok((_, res)) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let ciphertext_hash = ReplayWindow::ciphertext_hash(ciphertext);
+	let status = match window.contains(&ciphertext_hash) {
+		true => STATUS_REPLAY,
+		false => {
+			window.record(ciphertext_hash);
+			"ok"
+		}
+	};
+	
+	let replay_check = ReplayCheck {
+		status,
+		window: &window
+	};
+	
+	let replay_check_json = match serde_json::to_string(&replay_check) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	replay_check_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_parseMsgWithReplayWindow<'local> (
+	env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	window: JString<'local>,
+	msg_ciphertext: JByteArray<'local>,
+	own_seckey_kyber: JString<'local>,
+	remote_pubkey_sig: JString<'local>,
+	pfs_key: JString<'local>,
+	pfs_salt: JString<'local>
+) -> JString<'local> {
+	parse_message(env, msg_ciphertext, own_seckey_kyber, remote_pubkey_sig, pfs_key, pfs_salt, Some(window))
+}
+
+// Like addFragment, checks the counter of the reassembled message against the window
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_addFragmentWithReplayWindow<'local> (
+	env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	window: JString<'local>,
+	buffer: JString<'local>,
+	msg_bytes: JString<'local>
+) -> JString<'local> {
+	add_fragment(env, buffer, msg_bytes, Some(window))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn records_hashes_up_to_capacity() {
+		let mut window = ReplayWindow { capacity: 2, ..Default::default() };
+		for hash in ["a", "b", "c"] {
+			assert!(!window.contains(hash));
+			window.record(hash.to_string());
+		}
+		assert!(!window.contains("a"));
+		assert!(window.contains("b") && window.contains("c"));
+	}
+	
+	#[test]
+	fn parse_window_checks_capacity() {
+		assert_eq!(parse_window("").unwrap(), ReplayWindow::default());
+		assert_eq!(parse_window(r#"{"capacity":5}"#).unwrap().capacity, 5);
+		assert!(parse_window(r#"{"capacity":0}"#).is_err());
+		assert!(parse_window("{").is_err());
+	}
+	
+	#[test]
+	fn record_counter_reports_gaps() {
+		let mut window = ReplayWindow::default();
+		assert_eq!(window.record_counter(0), (0, false));
+		assert_eq!(window.record_counter(1), (0, false));
+		assert_eq!(window.record_counter(5), (3, false));
+		assert_eq!(window.highest_counter, Some(5));
+	}
+	
+	#[test]
+	fn record_counter_reports_out_of_order() {
+		let mut window = ReplayWindow::default();
+		window.record_counter(5);
+		assert!(!window.contains_counter(3));
+		assert_eq!(window.record_counter(3), (0, true));
+		assert_eq!(window.highest_counter, Some(5));
+		assert!(window.contains_counter(3));
+		assert!(window.contains_counter(5));
+		assert!(!window.contains_counter(4));
+	}
+	
+	#[test]
+	fn old_counters_count_as_replays() {
+		let mut window = ReplayWindow { capacity: 3, ..Default::default() };
+		for counter in 0..5 {
+			window.record_counter(counter);
+		}
+		assert_eq!(window.counters.len(), 3);
+		assert!(window.contains_counter(1));
+		assert!(window.contains_counter(2));
+	}
+}
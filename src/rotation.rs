@@ -0,0 +1,314 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use hex::{encode, decode};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64};
+use serde::{Serialize, Deserialize};
+use crate::{Error, GenKeyRotation, ApplyKeyRotation, ValidateRotationChain};
+use crate::handles::timestamp_value;
+use crate::signatures::{sign_detached, verify_detached, signed_data, fingerprint};
+use crate::error;
+
+// Announces that old_pubkey_sig is replaced by new_pubkey_sig. The old key signs the new one, and the new key signs the old one
+// to prove that whoever announces the rotation actually holds the new key. Rotations are sent to contacts as KeyRotation payloads.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeyRotation {
+	pub old_pubkey_sig: String,
+	pub new_pubkey_sig: String,
+	pub timestamp: String,
+	pub old_signature: String,
+	pub new_signature: String
+}
+
+// Rotations already applied for a contact, kept by the app next to the contact and passed in on every call ("" for none).
+// Timestamps have to increase strictly and retired keys can not come back, so old rotations can not be replayed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RotationHistory {
+	#[serde(default)]
+	pub last_timestamp: u64,
+	// fingerprints of the keys that were replaced
+	#[serde(default)]
+	pub retired: Vec<String>
+}
+
+fn rotation_signed_data(old_pubkey_sig: &[u8], new_pubkey_sig: &[u8], timestamp: &str) -> Vec<u8> {
+	signed_data(&[b"key-rotation", old_pubkey_sig, new_pubkey_sig, timestamp.as_bytes()])
+}
+
+pub fn gen_key_rotation(old_pubkey_sig: &[u8], old_seckey_sig: &[u8], new_pubkey_sig: &[u8], new_seckey_sig: &[u8]) -> Result<KeyRotation, String> {
+	let timestamp = get_current_timestamp()?;
+	let data = rotation_signed_data(old_pubkey_sig, new_pubkey_sig, &timestamp);
+	let old_signature = sign_detached(&data, old_seckey_sig)?;
+	let new_signature = sign_detached(&data, new_seckey_sig)?;
+	Ok(KeyRotation {
+		old_pubkey_sig: encode(old_pubkey_sig),
+		new_pubkey_sig: encode(new_pubkey_sig),
+		timestamp,
+		old_signature: BASE64.encode(old_signature),
+		new_signature: BASE64.encode(new_signature)
+	})
+}
+
+// Returns the new key if the rotation is validly signed by both keys, replaces current_pubkey_sig and is newer than the
+// rotations in history. history is only updated if the rotation is accepted.
+pub fn apply_key_rotation(current_pubkey_sig: &[u8], rotation: &KeyRotation, history: &mut RotationHistory) -> Result<Vec<u8>, String> {
+	let old_pubkey_sig = decode(&rotation.old_pubkey_sig).map_err(|_| "Old signature key invalid".to_string())?;
+	let new_pubkey_sig = decode(&rotation.new_pubkey_sig).map_err(|_| "New signature key invalid".to_string())?;
+	if old_pubkey_sig != current_pubkey_sig { return Err("Rotation does not replace the current signature key".to_string()); }
+	if new_pubkey_sig == old_pubkey_sig { return Err("Rotation does not change the signature key".to_string()); }
+	let timestamp = timestamp_value(&rotation.timestamp)?;
+	if timestamp <= history.last_timestamp { return Err("Rotation is not newer than the last applied rotation".to_string()); }
+	let new_fingerprint = fingerprint(&new_pubkey_sig);
+	if history.retired.contains(&new_fingerprint) { return Err("Rotation reuses a retired signature key".to_string()); }
+	let old_signature = BASE64.decode(&rotation.old_signature).map_err(|_| "Old key signature invalid".to_string())?;
+	let new_signature = BASE64.decode(&rotation.new_signature).map_err(|_| "New key signature invalid".to_string())?;
+	let data = rotation_signed_data(&old_pubkey_sig, &new_pubkey_sig, &rotation.timestamp);
+	verify_detached(&data, &old_signature, &old_pubkey_sig)?;
+	verify_detached(&data, &new_signature, &new_pubkey_sig)?;
+	history.last_timestamp = timestamp;
+	history.retired.push(fingerprint(&old_pubkey_sig));
+	Ok(new_pubkey_sig)
+}
+
+// Follows a chain of rotations starting at a trusted key and returns the key at its end together with the resulting history
+pub fn validate_rotation_chain(trusted_pubkey_sig: &[u8], chain: &[KeyRotation]) -> Result<(Vec<u8>, RotationHistory), String> {
+	let mut current = trusted_pubkey_sig.to_vec();
+	let mut history = RotationHistory::default();
+	for (index, rotation) in chain.iter().enumerate() {
+		current = apply_key_rotation(&current, rotation, &mut history).map_err(|err| format!("Rotation {} invalid: {}", index, err))?;
+	}
+	Ok((current, history))
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_genKeyRotation<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	old_pubkey_sig: JString<'local>,
+	old_seckey_sig: JString<'local>,
+	new_pubkey_sig: JString<'local>,
+	new_seckey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let old_pubkey_sig = env.get_string(&old_pubkey_sig);
+	if old_pubkey_sig.is_err() { error!(env, "Could not get java variable: old_pubkey_sig"); }
+	let old_pubkey_sig: String = old_pubkey_sig.unwrap().into();
+	let old_pubkey_sig = match decode(old_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "old_pubkey_sig invalid"); }
+	};
+	
+	let old_seckey_sig = env.get_string(&old_seckey_sig);
+	if old_seckey_sig.is_err() { error!(env, "Could not get java variable: old_seckey_sig"); }
+	let old_seckey_sig: String = old_seckey_sig.unwrap().into();
+	let old_seckey_sig = match decode(old_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "old_seckey_sig invalid"); }
+	};
+	
+	let new_pubkey_sig = env.get_string(&new_pubkey_sig);
+	if new_pubkey_sig.is_err() { error!(env, "Could not get java variable: new_pubkey_sig"); }
+	let new_pubkey_sig: String = new_pubkey_sig.unwrap().into();
+	let new_pubkey_sig = match decode(new_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "new_pubkey_sig invalid"); }
+	};
+	
+	let new_seckey_sig = env.get_string(&new_seckey_sig);
+	if new_seckey_sig.is_err() { error!(env, "Could not get java variable: new_seckey_sig"); }
+	let new_seckey_sig: String = new_seckey_sig.unwrap().into();
+	let new_seckey_sig = match decode(new_seckey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "new_seckey_sig invalid"); }
+	};
+	
+	let rotation = match gen_key_rotation(&old_pubkey_sig, &old_seckey_sig, &new_pubkey_sig, &new_seckey_sig) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not generate key rotation: {}", err)); }
+	};
+	
+	let gen_key_rotation = GenKeyRotation {
+		status: "ok",
+		rotation: &rotation
+	};
+	
+	let gen_key_rotation_json = match serde_json::to_string(&gen_key_rotation) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	gen_key_rotation_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_applyKeyRotation<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	remote_pubkey_sig: JString<'local>,
+	rotation: JString<'local>,
+	history: JString<'local>
+) -> JString<'local> {
+	
+	let remote_pubkey_sig = env.get_string(&remote_pubkey_sig);
+	if remote_pubkey_sig.is_err() { error!(env, "Could not get java variable: remote_pubkey_sig"); }
+	let remote_pubkey_sig: String = remote_pubkey_sig.unwrap().into();
+	let remote_pubkey_sig = match decode(remote_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "remote_pubkey_sig invalid"); }
+	};
+	
+	let rotation = env.get_string(&rotation);
+	if rotation.is_err() { error!(env, "Could not get java variable: rotation"); }
+	let rotation: String = rotation.unwrap().into();
+	let rotation: KeyRotation = match serde_json::from_str(&rotation) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "rotation invalid"); }
+	};
+	
+	let history = env.get_string(&history);
+	if history.is_err() { error!(env, "Could not get java variable: history"); }
+	let history: String = history.unwrap().into();
+	let mut history: RotationHistory = match history.as_str() {
+		"" => RotationHistory::default(),
+		_ => match serde_json::from_str(&history) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "history invalid"); }
+		}
+	};
+	
+	let new_pubkey_sig = match apply_key_rotation(&remote_pubkey_sig, &rotation, &mut history) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Key rotation rejected: {}", err)); }
+	};
+	
+	let apply_key_rotation = ApplyKeyRotation {
+		status: "ok",
+		remote_pubkey_sig: &encode(new_pubkey_sig),
+		history: &history
+	};
+	
+	let apply_key_rotation_json = match serde_json::to_string(&apply_key_rotation) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	apply_key_rotation_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_validateRotationChain<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	trusted_pubkey_sig: JString<'local>,
+	chain: JString<'local>
+) -> JString<'local> {
+	
+	let trusted_pubkey_sig = env.get_string(&trusted_pubkey_sig);
+	if trusted_pubkey_sig.is_err() { error!(env, "Could not get java variable: trusted_pubkey_sig"); }
+	let trusted_pubkey_sig: String = trusted_pubkey_sig.unwrap().into();
+	let trusted_pubkey_sig = match decode(trusted_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "trusted_pubkey_sig invalid"); }
+	};
+	
+	let chain = env.get_string(&chain);
+	if chain.is_err() { error!(env, "Could not get java variable: chain"); }
+	let chain: String = chain.unwrap().into();
+	let chain: Vec<KeyRotation> = match serde_json::from_str(&chain) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "chain invalid"); }
+	};
+	
+	let (new_pubkey_sig, history) = match validate_rotation_chain(&trusted_pubkey_sig, &chain) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Rotation chain rejected: {}", err)); }
+	};
+	
+	let validate_rotation_chain = ValidateRotationChain {
+		status: "ok",
+		remote_pubkey_sig: &encode(new_pubkey_sig),
+		rotations: chain.len(),
+		history: &history
+	};
+	
+	let validate_rotation_chain_json = match serde_json::to_string(&validate_rotation_chain) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	validate_rotation_chain_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	// gen_key_rotation always uses the current timestamp, chains need increasing ones
+	fn rotation(old: &(Vec<u8>, Vec<u8>), new: &(Vec<u8>, Vec<u8>), timestamp: &str) -> KeyRotation {
+		let data = rotation_signed_data(&old.0, &new.0, timestamp);
+		KeyRotation {
+			old_pubkey_sig: encode(&old.0),
+			new_pubkey_sig: encode(&new.0),
+			timestamp: timestamp.to_string(),
+			old_signature: BASE64.encode(sign_detached(&data, &old.1).unwrap()),
+			new_signature: BASE64.encode(sign_detached(&data, &new.1).unwrap())
+		}
+	}
+	
+	#[test]
+	fn applies_rotation_once() {
+		let (old, new) = (sign_keygen(), sign_keygen());
+		let rotation = gen_key_rotation(&old.0, &old.1, &new.0, &new.1).unwrap();
+		let mut history = RotationHistory::default();
+		assert_eq!(apply_key_rotation(&old.0, &rotation, &mut history).unwrap(), new.0);
+		assert_eq!(history.retired, vec![fingerprint(&old.0)]);
+		assert!(apply_key_rotation(&old.0, &rotation, &mut history).is_err());
+		assert!(apply_key_rotation(&new.0, &rotation, &mut RotationHistory::default()).is_err());
+	}
+	
+	#[test]
+	fn rejects_rotation_without_new_key() {
+		let (old, new, other) = (sign_keygen(), sign_keygen(), sign_keygen());
+		let mut forged = rotation(&old, &new, "5");
+		forged.new_signature = rotation(&old, &other, "5").new_signature;
+		assert!(apply_key_rotation(&old.0, &forged, &mut RotationHistory::default()).is_err());
+	}
+	
+	#[test]
+	fn validates_chains() {
+		let keys = [sign_keygen(), sign_keygen(), sign_keygen()];
+		let chain = vec![rotation(&keys[0], &keys[1], "1"), rotation(&keys[1], &keys[2], "2")];
+		let (current, history) = validate_rotation_chain(&keys[0].0, &chain).unwrap();
+		assert_eq!(current, keys[2].0);
+		assert_eq!(history.last_timestamp, 2);
+		// timestamps have to increase and retired keys can not come back
+		assert!(validate_rotation_chain(&keys[0].0, &[rotation(&keys[0], &keys[1], "2"), rotation(&keys[1], &keys[2], "2")]).is_err());
+		assert!(validate_rotation_chain(&keys[0].0, &[rotation(&keys[0], &keys[1], "1"), rotation(&keys[1], &keys[0], "2")]).is_err());
+		assert!(validate_rotation_chain(&keys[1].0, &chain).is_err());
+	}
+}
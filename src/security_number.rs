@@ -0,0 +1,187 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use hex::{encode_upper, decode};
+use crate::{Error, SecurityNumberFormats, SecurityNumberVerification};
+use crate::wordlists::{WORDS_EN, WORDS_DE, EMOJI};
+use crate::error;
+
+const QR_PAYLOAD_PREFIX: &str = "DAWNSN1:";
+const NUMERIC_BLOCK_SIZE: usize = 5;
+const WORD_COUNT: usize = 8;
+const EMOJI_COUNT: usize = 10;
+const QR_MATERIAL_SIZE: usize = 16;
+
+pub struct Renderings {
+	pub number: String,
+	pub numeric: String,
+	pub words: Vec<&'static str>,
+	pub emoji: Vec<&'static str>,
+	pub qr_payload: String
+}
+
+// derive_security_number is called with the keys in a fixed order, so both sides get the same number no matter which key they pass first
+pub fn derive_ordered_security_number(key_a: &[u8], key_b: &[u8]) -> Result<String, String> {
+	match key_a <= key_b {
+		true => derive_security_number(key_a, key_b),
+		false => derive_security_number(key_b, key_a)
+	}
+}
+
+// All renderings except the numeric one are taken from a hash of the security number, so they all represent the same derivation
+pub fn render_security_number(number: &str, language: &str) -> Result<Renderings, String> {
+	let words = match language {
+		"en" => &WORDS_EN,
+		"de" => &WORDS_DE,
+		_ => return Err(format!("Unsupported language: {}", language))
+	};
+	let material = hash(number.as_bytes());
+	if material.len() < WORD_COUNT + EMOJI_COUNT || material.len() < QR_MATERIAL_SIZE { return Err("Hash output too short".to_string()); }
+	let digits: Vec<char> = number.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+	let numeric = digits.chunks(NUMERIC_BLOCK_SIZE).map(|block| block.iter().collect::<String>()).collect::<Vec<String>>().join(" ");
+	Ok(Renderings {
+		number: number.to_string(),
+		numeric,
+		words: material[..WORD_COUNT].iter().map(|byte| words[*byte as usize]).collect(),
+		// 256 is a multiple of 64, so taking the remainder does not bias the selection
+		emoji: material[WORD_COUNT..WORD_COUNT + EMOJI_COUNT].iter().map(|byte| EMOJI[*byte as usize % EMOJI.len()]).collect(),
+		// upper case hex, so the payload fits the compact alphanumeric mode of QR codes
+		qr_payload: format!("{}{}", QR_PAYLOAD_PREFIX, encode_upper(&material[..QR_MATERIAL_SIZE]))
+	})
+}
+
+// Compares a scanned qr payload with the one derived from both keys, the order of the keys does not matter
+pub fn security_number_qr_matches(key_a: &[u8], key_b: &[u8], payload: &str) -> Result<bool, String> {
+	// scanners may add whitespace or change the case, so normalise before looking at the payload
+	let payload = payload.trim().to_uppercase();
+	if !payload.starts_with(QR_PAYLOAD_PREFIX) { return Err("Not a security number payload".to_string()); }
+	let number = derive_ordered_security_number(key_a, key_b).map_err(|err| format!("Could not derive security number: {}", err))?;
+	let renderings = render_security_number(&number, "en").map_err(|err| format!("Could not render security number: {}", err))?;
+	Ok(renderings.qr_payload == payload)
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_deriveSecurityNumberFormats<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	key_a: JString<'local>,
+	key_b: JString<'local>,
+	language: JString<'local>
+) -> JString<'local> {
+	
+	let key_a = env.get_string(&key_a);
+	if key_a.is_err() { error!(env, "Could not get java variable: key_a"); }
+	let key_a: String = key_a.unwrap().into();
+	let key_a = match decode(key_a) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "key_a invalid"); }
+	};
+	
+	let key_b = env.get_string(&key_b);
+	if key_b.is_err() { error!(env, "Could not get java variable: key_b"); }
+	let key_b: String = key_b.unwrap().into();
+	let key_b = match decode(key_b) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "key_b invalid"); }
+	};
+	
+	let language = env.get_string(&language);
+	if language.is_err() { error!(env, "Could not get java variable: language"); }
+	let language: String = language.unwrap().into();
+	
+	let number = match derive_ordered_security_number(&key_a, &key_b) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &format!("Could not derive security number: {}", error)); }
+	};
+	
+	let renderings = match render_security_number(&number, &language) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &format!("Could not render security number: {}", error)); }
+	};
+	
+	let security_number_formats = SecurityNumberFormats {
+		status: "ok",
+		number: &renderings.number,
+		numeric: &renderings.numeric,
+		words: &renderings.words,
+		emoji: &renderings.emoji,
+		qr_payload: &renderings.qr_payload
+	};
+	
+	let security_number_formats_json = match serde_json::to_string(&security_number_formats) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	security_number_formats_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_verifySecurityNumberQr<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	key_a: JString<'local>,
+	key_b: JString<'local>,
+	payload: JString<'local>
+) -> JString<'local> {
+	
+	let key_a = env.get_string(&key_a);
+	if key_a.is_err() { error!(env, "Could not get java variable: key_a"); }
+	let key_a: String = key_a.unwrap().into();
+	let key_a = match decode(key_a) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "key_a invalid"); }
+	};
+	
+	let key_b = env.get_string(&key_b);
+	if key_b.is_err() { error!(env, "Could not get java variable: key_b"); }
+	let key_b: String = key_b.unwrap().into();
+	let key_b = match decode(key_b) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "key_b invalid"); }
+	};
+	
+	let payload = env.get_string(&payload);
+	if payload.is_err() { error!(env, "Could not get java variable: payload"); }
+	let payload: String = payload.unwrap().into();
+	
+	let verified = match security_number_qr_matches(&key_a, &key_b, &payload) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &err); }
+	};
+	
+	let security_number_verification = SecurityNumberVerification {
+		status: "ok",
+		verified
+	};
+	
+	let security_number_verification_json = match serde_json::to_string(&security_number_verification) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	security_number_verification_json
+}
@@ -0,0 +1,113 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use serde::{Serialize, Deserialize};
+use crate::features::{FEATURE_SEQUENCE, Announcement};
+
+// Per contact state created by the init handshake. It is returned by parseInitRequest, parseInitResponse and inboxAccept,
+// kept by the app and passed in on every call, like the trust store. Sending with a session refuses messages the peer did not announce support for,
+// plain sendMsg does not check anything and stays compatible with apps that use their own msg_types.
+// sendMsgWithOptions and sendMsgFragmented return it again with an advanced counter, so there must only be one session per contact.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Session {
+	// none for peers that did not announce anything, i.e. old clients
+	pub peer_version: Option<u16>,
+	pub peer_features: Option<u32>,
+	// counter of the next message we send
+	pub next_counter: u64
+}
+
+impl Session {
+	pub fn new(announcement: Option<Announcement>) -> Self {
+		Session {
+			peer_version: announcement.map(|announcement| announcement.version),
+			peer_features: announcement.map(|announcement| announcement.features),
+			next_counter: 0
+		}
+	}
+	
+	pub fn supports(&self, features: u32) -> bool {
+		self.peer_features.unwrap_or(0) & features == features
+	}
+	
+	pub fn check_features(&self, required: u32) -> Result<(), String> {
+		match required & !self.peer_features.unwrap_or(0) {
+			0 => Ok(()),
+			missing => Err(format!("Peer does not support required features: {:08x}", missing))
+		}
+	}
+	
+	// Messages are only sequenced for peers that announced support for it
+	pub fn take_counter(&mut self) -> Result<Option<u64>, String> {
+		if !self.supports(FEATURE_SEQUENCE) { return Ok(None); }
+		let counter = self.next_counter;
+		self.next_counter = match counter.checked_add(1) {
+			Some(res) => res,
+			None => return Err("Message counter exhausted".to_string())
+		};
+		Ok(Some(counter))
+	}
+}
+
+// An empty session stands for a peer that did not announce any features
+pub fn parse_session(session: &str) -> Result<Session, String> {
+	match session {
+		"" => Ok(Session::default()),
+		_ => serde_json::from_str(session).map_err(|_| "session invalid".to_string())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::features::{FEATURE_FRAME, FEATURE_PADDING};
+	
+	#[test]
+	fn checks_announced_features() {
+		let session = Session::new(Some(Announcement { version: 1, features: FEATURE_FRAME }));
+		assert!(session.supports(FEATURE_FRAME));
+		assert!(!session.supports(FEATURE_FRAME | FEATURE_PADDING));
+		assert!(session.check_features(FEATURE_FRAME).is_ok());
+		assert_eq!(session.check_features(FEATURE_FRAME | FEATURE_PADDING).unwrap_err(), "Peer does not support required features: 00000002");
+		assert!(Session::default().check_features(0).is_ok());
+		assert!(Session::default().check_features(FEATURE_FRAME).is_err());
+	}
+	
+	#[test]
+	fn parse_session_roundtrip() {
+		assert_eq!(parse_session("").unwrap(), Session::default());
+		let session = Session { peer_version: Some(1), peer_features: Some(FEATURE_FRAME), next_counter: 0 };
+		assert_eq!(parse_session(&serde_json::to_string(&session).unwrap()).unwrap(), session);
+		assert!(parse_session("{").is_err());
+		assert!(parse_session(r#"{"peer_features":-1}"#).is_err());
+	}
+	
+	#[test]
+	fn counter_only_advances_when_sequenced() {
+		let mut session = Session::default();
+		assert_eq!(session.take_counter().unwrap(), None);
+		assert_eq!(session.next_counter, 0);
+		let mut session = Session::new(Some(Announcement { version: 1, features: FEATURE_SEQUENCE }));
+		assert_eq!(session.take_counter().unwrap(), Some(0));
+		assert_eq!(session.take_counter().unwrap(), Some(1));
+		session.next_counter = u64::MAX;
+		assert!(session.take_counter().is_err());
+	}
+}
@@ -0,0 +1,129 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use dawn_stdlib::hash;
+use hex::encode;
+use pqcrypto_dilithium::dilithium5::{detached_sign, verify_detached_signature, PublicKey, SecretKey, DetachedSignature};
+use pqcrypto_traits::sign::{PublicKey as _, SecretKey as _, DetachedSignature as _};
+
+// Detached signatures made with the signing keys generated by sign_keygen (own_seckey_sig / remote_pubkey_sig)
+
+pub fn sign_detached(data: &[u8], seckey_sig: &[u8]) -> Result<Vec<u8>, String> {
+	let seckey_sig = match SecretKey::from_bytes(seckey_sig) {
+		Ok(key) => key,
+		Err(_) => return Err("Signing key invalid".to_string())
+	};
+	Ok(detached_sign(data, &seckey_sig).as_bytes().to_vec())
+}
+
+pub fn verify_detached(data: &[u8], signature: &[u8], pubkey_sig: &[u8]) -> Result<(), String> {
+	let pubkey_sig = match PublicKey::from_bytes(pubkey_sig) {
+		Ok(key) => key,
+		Err(_) => return Err("Signature public key invalid".to_string())
+	};
+	let signature = match DetachedSignature::from_bytes(signature) {
+		Ok(signature) => signature,
+		Err(_) => return Err("Signature invalid".to_string())
+	};
+	match verify_detached_signature(&signature, data, &pubkey_sig) {
+		Ok(()) => Ok(()),
+		Err(_) => Err("Signature verification failed".to_string())
+	}
+}
+
+// Hex encoded hash of a public signing key, used wherever a signing key has to be identified or compared
+pub fn fingerprint(pubkey_sig: &[u8]) -> String {
+	encode(hash(pubkey_sig))
+}
+
+// Concatenates length prefixed fields so that signed data can not be reinterpreted by shifting bytes between fields
+pub fn signed_data(fields: &[&[u8]]) -> Vec<u8> {
+	let mut data = Vec::new();
+	for field in fields {
+		data.extend_from_slice(&(field.len() as u64).to_be_bytes());
+		data.extend_from_slice(field);
+	}
+	data
+}
+
+// Reads the given number of fields written by signed_data and returns them together with the remaining data
+pub fn read_signed_data(data: &[u8], count: usize) -> Result<(Vec<Vec<u8>>, &[u8]), String> {
+	let mut fields = Vec::with_capacity(count);
+	let mut rest = data;
+	for _ in 0..count {
+		if rest.len() < 8 { return Err("Field length missing".to_string()); }
+		let mut length = [0u8; 8];
+		length.copy_from_slice(&rest[..8]);
+		let length = u64::from_be_bytes(length);
+		if length > (rest.len() - 8) as u64 { return Err("Field length invalid".to_string()); }
+		let length = length as usize;
+		fields.push(rest[8..8 + length].to_vec());
+		rest = &rest[8 + length..];
+	}
+	Ok((fields, rest))
+}
+
+// Reverses signed_data, expecting exactly the given number of fields
+pub fn split_signed_data(data: &[u8], count: usize) -> Result<Vec<Vec<u8>>, String> {
+	let (fields, rest) = read_signed_data(data, count)?;
+	if !rest.is_empty() { return Err("Trailing data after fields".to_string()); }
+	Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dawn_stdlib::sign_keygen;
+	
+	#[test]
+	fn signed_data_roundtrip() {
+		let data = signed_data(&[b"a", b"", b"bc"]);
+		assert_eq!(split_signed_data(&data, 3).unwrap(), vec![b"a".to_vec(), vec![], b"bc".to_vec()]);
+		assert_ne!(data, signed_data(&[b"ab", b"", b"c"]));
+		assert!(split_signed_data(&data, 2).is_err());
+		assert!(split_signed_data(&data, 4).is_err());
+		assert!(split_signed_data(&data[..data.len() - 1], 3).is_err());
+	}
+	
+	#[test]
+	fn sign_roundtrip() {
+		let (pubkey_sig, seckey_sig) = sign_keygen();
+		let signature = sign_detached(b"data", &seckey_sig).unwrap();
+		assert!(verify_detached(b"data", &signature, &pubkey_sig).is_ok());
+		assert!(verify_detached(b"other", &signature, &pubkey_sig).is_err());
+	}
+	
+	// sign_detached assumes dawn-stdlib signs with dilithium5 and the same key encoding, a different scheme would break both checks
+	#[test]
+	fn matches_dawn_stdlib_signatures() {
+		use dawn_stdlib::{send_msg, parse_msg, kyber_keygen, sym_key_gen, id_gen, mdc_gen};
+		use pqcrypto_dilithium::dilithium5::{keypair, public_key_bytes, secret_key_bytes};
+		
+		let (pubkey_sig, seckey_sig) = sign_keygen();
+		assert_eq!(pubkey_sig.len(), public_key_bytes());
+		assert_eq!(seckey_sig.len(), secret_key_bytes());
+		
+		let (pubkey_sig, seckey_sig) = keypair();
+		let (pubkey_kyber, seckey_kyber) = kyber_keygen();
+		let (pfs_key, pfs_salt) = (sym_key_gen(), sym_key_gen());
+		let (_, _, ciphertext) = send_msg((0, Some("signed by dawn-stdlib"), None), &pubkey_kyber, Some(seckey_sig.as_bytes()), &pfs_key, &pfs_salt, &id_gen(), &mdc_gen()).unwrap();
+		assert!(parse_msg(&ciphertext, &seckey_kyber, Some(pubkey_sig.as_bytes()), &pfs_key, &pfs_salt).is_ok());
+		assert!(parse_msg(&ciphertext, &seckey_kyber, Some(&sign_keygen().0), &pfs_key, &pfs_salt).is_err());
+	}
+}
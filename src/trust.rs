@@ -0,0 +1,424 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use std::collections::BTreeMap;
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use hex::{encode, decode};
+use serde::{Serialize, Deserialize};
+use crate::{Error, TrustResult, TrustRotation};
+use crate::signatures::fingerprint;
+use crate::rotation::{KeyRotation, RotationHistory, validate_rotation_chain};
+use crate::security_number::security_number_qr_matches;
+use crate::error;
+
+// Returned as status when the signing key of a contact does not match the pinned one
+pub const STATUS_KEY_CHANGED: &str = "key_changed";
+// Returned as status when a scanned security number does not belong to the keys, the entry is left as it was
+pub const STATUS_SECURITY_NUMBER_MISMATCH: &str = "security_number_mismatch";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustState {
+	Unverified,
+	// verified by scanning the security number qr code, or carried over along a rotation chain
+	Verified,
+	// the contact presented a different signing key than the pinned one, see changed_fingerprint
+	Changed
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrustEntry {
+	pub fingerprint: String,
+	pub state: TrustState,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub changed_fingerprint: Option<String>
+}
+
+// Signing key fingerprints pinned on first use, keyed by contact id. The store is kept by the app and passed in on every call.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TrustStore {
+	#[serde(default)]
+	pub contacts: BTreeMap<String, TrustEntry>
+}
+
+impl TrustStore {
+	// Pins the key on first use. Returns false if the key does not match the pinned one, the entry is then marked as changed.
+	pub fn check(&mut self, contact_id: &str, pubkey_sig: &[u8]) -> bool {
+		let fingerprint = fingerprint(pubkey_sig);
+		match self.contacts.get_mut(contact_id) {
+			None => {
+				self.contacts.insert(contact_id.to_string(), TrustEntry { fingerprint, state: TrustState::Unverified, changed_fingerprint: None });
+				true
+			},
+			Some(entry) if entry.fingerprint == fingerprint => true,
+			Some(entry) => {
+				entry.state = TrustState::Changed;
+				entry.changed_fingerprint = Some(fingerprint);
+				false
+			}
+		}
+	}
+	
+	fn mark_verified(&mut self, contact_id: &str, fingerprint: &str) -> Result<(), String> {
+		let entry = match self.contacts.get_mut(contact_id) {
+			Some(entry) => entry,
+			None => return Err("Unknown contact".to_string())
+		};
+		if entry.state == TrustState::Changed { return Err("Signing key changed, the change has to be accepted first".to_string()); }
+		if entry.fingerprint != fingerprint { return Err("Fingerprint does not match the pinned signing key".to_string()); }
+		entry.state = TrustState::Verified;
+		Ok(())
+	}
+	
+	// Marks the contact as verified if the scanned qr payload is the security number of both signing keys.
+	// remote_pubkey_sig has to be the pinned key, so a number compared for another key can not verify the contact.
+	pub fn verify_security_number_qr(&mut self, contact_id: &str, own_pubkey_sig: &[u8], remote_pubkey_sig: &[u8], payload: &str) -> Result<bool, String> {
+		if !security_number_qr_matches(own_pubkey_sig, remote_pubkey_sig, payload)? { return Ok(false); }
+		self.mark_verified(contact_id, &fingerprint(remote_pubkey_sig))?;
+		Ok(true)
+	}
+	
+	// Pins the key at the end of a chain of rotations starting at the pinned key and returns it together with the rotation history.
+	// Every key signed its successor, so a verified contact stays verified. If the contact already presented the new key,
+	// the change is resolved, but it has to be verified again as the state before the change is not known.
+	pub fn apply_rotation_chain(&mut self, contact_id: &str, chain: &[KeyRotation]) -> Result<(Vec<u8>, RotationHistory), String> {
+		let entry = match self.contacts.get_mut(contact_id) {
+			Some(entry) => entry,
+			None => return Err("Unknown contact".to_string())
+		};
+		let pinned_pubkey_sig = match chain.first() {
+			Some(rotation) => decode(&rotation.old_pubkey_sig).map_err(|_| "Old signature key invalid".to_string())?,
+			None => return Err("Rotation chain empty".to_string())
+		};
+		if fingerprint(&pinned_pubkey_sig) != entry.fingerprint { return Err("Rotation chain does not start at the pinned signing key".to_string()); }
+		let (new_pubkey_sig, history) = validate_rotation_chain(&pinned_pubkey_sig, chain)?;
+		entry.fingerprint = fingerprint(&new_pubkey_sig);
+		if entry.changed_fingerprint.as_ref() == Some(&entry.fingerprint) {
+			entry.changed_fingerprint = None;
+			entry.state = TrustState::Unverified;
+		}
+		Ok((new_pubkey_sig, history))
+	}
+	
+	// Pins the changed key. It has to be verified again afterwards.
+	pub fn accept_change(&mut self, contact_id: &str) -> Result<(), String> {
+		let entry = match self.contacts.get_mut(contact_id) {
+			Some(entry) => entry,
+			None => return Err("Unknown contact".to_string())
+		};
+		let changed_fingerprint = match entry.changed_fingerprint.take() {
+			Some(fingerprint) => fingerprint,
+			None => return Err("Signing key did not change".to_string())
+		};
+		entry.fingerprint = changed_fingerprint;
+		entry.state = TrustState::Unverified;
+		Ok(())
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_trustCheck<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	store: JString<'local>,
+	contact_id: JString<'local>,
+	remote_pubkey_sig: JString<'local>
+) -> JString<'local> {
+	
+	let store = env.get_string(&store);
+	if store.is_err() { error!(env, "Could not get java variable: store"); }
+	let store: String = store.unwrap().into();
+	let mut store: TrustStore = match store.as_str() {
+		"" => TrustStore::default(),
+		_ => match serde_json::from_str(&store) {
+			Ok(res) => res,
+			Err(_) => { error!(env, "store invalid"); }
+		}
+	};
+	
+	let contact_id = env.get_string(&contact_id);
+	if contact_id.is_err() { error!(env, "Could not get java variable: contact_id"); }
+	let contact_id: String = contact_id.unwrap().into();
+	
+	let remote_pubkey_sig = env.get_string(&remote_pubkey_sig);
+	if remote_pubkey_sig.is_err() { error!(env, "Could not get java variable: remote_pubkey_sig"); }
+	let remote_pubkey_sig: String = remote_pubkey_sig.unwrap().into();
+	let remote_pubkey_sig = match decode(remote_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "remote_pubkey_sig invalid"); }
+	};
+	
+	let status = match store.check(&contact_id, &remote_pubkey_sig) {
+		true => "ok",
+		false => STATUS_KEY_CHANGED
+	};
+	
+	let trust_result = TrustResult {
+		status,
+		entry: &store.contacts[&contact_id],
+		store: &store
+	};
+	
+	let trust_result_json = match serde_json::to_string(&trust_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	trust_result_json
+}
+
+// Takes the payload scanned from the contact's security number qr code
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_trustVerifySecurityNumberQr<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	store: JString<'local>,
+	contact_id: JString<'local>,
+	own_pubkey_sig: JString<'local>,
+	remote_pubkey_sig: JString<'local>,
+	payload: JString<'local>
+) -> JString<'local> {
+	
+	let store = env.get_string(&store);
+	if store.is_err() { error!(env, "Could not get java variable: store"); }
+	let store: String = store.unwrap().into();
+	let mut store: TrustStore = match serde_json::from_str(&store) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "store invalid"); }
+	};
+	
+	let contact_id = env.get_string(&contact_id);
+	if contact_id.is_err() { error!(env, "Could not get java variable: contact_id"); }
+	let contact_id: String = contact_id.unwrap().into();
+	
+	let own_pubkey_sig = env.get_string(&own_pubkey_sig);
+	if own_pubkey_sig.is_err() { error!(env, "Could not get java variable: own_pubkey_sig"); }
+	let own_pubkey_sig: String = own_pubkey_sig.unwrap().into();
+	let own_pubkey_sig = match decode(own_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "own_pubkey_sig invalid"); }
+	};
+	
+	let remote_pubkey_sig = env.get_string(&remote_pubkey_sig);
+	if remote_pubkey_sig.is_err() { error!(env, "Could not get java variable: remote_pubkey_sig"); }
+	let remote_pubkey_sig: String = remote_pubkey_sig.unwrap().into();
+	let remote_pubkey_sig = match decode(remote_pubkey_sig) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "remote_pubkey_sig invalid"); }
+	};
+	
+	let payload = env.get_string(&payload);
+	if payload.is_err() { error!(env, "Could not get java variable: payload"); }
+	let payload: String = payload.unwrap().into();
+	
+	let status = match store.verify_security_number_qr(&contact_id, &own_pubkey_sig, &remote_pubkey_sig, &payload) {
+		Ok(true) => "ok",
+		Ok(false) => STATUS_SECURITY_NUMBER_MISMATCH,
+		Err(err) => { error!(env, &format!("Could not mark contact as verified: {}", err)); }
+	};
+	
+	let entry = match store.contacts.get(&contact_id) {
+		Some(entry) => entry,
+		None => { error!(env, "Unknown contact"); }
+	};
+	
+	let trust_result = TrustResult {
+		status,
+		entry,
+		store: &store
+	};
+	
+	let trust_result_json = match serde_json::to_string(&trust_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	trust_result_json
+}
+
+// Takes the rotations received from the contact, oldest first, as json array of KeyRotation
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_trustApplyRotationChain<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	store: JString<'local>,
+	contact_id: JString<'local>,
+	chain: JString<'local>
+) -> JString<'local> {
+	
+	let store = env.get_string(&store);
+	if store.is_err() { error!(env, "Could not get java variable: store"); }
+	let store: String = store.unwrap().into();
+	let mut store: TrustStore = match serde_json::from_str(&store) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "store invalid"); }
+	};
+	
+	let contact_id = env.get_string(&contact_id);
+	if contact_id.is_err() { error!(env, "Could not get java variable: contact_id"); }
+	let contact_id: String = contact_id.unwrap().into();
+	
+	let chain = env.get_string(&chain);
+	if chain.is_err() { error!(env, "Could not get java variable: chain"); }
+	let chain: String = chain.unwrap().into();
+	let chain: Vec<KeyRotation> = match serde_json::from_str(&chain) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "chain invalid"); }
+	};
+	
+	let (new_pubkey_sig, history) = match store.apply_rotation_chain(&contact_id, &chain) {
+		Ok(res) => res,
+		Err(err) => { error!(env, &format!("Could not apply rotation chain: {}", err)); }
+	};
+	
+	let trust_rotation = TrustRotation {
+		status: "ok",
+		entry: &store.contacts[&contact_id],
+		remote_pubkey_sig: &encode(new_pubkey_sig),
+		history: &history,
+		store: &store
+	};
+	
+	let trust_rotation_json = match serde_json::to_string(&trust_rotation) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	trust_rotation_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_trustAcceptChange<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	store: JString<'local>,
+	contact_id: JString<'local>
+) -> JString<'local> {
+	
+	let store = env.get_string(&store);
+	if store.is_err() { error!(env, "Could not get java variable: store"); }
+	let store: String = store.unwrap().into();
+	let mut store: TrustStore = match serde_json::from_str(&store) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "store invalid"); }
+	};
+	
+	let contact_id = env.get_string(&contact_id);
+	if contact_id.is_err() { error!(env, "Could not get java variable: contact_id"); }
+	let contact_id: String = contact_id.unwrap().into();
+	
+	if let Err(err) = store.accept_change(&contact_id) { error!(env, &format!("Could not accept signing key change: {}", err)); }
+	
+	let trust_result = TrustResult {
+		status: "ok",
+		entry: &store.contacts[&contact_id],
+		store: &store
+	};
+	
+	let trust_result_json = match serde_json::to_string(&trust_result) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	trust_result_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn pins_key_on_first_use() {
+		let mut store = TrustStore::default();
+		assert!(store.check("alice", b"key"));
+		assert!(store.check("alice", b"key"));
+		assert_eq!(store.contacts["alice"].state, TrustState::Unverified);
+		store.mark_verified("alice", &fingerprint(b"key")).unwrap();
+		assert_eq!(store.contacts["alice"].state, TrustState::Verified);
+		assert!(store.mark_verified("alice", &fingerprint(b"other")).is_err());
+		assert!(store.mark_verified("bob", &fingerprint(b"key")).is_err());
+	}
+	
+	#[test]
+	fn changed_key_has_to_be_accepted() {
+		let mut store = TrustStore::default();
+		store.check("alice", b"key");
+		assert!(store.accept_change("alice").is_err());
+		assert!(!store.check("alice", b"new key"));
+		assert_eq!(store.contacts["alice"].state, TrustState::Changed);
+		assert!(store.mark_verified("alice", &fingerprint(b"key")).is_err());
+		store.accept_change("alice").unwrap();
+		let entry = &store.contacts["alice"];
+		assert_eq!((entry.fingerprint.as_str(), entry.state, &entry.changed_fingerprint), (fingerprint(b"new key").as_str(), TrustState::Unverified, &None));
+		assert!(store.check("alice", b"new key"));
+	}
+	
+	#[test]
+	fn security_number_qr_verifies_pinned_key() {
+		use crate::security_number::{derive_ordered_security_number, render_security_number};
+		let payload = |key_a: &[u8], key_b: &[u8]| render_security_number(&derive_ordered_security_number(key_a, key_b).unwrap(), "en").unwrap().qr_payload;
+		let mut store = TrustStore::default();
+		store.check("alice", b"alice key");
+		assert!(!store.verify_security_number_qr("alice", b"own key", b"alice key", &payload(b"own key", b"other key")).unwrap());
+		assert_eq!(store.contacts["alice"].state, TrustState::Unverified);
+		assert!(store.verify_security_number_qr("alice", b"own key", b"other key", &payload(b"own key", b"other key")).is_err());
+		assert!(store.verify_security_number_qr("alice", b"own key", b"alice key", "not a payload").is_err());
+		let scanned = format!(" {} ", payload(b"alice key", b"own key").to_lowercase());
+		assert!(store.verify_security_number_qr("alice", b"own key", b"alice key", &scanned).unwrap());
+		assert_eq!(store.contacts["alice"].state, TrustState::Verified);
+	}
+	
+	#[test]
+	fn rotation_chain_repins_key() {
+		use crate::rotation::gen_key_rotation;
+		use dawn_stdlib::sign_keygen;
+		let (old, new) = (sign_keygen(), sign_keygen());
+		let chain = vec![gen_key_rotation(&old.0, &old.1, &new.0, &new.1).unwrap()];
+		let mut store = TrustStore::default();
+		assert!(store.apply_rotation_chain("alice", &chain).is_err());
+		store.check("alice", &new.0);
+		assert!(store.apply_rotation_chain("alice", &chain).is_err());
+		
+		let mut store = TrustStore::default();
+		store.check("alice", &old.0);
+		store.mark_verified("alice", &fingerprint(&old.0)).unwrap();
+		assert!(store.apply_rotation_chain("alice", &[]).is_err());
+		let (pubkey_sig, history) = store.apply_rotation_chain("alice", &chain).unwrap();
+		assert_eq!(pubkey_sig, new.0);
+		assert_eq!(history.retired, vec![fingerprint(&old.0)]);
+		assert_eq!((store.contacts["alice"].fingerprint.as_str(), store.contacts["alice"].state), (fingerprint(&new.0).as_str(), TrustState::Verified));
+		assert!(store.check("alice", &new.0));
+		
+		let mut store = TrustStore::default();
+		store.check("alice", &old.0);
+		assert!(!store.check("alice", &new.0));
+		store.apply_rotation_chain("alice", &chain).unwrap();
+		let entry = &store.contacts["alice"];
+		assert_eq!((entry.state, &entry.changed_fingerprint), (TrustState::Unverified, &None));
+	}
+}
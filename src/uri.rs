@@ -0,0 +1,262 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+use dawn_stdlib::*;
+use jni::JNIEnv;
+use jni::objects::{JClass, JString};
+use base64::{Engine as _, engine::general_purpose::STANDARD_NO_PAD as BASE64, engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL};
+use hex::encode;
+use crate::{Error, GenHandleUri, ParseHandleUri};
+use crate::handles::handle_info;
+use crate::signatures::signed_data;
+use crate::error;
+
+pub const URI_SCHEME: &str = "dawn://";
+const URI_ACTION_ADD: &str = "add?";
+const URI_VERSION: &str = "1";
+// Handles carry two kyber public keys and signed handles a dilithium key and signature on top, which is about 14 KB
+// in base64url. The limit leaves room for that and only guards against unbounded input.
+const MAX_URI_LENGTH: usize = 32768;
+const MAX_NAME_HINT_LENGTH: usize = 64;
+const CHECKSUM_SIZE: usize = 4;
+
+pub struct HandleUri {
+	pub handle: Vec<u8>,
+	pub name_hint: Option<String>
+}
+
+fn checksum(version: &str, handle: &[u8], name_hint: &str) -> String {
+	encode(&hash(&signed_data(&[b"dawn-uri", version.as_bytes(), handle, name_hint.as_bytes()]))[..CHECKSUM_SIZE])
+}
+
+// Percent encodes everything except the unreserved characters of RFC 3986
+fn percent_encode(text: &str) -> String {
+	let mut encoded = String::with_capacity(text.len());
+	for byte in text.bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+			_ => encoded.push_str(&format!("%{:02X}", byte))
+		}
+	}
+	encoded
+}
+
+fn percent_decode(text: &str) -> Result<String, String> {
+	let bytes = text.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		if bytes[i] == b'%' {
+			let byte = match text.get(i + 1..i + 3).map(|hex| u8::from_str_radix(hex, 16)) {
+				Some(Ok(byte)) => byte,
+				_ => return Err("URI parameter invalid: percent encoding".to_string())
+			};
+			decoded.push(byte);
+			i += 3;
+		}
+		else {
+			decoded.push(bytes[i]);
+			i += 1;
+		}
+	}
+	match String::from_utf8(decoded) {
+		Ok(decoded) => Ok(decoded),
+		Err(_) => Err("URI parameter invalid: not utf-8".to_string())
+	}
+}
+
+pub fn gen_handle_uri(handle: &[u8], name_hint: &str) -> Result<String, String> {
+	if name_hint.chars().count() > MAX_NAME_HINT_LENGTH { return Err("Name hint too long".to_string()); }
+	let mut uri = format!("{}{}v={}&h={}", URI_SCHEME, URI_ACTION_ADD, URI_VERSION, BASE64_URL.encode(handle));
+	if !name_hint.is_empty() { uri.push_str(&format!("&n={}", percent_encode(name_hint))); }
+	uri.push_str(&format!("&c={}", checksum(URI_VERSION, handle, name_hint)));
+	if uri.len() > MAX_URI_LENGTH { return Err("URI too long".to_string()); }
+	Ok(uri)
+}
+
+pub fn parse_handle_uri(uri: &str) -> Result<HandleUri, String> {
+	if uri.len() > MAX_URI_LENGTH { return Err("URI too long".to_string()); }
+	let query = match uri.strip_prefix(URI_SCHEME) {
+		Some(rest) => match rest.strip_prefix(URI_ACTION_ADD) {
+			Some(query) => query,
+			None => return Err("URI action invalid".to_string())
+		},
+		None => return Err("URI scheme invalid".to_string())
+	};
+	let (mut version, mut handle, mut name_hint, mut checksum_value) = (None, None, None, None);
+	for parameter in query.split('&') {
+		let (key, value) = match parameter.split_once('=') {
+			Some(res) => res,
+			None => return Err(format!("URI parameter malformed: {}", parameter))
+		};
+		let slot = match key {
+			"v" => &mut version,
+			"h" => &mut handle,
+			"n" => &mut name_hint,
+			"c" => &mut checksum_value,
+			// unknown parameters are ignored, so later versions can add optional ones
+			_ => continue
+		};
+		if slot.is_some() { return Err(format!("URI parameter duplicated: {}", key)); }
+		*slot = Some(value);
+	}
+	match version {
+		Some(URI_VERSION) => (),
+		Some(_) => return Err("URI version unsupported".to_string()),
+		None => return Err("URI parameter missing: v".to_string())
+	}
+	let handle = match handle {
+		Some(handle) => match BASE64_URL.decode(handle) {
+			Ok(handle) => handle,
+			Err(_) => return Err("URI parameter invalid: h".to_string())
+		},
+		None => return Err("URI parameter missing: h".to_string())
+	};
+	let name_hint = match name_hint {
+		Some(name_hint) => percent_decode(name_hint)?,
+		None => String::new()
+	};
+	if name_hint.chars().count() > MAX_NAME_HINT_LENGTH { return Err("URI parameter invalid: n".to_string()); }
+	let checksum_value = match checksum_value {
+		Some(checksum_value) => checksum_value,
+		None => return Err("URI parameter missing: c".to_string())
+	};
+	if !checksum_value.eq_ignore_ascii_case(&checksum(URI_VERSION, &handle, &name_hint)) { return Err("URI checksum mismatch".to_string()); }
+	Ok(HandleUri {
+		handle,
+		name_hint: match name_hint.is_empty() {
+			true => None,
+			false => Some(name_hint)
+		}
+	})
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_genHandleUri<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	handle: JString<'local>,
+	name_hint: JString<'local>
+) -> JString<'local> {
+	
+	let handle = env.get_string(&handle);
+	if handle.is_err() { error!(env, "Could not get java variable: handle"); }
+	let handle: String = handle.unwrap().into();
+	let handle = match BASE64.decode(handle) {
+		Ok(res) => res,
+		Err(_) => { error!(env, "handle invalid"); }
+	};
+	
+	let name_hint = env.get_string(&name_hint);
+	if name_hint.is_err() { error!(env, "Could not get java variable: name_hint"); }
+	let name_hint: String = name_hint.unwrap().into();
+	
+	let uri = match gen_handle_uri(&handle, &name_hint) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let gen_handle_uri = GenHandleUri {
+		status: "ok",
+		uri: &uri
+	};
+	
+	let gen_handle_uri_json = match serde_json::to_string(&gen_handle_uri) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	gen_handle_uri_json
+}
+
+#[no_mangle]
+pub extern "C" fn Java_dawn_android_LibraryConnector_parseHandleUri<'local> (
+	mut env: JNIEnv<'local>,
+	_class: JClass<'local>,
+	uri: JString<'local>
+) -> JString<'local> {
+	
+	let uri = env.get_string(&uri);
+	if uri.is_err() { error!(env, "Could not get java variable: uri"); }
+	let uri: String = uri.unwrap().into();
+	
+	let handle_uri = match parse_handle_uri(uri.trim()) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let info = match handle_info(handle_uri.handle.clone()) {
+		Ok(res) => res,
+		Err(error) => { error!(env, &error); }
+	};
+	
+	let parse_handle_uri = ParseHandleUri {
+		status: "ok",
+		handle: &BASE64.encode(&handle_uri.handle),
+		name_hint: handle_uri.name_hint.as_deref(),
+		info: &info
+	};
+	
+	let parse_handle_uri_json = match serde_json::to_string(&parse_handle_uri) {
+		Ok(res) => match env.new_string(res) {
+			Ok(jstring) => jstring,
+			Err(_) => { error!(env, "Could not create new java string"); }
+		}
+		Err(_) => { error!(env, "Could not serialize json"); }
+	};
+	parse_handle_uri_json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	#[test]
+	fn uri_roundtrip() {
+		let uri = gen_handle_uri(b"handle", "Ann & Bob").unwrap();
+		assert!(uri.contains("&n=Ann%20%26%20Bob&"));
+		let parsed = parse_handle_uri(&uri).unwrap();
+		assert_eq!(parsed.handle, b"handle");
+		assert_eq!(parsed.name_hint.as_deref(), Some("Ann & Bob"));
+		let parsed = parse_handle_uri(&gen_handle_uri(b"handle", "").unwrap()).unwrap();
+		assert_eq!(parsed.name_hint, None);
+	}
+	
+	#[test]
+	fn rejects_checksum_mismatch() {
+		let uri = gen_handle_uri(b"handle", "name").unwrap();
+		let tampered = uri.replace("&n=name", "&n=nams");
+		assert!(parse_handle_uri(&tampered).is_err());
+		let (rest, checksum_value) = uri.rsplit_once("&c=").unwrap();
+		assert!(parse_handle_uri(&format!("{}&c={}", rest, checksum_value.to_uppercase())).is_ok());
+		assert!(parse_handle_uri(rest).is_err());
+	}
+	
+	#[test]
+	fn rejects_malformed_uris() {
+		assert!(parse_handle_uri("https://add?v=1").is_err());
+		assert!(parse_handle_uri("dawn://add?v=2&h=aGFuZGxl&c=00000000").is_err());
+		let uri = gen_handle_uri(b"handle", "").unwrap();
+		assert!(parse_handle_uri(&format!("{}&v=1", uri)).is_err());
+		assert!(percent_decode("%zz").is_err());
+		assert_eq!(percent_decode(&percent_encode("ä ~")).unwrap(), "ä ~");
+	}
+}
@@ -0,0 +1,86 @@
+/*	Copyright (c) 2023 Laurenz Werner
+	
+	This file is part of Dawn.
+	
+	Dawn is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+	
+	Dawn is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+	
+	You should have received a copy of the GNU General Public License
+	along with Dawn.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+
+// Fixed dictionaries for rendering security numbers. They must never be reordered or changed, otherwise
+// two devices running different versions would show different words for the same security number.
+
+pub const WORDS_EN: [&str; 256] = [
+	"acid", "acorn", "actor", "adult", "agent", "album", "alley", "amber", "anchor", "angle",
+	"ankle", "apple", "apron", "arrow", "artist", "atlas", "attic", "autumn", "avocado", "axis",
+	"bacon", "badge", "bagel", "baker", "bamboo", "banana", "band", "banjo", "barn", "barrel",
+	"basket", "beach", "beard", "beaver", "bed", "bee", "beetle", "bell", "belt", "bench",
+	"berry", "bicycle", "bird", "biscuit", "blanket", "blossom", "boat", "bone", "book", "boot",
+	"bottle", "box", "bracelet", "branch", "bread", "brick", "bridge", "broom", "bubble", "bucket",
+	"buffalo", "bull", "butter", "button", "cabin", "cactus", "cake", "camel", "camera", "candle",
+	"canoe", "canyon", "captain", "carpet", "carrot", "castle", "cat", "cave", "cello", "chain",
+	"chair", "chalk", "cheese", "cherry", "chess", "chicken", "chimney", "circle", "city", "clock",
+	"cloud", "clover", "coast", "cobra", "coconut", "coffee", "comet", "compass", "cookie", "copper",
+	"coral", "cotton", "cow", "crab", "crane", "crayon", "cricket", "crown", "cup", "curtain",
+	"cushion", "daisy", "desert", "diamond", "dinner", "doctor", "dolphin", "donkey", "door", "dragon",
+	"drum", "duck", "eagle", "earth", "easel", "echo", "egg", "elbow", "elephant", "elk",
+	"engine", "falcon", "farm", "feather", "fence", "fern", "ferry", "fiddle", "field", "finger",
+	"fire", "fish", "flag", "flute", "forest", "fork", "fossil", "fountain", "fox", "frog",
+	"garden", "garlic", "giraffe", "glacier", "glove", "goat", "gold", "gorilla", "grape", "guitar",
+	"hammer", "harbor", "harp", "hat", "hawk", "helmet", "hill", "honey", "horse", "hotel",
+	"island", "ivory", "jacket", "jaguar", "jelly", "jewel", "jungle", "kangaroo", "kettle", "key",
+	"kite", "kitten", "koala", "ladder", "lake", "lamp", "lantern", "lemon", "leopard", "letter",
+	"lily", "lion", "lizard", "lobster", "lock", "mango", "maple", "marble", "meadow", "melon",
+	"mirror", "monkey", "moon", "moose", "mountain", "mouse", "mushroom", "needle", "nest", "noodle",
+	"oak", "ocean", "olive", "onion", "orange", "orchid", "otter", "owl", "paddle", "palace",
+	"panda", "paper", "parrot", "peach", "peanut", "pear", "pencil", "penguin", "pepper", "piano",
+	"pigeon", "pillow", "pine", "pirate", "planet", "plum", "pocket", "pony", "potato", "pumpkin",
+	"puzzle", "quilt", "rabbit", "radio", "rain", "raven", "river", "robot", "rocket", "rose",
+	"saddle", "sailor", "salmon", "sand", "saturn", "scarf"
+];
+
+pub const WORDS_DE: [&str; 256] = [
+	"abend", "acker", "adler", "ahorn", "akte", "alpen", "ameise", "amsel", "angel", "anker",
+	"apfel", "arzt", "ast", "atlas", "auge", "auto", "axt", "bach", "backe", "bad",
+	"bagger", "ball", "banane", "bank", "bär", "bart", "bauer", "baum", "becher", "beere",
+	"berg", "besen", "bett", "biber", "biene", "bild", "birne", "blatt", "blitz", "blume",
+	"boden", "bohne", "boot", "brett", "brief", "brille", "brot", "brücke", "brunnen", "buch",
+	"burg", "butter", "dach", "dackel", "dampf", "daumen", "decke", "delfin", "deich", "dose",
+	"drache", "draht", "dübel", "eber", "echo", "efeu", "ei", "eiche", "eimer", "eis",
+	"elch", "engel", "ente", "erbse", "erde", "esel", "eule", "fabrik", "fackel", "fahne",
+	"falke", "farn", "faser", "feder", "feile", "fels", "fenster", "ferkel", "fest", "feuer",
+	"fichte", "finger", "fisch", "flasche", "fliege", "flöte", "floß", "flügel", "fluss", "fohlen",
+	"forelle", "frosch", "fuchs", "funke", "gabel", "gans", "garten", "geige", "geld", "gras",
+	"greif", "grille", "gurke", "hafen", "hahn", "hai", "hammer", "hand", "harfe", "hase",
+	"haus", "hecht", "hecke", "heft", "held", "hemd", "herd", "hering", "herz", "heu",
+	"himmel", "hirsch", "hobel", "honig", "horn", "hose", "huhn", "hummel", "hund", "hut",
+	"igel", "insel", "jacke", "jäger", "kabel", "kaffee", "kakao", "kamel", "kamm", "kanne",
+	"kappe", "karte", "käse", "katze", "kegel", "kerze", "kessel", "kette", "kiefer", "kirsche",
+	"kiste", "klee", "knopf", "koch", "koffer", "komet", "korb", "kran", "kranich", "krebs",
+	"kreide", "krone", "kuchen", "kugel", "kuh", "kürbis", "lachs", "lampe", "land", "laterne",
+	"laub", "leiter", "lerche", "licht", "lilie", "linde", "löffel", "löwe", "luchs", "luft",
+	"mantel", "marder", "markt", "maus", "meer", "mehl", "meise", "melone", "messer", "milch",
+	"mond", "möwe", "mühle", "mütze", "nadel", "nagel", "nebel", "nest", "nuss", "ofen",
+	"ohr", "orgel", "otter", "palme", "panda", "papier", "pfau", "pfeffer", "pferd", "pflug",
+	"pilz", "pinsel", "pirat", "pudel", "puppe", "quelle", "rabe", "rad", "rakete", "rasen",
+	"raupe", "regen", "reh", "reiter", "rind", "ring", "robbe", "rose", "rübe", "sack",
+	"salz", "sand", "sattel", "schaf", "schal", "schiff", "schloss", "schnee", "schuh", "see",
+	"segel", "seil", "sense", "sichel", "sonne", "spaten"
+];
+
+pub const EMOJI: [&str; 64] = [
+	"🐶", "🐱", "🐭", "🐰", "🦊", "🐻", "🐼", "🐨", "🐯", "🦁", "🐮", "🐷", "🐸", "🐵", "🐔", "🐧",
+	"🐦", "🦆", "🦉", "🐴", "🦄", "🐝", "🐛", "🦋", "🐌", "🐞", "🐢", "🐍", "🐙", "🦀", "🐬", "🐳",
+	"🌵", "🌲", "🌻", "🌹", "🍁", "🍄", "🌙", "⭐", "☀️", "🌈", "❄️", "🔥", "💧", "🍎", "🍋", "🍌",
+	"🍉", "🍇", "🍓", "🍒", "🥕", "🌽", "🎈", "🎁", "🔔", "🔑", "⚓", "🚲", "🚀", "⛵", "🎸", "🏠"
+];